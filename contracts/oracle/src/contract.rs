@@ -2,16 +2,50 @@ use cosmwasm_schema::{cw_serde, QueryResponses};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Binary, CustomQuery, Decimal, Deps, DepsMut, Env,
+    to_json_binary, Binary, CustomQuery, Decimal, Deps, DepsMut, Env, Event,
     MessageInfo, Response, StdResult,
 };
 use cw_storage_plus::Map;
 use nibiru_ownable::{ownable_execute, ownable_query, OwnershipError};
+use std::collections::HashSet;
+
+use crate::{
+    error::ContractError,
+    feeders::{
+        add_feeder, query_feeders, query_missed_rounds, query_submissions,
+        remove_feeder, submit_price, update_feeder_config, FeedKind,
+        FeederConfig, MissedRoundsResponse, Submission, COLLATERAL_FEED,
+        FEEDER_CONFIG, PRICE_FEED,
+    },
+    history::{
+        query_last_prices, query_price_range, query_twap, save_price, PriceRange,
+    },
+    native::{
+        assert_pushed_mode, load_price, query_exchange_rate_keys,
+        set_exchange_rate_keys, set_oracle_mode, OracleMode, ORACLE_MODE,
+    },
+};
 
 #[cw_serde]
 pub struct Price {
     pub last_update_block: u64,
     pub price: Decimal,
+    /// Price replaced by the last update, unset before the second update.
+    pub previous_price: Option<Decimal>,
+}
+
+impl Price {
+    pub(crate) fn updated(
+        previous: Option<Price>,
+        price: Decimal,
+        block: u64,
+    ) -> Self {
+        Price {
+            last_update_block: block,
+            price,
+            previous_price: previous.map(|previous| previous.price),
+        }
+    }
 }
 
 pub const PRICES: Map<u64, Price> = Map::new("prices");
@@ -39,8 +73,49 @@ pub fn instantiate(
 #[ownable_execute]
 #[cw_serde]
 pub enum OraclesExecuteMsg {
-    SetPrice { index: u64, price: Decimal },
-    SetCollateralPrice { index: u64, price: Decimal },
+    SetPrice {
+        index: u64,
+        price: Decimal,
+    },
+    SetCollateralPrice {
+        index: u64,
+        price: Decimal,
+    },
+    // Sets several prices at once, each index at most once per list
+    SetPrices {
+        prices: Vec<(u64, Decimal)>,
+        collateral_prices: Vec<(u64, Decimal)>,
+    },
+
+    // Feeders submit prices in rounds, the median is published once the
+    // round window is over or every feeder submitted. Direct owner updates
+    // are disabled once configured.
+    SubmitPrice {
+        index: u64,
+        price: Decimal,
+    },
+    SubmitCollateralPrice {
+        index: u64,
+        price: Decimal,
+    },
+    UpdateFeederConfig {
+        feeder_config: FeederConfig,
+    },
+    // Native mode reads the prices from the `x/oracle` exchange rates of the
+    // pairs mapped to the indices
+    SetOracleMode {
+        mode: OracleMode,
+    },
+    SetExchangeRateKeys {
+        keys: Vec<(u64, String)>,
+        collateral_keys: Vec<(u64, String)>,
+    },
+    AddFeeder {
+        address: String,
+    },
+    RemoveFeeder {
+        address: String,
+    },
 }
 
 #[ownable_query]
@@ -53,61 +128,300 @@ pub enum OracleQueryMsg {
 
     #[returns(Decimal)]
     GetCollateralPrice { index: u64 },
+
+    // Retrieve the price of the given pair with its last update block and
+    // the price it replaced
+    #[returns(Price)]
+    GetPriceData { index: u64 },
+
+    #[returns(Price)]
+    GetCollateralPriceData { index: u64 },
+
+    // Time-weighted average price over the last `window_blocks` blocks
+    #[returns(Decimal)]
+    Twap {
+        kind: FeedKind,
+        index: u64,
+        window_blocks: u64,
+    },
+
+    // Last `count` published prices, newest first
+    #[returns(Vec<Price>)]
+    LastPrices {
+        kind: FeedKind,
+        index: u64,
+        count: u32,
+    },
+
+    // Highest and lowest prices published since the given block
+    #[returns(PriceRange)]
+    PriceRange {
+        kind: FeedKind,
+        index: u64,
+        since_block: u64,
+    },
+
+    #[returns(OracleMode)]
+    OracleMode {},
+
+    // `x/oracle` pairs of the indices, ordered by index
+    #[returns(Vec<(u64, String)>)]
+    ExchangeRateKeys {
+        kind: FeedKind,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    #[returns(Option<FeederConfig>)]
+    FeederConfig {},
+
+    // Feeders with the block they were added at
+    #[returns(Vec<(cosmwasm_std::Addr, u64)>)]
+    Feeders {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    // Prices submitted by the feeder, ordered by index and round
+    #[returns(Vec<Submission>)]
+    FeederSubmissions {
+        feeder: String,
+        kind: FeedKind,
+        start_after: Option<(u64, u64)>,
+        limit: Option<u32>,
+    },
+
+    // Rounds of the index the feeder did not submit a price to, out of the
+    // `limit` rounds after `start_after`
+    #[returns(MissedRoundsResponse)]
+    MissedRounds {
+        feeder: String,
+        kind: FeedKind,
+        index: u64,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 impl CustomQuery for OracleQueryMsg {}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     msg: OraclesExecuteMsg,
-) -> Result<Response, OwnershipError> {
+) -> Result<Response, ContractError> {
     match msg {
         OraclesExecuteMsg::SetPrice { index, price } => {
-            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
-            PRICES.save(
+            assert_owner_sets_prices(&deps, &info)?;
+            save_price(
                 deps.storage,
+                &PRICE_FEED,
                 index,
-                &Price {
-                    price,
-                    last_update_block: env.block.height,
-                },
+                price,
+                env.block.height,
             )?;
 
             Ok(Response::new().add_attribute("method", "SetPrice"))
         }
         OraclesExecuteMsg::SetCollateralPrice { index, price } => {
-            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
-            COLLATERAL_PRICES.save(
+            assert_owner_sets_prices(&deps, &info)?;
+            save_price(
                 deps.storage,
+                &COLLATERAL_FEED,
                 index,
-                &Price {
-                    price,
-                    last_update_block: env.block.height,
-                },
+                price,
+                env.block.height,
             )?;
 
             Ok(Response::new().add_attribute("method", "SetCollateralPrice"))
         }
+        OraclesExecuteMsg::SetPrices {
+            prices,
+            collateral_prices,
+        } => {
+            assert_owner_sets_prices(&deps, &info)?;
+            for list in [&prices, &collateral_prices] {
+                let mut indices = HashSet::new();
+                if let Some((index, _)) =
+                    list.iter().find(|(index, _)| !indices.insert(*index))
+                {
+                    return Err(ContractError::DuplicateIndex(*index));
+                }
+            }
+
+            let mut response =
+                Response::new().add_attribute("method", "SetPrices");
+            for (feed, list, event) in [
+                (PRICE_FEED, prices, "set_price"),
+                (COLLATERAL_FEED, collateral_prices, "set_collateral_price"),
+            ] {
+                for (index, price) in list {
+                    save_price(
+                        deps.storage,
+                        &feed,
+                        index,
+                        price,
+                        env.block.height,
+                    )?;
+                    response = response.add_event(
+                        Event::new(event)
+                            .add_attribute("index", index.to_string())
+                            .add_attribute("price", price.to_string()),
+                    );
+                }
+            }
+            Ok(response)
+        }
+        OraclesExecuteMsg::SubmitPrice { index, price } => submit_price(
+            deps,
+            &env.block,
+            info.sender,
+            FeedKind::Price,
+            index,
+            price,
+        ),
+        OraclesExecuteMsg::SubmitCollateralPrice { index, price } => {
+            submit_price(
+                deps,
+                &env.block,
+                info.sender,
+                FeedKind::Collateral,
+                index,
+                price,
+            )
+        }
+        OraclesExecuteMsg::UpdateFeederConfig { feeder_config } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            update_feeder_config(deps, feeder_config)
+        }
+        OraclesExecuteMsg::SetOracleMode { mode } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            set_oracle_mode(deps, mode)
+        }
+        OraclesExecuteMsg::SetExchangeRateKeys {
+            keys,
+            collateral_keys,
+        } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            set_exchange_rate_keys(deps.branch(), &PRICE_FEED, keys)?;
+            set_exchange_rate_keys(deps, &COLLATERAL_FEED, collateral_keys)?;
+            Ok(Response::new().add_attribute("method", "SetExchangeRateKeys"))
+        }
+        OraclesExecuteMsg::AddFeeder { address } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            add_feeder(deps, &env.block, address)
+        }
+        OraclesExecuteMsg::RemoveFeeder { address } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            remove_feeder(deps, address)
+        }
         OraclesExecuteMsg::UpdateOwnership(action) => {
-            execute_update_ownership(deps, env, info, action)
+            Ok(execute_update_ownership(deps, env, info, action)?)
         }
     }
 }
 
+/// Only the owner sets prices directly, and only until feeders are set up.
+fn assert_owner_sets_prices(
+    deps: &DepsMut,
+    info: &MessageInfo,
+) -> Result<(), ContractError> {
+    nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+    assert_pushed_mode(deps.storage)?;
+    if FEEDER_CONFIG.exists(deps.storage) {
+        return Err(ContractError::FeedersEnabled);
+    }
+    Ok(())
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: OracleQueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: OracleQueryMsg) -> StdResult<Binary> {
     match msg {
         OracleQueryMsg::GetPrice { index } => {
-            let price = PRICES.load(deps.storage, index)?.price;
-            to_json_binary(&price)
+            let price = load_price(deps, &env, &PRICE_FEED, index)?;
+            to_json_binary(&price.price)
         }
         OracleQueryMsg::GetCollateralPrice { index } => {
-            let price = COLLATERAL_PRICES.load(deps.storage, index)?;
+            let price = load_price(deps, &env, &COLLATERAL_FEED, index)?;
             to_json_binary(&price.price)
         }
+        OracleQueryMsg::GetPriceData { index } => {
+            to_json_binary(&load_price(deps, &env, &PRICE_FEED, index)?)
+        }
+        OracleQueryMsg::GetCollateralPriceData { index } => {
+            to_json_binary(&load_price(deps, &env, &COLLATERAL_FEED, index)?)
+        }
+        OracleQueryMsg::OracleMode {} => to_json_binary(
+            &ORACLE_MODE.may_load(deps.storage)?.unwrap_or_default(),
+        ),
+        OracleQueryMsg::ExchangeRateKeys {
+            kind,
+            start_after,
+            limit,
+        } => to_json_binary(&query_exchange_rate_keys(
+            deps,
+            &kind.feed(),
+            start_after,
+            limit,
+        )?),
+        OracleQueryMsg::Twap {
+            kind,
+            index,
+            window_blocks,
+        } => to_json_binary(&query_twap(
+            deps,
+            env.block.height,
+            &kind.feed(),
+            index,
+            window_blocks,
+        )?),
+        OracleQueryMsg::LastPrices { kind, index, count } => {
+            to_json_binary(&query_last_prices(deps, &kind.feed(), index, count)?)
+        }
+        OracleQueryMsg::PriceRange {
+            kind,
+            index,
+            since_block,
+        } => to_json_binary(&query_price_range(
+            deps,
+            &kind.feed(),
+            index,
+            since_block,
+        )?),
+        OracleQueryMsg::FeederConfig {} => {
+            to_json_binary(&FEEDER_CONFIG.may_load(deps.storage)?)
+        }
+        OracleQueryMsg::Feeders { start_after, limit } => {
+            to_json_binary(&query_feeders(deps, start_after, limit)?)
+        }
+        OracleQueryMsg::FeederSubmissions {
+            feeder,
+            kind,
+            start_after,
+            limit,
+        } => to_json_binary(&query_submissions(
+            deps,
+            feeder,
+            kind,
+            start_after,
+            limit,
+        )?),
+        OracleQueryMsg::MissedRounds {
+            feeder,
+            kind,
+            index,
+            start_after,
+            limit,
+        } => to_json_binary(&query_missed_rounds(
+            deps,
+            feeder,
+            kind,
+            index,
+            start_after,
+            limit,
+        )?),
         OracleQueryMsg::Ownership {} => Ok(to_json_binary(
             &nibiru_ownable::get_ownership(deps.storage)?,
         )?),
@@ -198,6 +512,83 @@ mod tests {
         assert_eq!(price, Decimal::percent(200));
     }
 
+    #[test]
+    fn price_data_keeps_previous_price() {
+        let mut deps = mock_dependencies();
+        let msg = OracleInstantiateMsg {
+            owner: Some("owner".to_string()),
+        };
+        let info = message_info(&Addr::unchecked("creator"), &[]);
+        let _ = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let mut env = mock_env();
+        let info = message_info(&Addr::unchecked("owner"), &[]);
+        for price in [Decimal::percent(100), Decimal::percent(110)] {
+            let msg = OraclesExecuteMsg::SetPrice { index: 1, price };
+            execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+            env.block.height += 5;
+        }
+
+        let msg = OracleQueryMsg::GetPriceData { index: 1 };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let price: Price = from_json(res).unwrap();
+        assert_eq!(
+            price,
+            Price {
+                last_update_block: mock_env().block.height + 5,
+                price: Decimal::percent(110),
+                previous_price: Some(Decimal::percent(100)),
+            }
+        );
+    }
+
+    #[test]
+    fn set_prices() {
+        let mut deps = mock_dependencies();
+        let msg = OracleInstantiateMsg {
+            owner: Some("owner".to_string()),
+        };
+        let info = message_info(&Addr::unchecked("creator"), &[]);
+        let _ = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = message_info(&Addr::unchecked("owner"), &[]);
+        let msg = OraclesExecuteMsg::SetPrices {
+            prices: vec![(1, Decimal::percent(100)), (1, Decimal::percent(110))],
+            collateral_prices: vec![],
+        };
+        let err =
+            execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicateIndex(1));
+
+        // the same index can be in both lists
+        let msg = OraclesExecuteMsg::SetPrices {
+            prices: vec![(1, Decimal::percent(100)), (2, Decimal::percent(300))],
+            collateral_prices: vec![(1, Decimal::percent(200))],
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.events,
+            vec![
+                Event::new("set_price")
+                    .add_attribute("index", "1")
+                    .add_attribute("price", "1"),
+                Event::new("set_price")
+                    .add_attribute("index", "2")
+                    .add_attribute("price", "3"),
+                Event::new("set_collateral_price")
+                    .add_attribute("index", "1")
+                    .add_attribute("price", "2"),
+            ]
+        );
+
+        let msg = OracleQueryMsg::GetPrice { index: 2 };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        assert_eq!(from_json::<Decimal>(res).unwrap(), Decimal::percent(300));
+        let msg = OracleQueryMsg::GetCollateralPrice { index: 1 };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        assert_eq!(from_json::<Decimal>(res).unwrap(), Decimal::percent(200));
+    }
+
     #[test]
     fn unauthorized_set_price() {
         let mut deps = mock_dependencies();
@@ -214,7 +605,7 @@ mod tests {
         };
         let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
         match err {
-            OwnershipError::NotOwner {} => {}
+            ContractError::Ownership(OwnershipError::NotOwner) => {}
             _ => panic!("Unexpected error: {:?}", err),
         }
     }
@@ -235,7 +626,7 @@ mod tests {
         };
         let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
         match err {
-            OwnershipError::NotOwner {} => {}
+            ContractError::Ownership(OwnershipError::NotOwner) => {}
             _ => panic!("Unexpected error: {:?}", err),
         }
     }
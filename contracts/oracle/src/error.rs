@@ -0,0 +1,36 @@
+use cosmwasm_std::StdError;
+use nibiru_ownable::OwnershipError;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("{0}")]
+    Ownership(#[from] OwnershipError),
+
+    #[error("sender is not a price feeder")]
+    NotFeeder,
+
+    #[error("the feeder already submitted a price for this round")]
+    AlreadySubmitted,
+
+    #[error("prices are set by the feeders")]
+    FeedersEnabled,
+
+    #[error("price feeders are not configured")]
+    FeedersNotConfigured,
+
+    #[error("the quorum and round length must be positive")]
+    InvalidFeederConfig,
+
+    #[error("the quorum must be a majority of the {0} feeders")]
+    InvalidQuorum(u32),
+
+    #[error("index {0} is set more than once")]
+    DuplicateIndex(u64),
+
+    #[error("prices are read from the chain oracle module")]
+    NativeMode,
+}
@@ -0,0 +1,700 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{
+    Addr, BlockInfo, Decimal, Deps, DepsMut, Order, Response, StdResult, Storage,
+};
+use cw_storage_plus::{Bound, Item, Map};
+
+use crate::{
+    contract::{Price, COLLATERAL_PRICES, PRICES},
+    error::ContractError,
+    history::save_price,
+    native::assert_pushed_mode,
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+#[cw_serde]
+pub struct FeederConfig {
+    /// Submissions a round needs by the end of its window for its median to
+    /// be published.
+    pub quorum: u32,
+    /// Blocks a round stays open for after its first submission.
+    pub round_blocks: u64,
+}
+
+#[cw_serde]
+pub struct Round {
+    pub start_block: u64,
+    pub prices: Vec<Decimal>,
+    pub published: bool,
+}
+
+#[cw_serde]
+pub struct Submission {
+    pub index: u64,
+    pub round: u64,
+    pub price: Decimal,
+}
+
+#[cw_serde]
+pub struct MissedRoundsResponse {
+    pub missed_rounds: Vec<u64>,
+    /// Last round scanned, to continue from, unset once no round is left.
+    pub last_round: Option<u64>,
+}
+
+#[cw_serde]
+pub enum FeedKind {
+    Price,
+    Collateral,
+}
+
+/// Storage of one kind of prices: the published prices, their history by
+/// (index, slot) with the number of prices ever published per index, the
+/// rounds by (index, round), the submissions by (feeder, index, round) and
+/// the `x/oracle` pair of each index.
+pub struct Feed {
+    pub prices: Map<u64, Price>,
+    pub history: Map<(u64, u64), Price>,
+    pub history_counts: Map<u64, u64>,
+    pub rounds: Map<(u64, u64), Round>,
+    pub submissions: Map<(Addr, u64, u64), Decimal>,
+    pub exchange_rate_keys: Map<u64, String>,
+}
+
+pub const FEEDER_CONFIG: Item<FeederConfig> = Item::new("feeder_config");
+/// Feeders and the block they were added at.
+pub const FEEDERS: Map<Addr, u64> = Map::new("feeders");
+pub const FEEDER_COUNT: Item<u32> = Item::new("feeder_count");
+
+pub const PRICE_FEED: Feed = Feed {
+    prices: PRICES,
+    history: Map::new("price_history"),
+    history_counts: Map::new("price_history_counts"),
+    rounds: Map::new("price_rounds"),
+    submissions: Map::new("price_submissions"),
+    exchange_rate_keys: Map::new("exchange_rate_keys"),
+};
+pub const COLLATERAL_FEED: Feed = Feed {
+    prices: COLLATERAL_PRICES,
+    history: Map::new("collateral_price_history"),
+    history_counts: Map::new("collateral_price_history_counts"),
+    rounds: Map::new("collateral_price_rounds"),
+    submissions: Map::new("collateral_price_submissions"),
+    exchange_rate_keys: Map::new("collateral_exchange_rate_keys"),
+};
+
+impl FeedKind {
+    pub fn feed(&self) -> Feed {
+        match self {
+            FeedKind::Price => PRICE_FEED,
+            FeedKind::Collateral => COLLATERAL_FEED,
+        }
+    }
+}
+
+pub fn update_feeder_config(
+    deps: DepsMut,
+    feeder_config: FeederConfig,
+) -> Result<Response, ContractError> {
+    if feeder_config.quorum == 0 || feeder_config.round_blocks == 0 {
+        return Err(ContractError::InvalidFeederConfig);
+    }
+    let feeder_count = FEEDER_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    validate_quorum(feeder_config.quorum, feeder_count)?;
+    FEEDER_CONFIG.save(deps.storage, &feeder_config)?;
+    Ok(Response::new().add_attribute("method", "UpdateFeederConfig"))
+}
+
+pub fn add_feeder(
+    deps: DepsMut,
+    block: &BlockInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let feeder = deps.api.addr_validate(&address)?;
+    if !FEEDERS.has(deps.storage, feeder.clone()) {
+        update_feeder_count(deps.storage, 1)?;
+    }
+    FEEDERS.save(deps.storage, feeder.clone(), &block.height)?;
+    Ok(Response::new()
+        .add_attribute("method", "AddFeeder")
+        .add_attribute("feeder", feeder))
+}
+
+pub fn remove_feeder(
+    deps: DepsMut,
+    address: String,
+) -> Result<Response, ContractError> {
+    let feeder = deps.api.addr_validate(&address)?;
+    if !FEEDERS.has(deps.storage, feeder.clone()) {
+        return Err(ContractError::NotFeeder);
+    }
+    update_feeder_count(deps.storage, -1)?;
+    FEEDERS.remove(deps.storage, feeder.clone());
+    Ok(Response::new()
+        .add_attribute("method", "RemoveFeeder")
+        .add_attribute("feeder", feeder))
+}
+
+/// Changes the feeder count by `delta`, keeping the configured quorum a
+/// majority of the feeders that can still reach it.
+fn update_feeder_count(
+    storage: &mut dyn Storage,
+    delta: i32,
+) -> Result<(), ContractError> {
+    let feeder_count = FEEDER_COUNT
+        .may_load(storage)?
+        .unwrap_or_default()
+        .saturating_add_signed(delta);
+    if let Some(config) = FEEDER_CONFIG.may_load(storage)? {
+        validate_quorum(config.quorum, feeder_count)?;
+    }
+    FEEDER_COUNT.save(storage, &feeder_count)?;
+    Ok(())
+}
+
+/// A quorum above the feeder count would never publish, and one of half the
+/// feeders or less would publish rounds most feeders did not submit to.
+fn validate_quorum(quorum: u32, feeder_count: u32) -> Result<(), ContractError> {
+    if quorum > feeder_count || quorum * 2 <= feeder_count {
+        return Err(ContractError::InvalidQuorum(feeder_count));
+    }
+    Ok(())
+}
+
+/// Records the price of the feeder in the current round of the index. The
+/// median of a round is published once every feeder submitted to it, or
+/// otherwise by the first submission after its window, which opens a new
+/// round, if it got `quorum` submissions. Publishing before the window is
+/// over on fewer submissions would let a colluding minority that submits
+/// first set the price.
+pub fn submit_price(
+    deps: DepsMut,
+    block: &BlockInfo,
+    feeder: Addr,
+    kind: FeedKind,
+    index: u64,
+    price: Decimal,
+) -> Result<Response, ContractError> {
+    let config = FEEDER_CONFIG
+        .may_load(deps.storage)?
+        .ok_or(ContractError::FeedersNotConfigured)?;
+    assert_pushed_mode(deps.storage)?;
+    if !FEEDERS.has(deps.storage, feeder.clone()) {
+        return Err(ContractError::NotFeeder);
+    }
+
+    let feed = kind.feed();
+    let mut response = Response::new()
+        .add_attribute("method", "SubmitPrice")
+        .add_attribute("feeder", feeder.clone())
+        .add_attribute("index", index.to_string());
+    let new_round = Round {
+        start_block: block.height,
+        prices: vec![],
+        published: false,
+    };
+    let last_round = feed
+        .rounds
+        .prefix(index)
+        .range(deps.storage, None, None, Order::Descending)
+        .next()
+        .transpose()?;
+    let (round_id, mut round) = match last_round {
+        Some((id, round))
+            if block.height < round.start_block + config.round_blocks =>
+        {
+            (id, round)
+        }
+        Some((id, mut round)) => {
+            // the window of the last round is over
+            if let Some(median) = publish_round(
+                deps.storage,
+                block,
+                &feed,
+                &config,
+                index,
+                &mut round,
+            )? {
+                feed.rounds.save(deps.storage, (index, id), &round)?;
+                response = response
+                    .add_attribute("published_round", id.to_string())
+                    .add_attribute("published_price", median.to_string());
+            }
+            (id + 1, new_round)
+        }
+        None => (0, new_round),
+    };
+
+    if feed
+        .submissions
+        .has(deps.storage, (feeder.clone(), index, round_id))
+    {
+        return Err(ContractError::AlreadySubmitted);
+    }
+    feed.submissions.save(
+        deps.storage,
+        (feeder.clone(), index, round_id),
+        &price,
+    )?;
+    round.prices.push(price);
+
+    response = response.add_attribute("round", round_id.to_string());
+    // every feeder submitted, the window need not be waited for
+    if round.prices.len() >= FEEDER_COUNT.load(deps.storage)? as usize {
+        if let Some(median) = publish_round(
+            deps.storage,
+            block,
+            &feed,
+            &config,
+            index,
+            &mut round,
+        )? {
+            response = response
+                .add_attribute("published_round", round_id.to_string())
+                .add_attribute("published_price", median.to_string());
+        }
+    }
+    feed.rounds.save(deps.storage, (index, round_id), &round)?;
+
+    Ok(response)
+}
+
+/// Publishes the median of the round unless it already was or got fewer
+/// than `quorum` submissions, returning the published median.
+fn publish_round(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    feed: &Feed,
+    config: &FeederConfig,
+    index: u64,
+    round: &mut Round,
+) -> Result<Option<Decimal>, ContractError> {
+    if round.published || round.prices.len() < config.quorum as usize {
+        return Ok(None);
+    }
+    let median = median(&round.prices);
+    save_price(storage, feed, index, median, block.height)?;
+    round.published = true;
+    Ok(Some(median))
+}
+
+fn median(prices: &[Decimal]) -> Decimal {
+    let mut prices = prices.to_vec();
+    prices.sort();
+    let middle = prices.len() / 2;
+    if prices.len().is_multiple_of(2) {
+        (prices[middle - 1] + prices[middle]) * Decimal::percent(50)
+    } else {
+        prices[middle]
+    }
+}
+
+pub fn query_feeders(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(Addr, u64)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|addr| Bound::exclusive(Addr::unchecked(addr)));
+    FEEDERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect()
+}
+
+/// Prices submitted by the feeder, ordered by index and round.
+pub fn query_submissions(
+    deps: Deps,
+    feeder: String,
+    kind: FeedKind,
+    start_after: Option<(u64, u64)>,
+    limit: Option<u32>,
+) -> StdResult<Vec<Submission>> {
+    let feeder = deps.api.addr_validate(&feeder)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    kind.feed()
+        .submissions
+        .sub_prefix(feeder)
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let ((index, round), price) = item?;
+            Ok(Submission {
+                index,
+                round,
+                price,
+            })
+        })
+        .collect()
+}
+
+/// Rounds of the index opened since the feeder was added that the feeder
+/// did not submit a price to, out of the `limit` rounds after `start_after`.
+pub fn query_missed_rounds(
+    deps: Deps,
+    feeder: String,
+    kind: FeedKind,
+    index: u64,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<MissedRoundsResponse> {
+    let feeder = deps.api.addr_validate(&feeder)?;
+    let added_block = FEEDERS.load(deps.storage, feeder.clone())?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let feed = kind.feed();
+
+    let mut response = MissedRoundsResponse {
+        missed_rounds: vec![],
+        last_round: None,
+    };
+    for item in feed
+        .rounds
+        .prefix(index)
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+    {
+        let (round_id, round) = item?;
+        if round.start_block >= added_block
+            && !feed
+                .submissions
+                .has(deps.storage, (feeder.clone(), index, round_id))
+        {
+            response.missed_rounds.push(round_id);
+        }
+        response.last_round = Some(round_id);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{
+        execute, instantiate, query, OracleInstantiateMsg, OracleQueryMsg,
+        OraclesExecuteMsg,
+    };
+    use cosmwasm_std::testing::{
+        message_info, mock_dependencies, mock_env, MockApi, MockQuerier,
+        MockStorage,
+    };
+    use cosmwasm_std::{from_json, Env, OwnedDeps};
+
+    type MockDeps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+    /// Oracle with three feeders, a quorum of two and rounds of 10 blocks.
+    fn set_up() -> (MockDeps, Vec<Addr>) {
+        set_up_with(3, 2)
+    }
+
+    fn set_up_with(feeder_count: u32, quorum: u32) -> (MockDeps, Vec<Addr>) {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            message_info(&owner, &[]),
+            OracleInstantiateMsg {
+                owner: Some(owner.to_string()),
+            },
+        )
+        .unwrap();
+
+        let feeders: Vec<Addr> = (1..=feeder_count)
+            .map(|i| deps.api.addr_make(&format!("feeder{i}")))
+            .collect();
+        let mut msgs: Vec<OraclesExecuteMsg> = feeders
+            .iter()
+            .map(|feeder| OraclesExecuteMsg::AddFeeder {
+                address: feeder.to_string(),
+            })
+            .collect();
+        msgs.push(OraclesExecuteMsg::UpdateFeederConfig {
+            feeder_config: FeederConfig {
+                quorum,
+                round_blocks: 10,
+            },
+        });
+        for msg in msgs {
+            execute(deps.as_mut(), mock_env(), message_info(&owner, &[]), msg)
+                .unwrap();
+        }
+        (deps, feeders)
+    }
+
+    fn env_at(height: u64) -> Env {
+        let mut env = mock_env();
+        env.block.height = height;
+        env
+    }
+
+    fn submit(
+        deps: &mut MockDeps,
+        height: u64,
+        feeder: &Addr,
+        price: u64,
+    ) -> Result<Response, ContractError> {
+        execute(
+            deps.as_mut(),
+            env_at(height),
+            message_info(feeder, &[]),
+            OraclesExecuteMsg::SubmitPrice {
+                index: 0,
+                price: Decimal::from_ratio(price, 1_u64),
+            },
+        )
+    }
+
+    fn price(deps: &MockDeps) -> Option<Price> {
+        query(
+            deps.as_ref(),
+            mock_env(),
+            OracleQueryMsg::GetPriceData { index: 0 },
+        )
+        .ok()
+        .map(|res| from_json(res).unwrap())
+    }
+
+    #[test]
+    fn median_is_published_when_the_round_closes() {
+        let (mut deps, feeders) = set_up();
+        let height = mock_env().block.height;
+
+        submit(&mut deps, height, &feeders[0], 100).unwrap();
+        assert_eq!(
+            submit(&mut deps, height, &feeders[0], 100),
+            Err(ContractError::AlreadySubmitted)
+        );
+        submit(&mut deps, height + 1, &feeders[1], 110).unwrap();
+        assert_eq!(price(&deps), None);
+
+        // the first submission after the window publishes the round
+        submit(&mut deps, height + 10, &feeders[0], 120).unwrap();
+        assert_eq!(
+            price(&deps),
+            Some(Price {
+                last_update_block: height + 10,
+                price: Decimal::from_ratio(105_u64, 1_u64),
+                previous_price: None,
+            })
+        );
+
+        // a round every feeder submitted to is published right away
+        submit(&mut deps, height + 11, &feeders[1], 130).unwrap();
+        submit(&mut deps, height + 12, &feeders[2], 100).unwrap();
+        let expected = Price {
+            last_update_block: height + 12,
+            price: Decimal::from_ratio(120_u64, 1_u64),
+            previous_price: Some(Decimal::from_ratio(105_u64, 1_u64)),
+        };
+        assert_eq!(price(&deps), Some(expected.clone()));
+
+        // a round short of the quorum is not published
+        submit(&mut deps, height + 20, &feeders[0], 200).unwrap();
+        submit(&mut deps, height + 30, &feeders[0], 200).unwrap();
+        assert_eq!(price(&deps), Some(expected));
+    }
+
+    #[test]
+    fn colluding_minority_cannot_set_the_price() {
+        let (mut deps, feeders) = set_up_with(5, 3);
+        let height = mock_env().block.height;
+        let honest_price = Some(Decimal::from_ratio(100_u64, 1_u64));
+
+        // the colluders submit first and reach the quorum with one feeder
+        submit(&mut deps, height, &feeders[0], 1_000).unwrap();
+        submit(&mut deps, height, &feeders[1], 1_000).unwrap();
+        submit(&mut deps, height + 1, &feeders[2], 100).unwrap();
+        assert_eq!(price(&deps), None);
+
+        submit(&mut deps, height + 2, &feeders[3], 100).unwrap();
+        submit(&mut deps, height + 3, &feeders[4], 100).unwrap();
+        assert_eq!(price(&deps).map(|price| price.price), honest_price);
+
+        // a round the colluders stay out of is published after its window,
+        // their next round is short of the quorum
+        for feeder in &feeders[2..] {
+            submit(&mut deps, height + 10, feeder, 100).unwrap();
+        }
+        submit(&mut deps, height + 20, &feeders[0], 1_000).unwrap();
+        submit(&mut deps, height + 20, &feeders[1], 1_000).unwrap();
+        assert_eq!(price(&deps).map(|price| price.price), honest_price);
+        assert_eq!(
+            price(&deps).map(|price| price.last_update_block),
+            Some(height + 20)
+        );
+    }
+
+    #[test]
+    fn only_feeders_set_prices() {
+        let (mut deps, _) = set_up();
+        let owner = deps.api.addr_make("owner");
+        let height = mock_env().block.height;
+
+        assert_eq!(
+            submit(&mut deps, height, &owner, 100),
+            Err(ContractError::NotFeeder)
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            message_info(&owner, &[]),
+            OraclesExecuteMsg::SetPrice {
+                index: 0,
+                price: Decimal::one(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::FeedersEnabled);
+    }
+
+    #[test]
+    fn quorum_is_a_majority_of_the_feeders() {
+        let (mut deps, feeders) = set_up();
+        let owner = deps.api.addr_make("owner");
+        let mut execute_owner = |msg| {
+            execute(deps.as_mut(), mock_env(), message_info(&owner, &[]), msg)
+        };
+        let update_quorum = |quorum| OraclesExecuteMsg::UpdateFeederConfig {
+            feeder_config: FeederConfig {
+                quorum,
+                round_blocks: 10,
+            },
+        };
+
+        for quorum in [1, 4] {
+            assert_eq!(
+                execute_owner(update_quorum(quorum)),
+                Err(ContractError::InvalidQuorum(3))
+            );
+        }
+
+        // a fourth feeder needs a quorum of 3
+        let feeder4 = MockApi::default().addr_make("feeder4");
+        assert_eq!(
+            execute_owner(OraclesExecuteMsg::AddFeeder {
+                address: feeder4.to_string(),
+            }),
+            Err(ContractError::InvalidQuorum(4))
+        );
+        execute_owner(update_quorum(3)).unwrap();
+        execute_owner(OraclesExecuteMsg::AddFeeder {
+            address: feeder4.to_string(),
+        })
+        .unwrap();
+
+        // removing feeders can't leave the quorum out of reach
+        execute_owner(OraclesExecuteMsg::RemoveFeeder {
+            address: feeders[0].to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            execute_owner(OraclesExecuteMsg::RemoveFeeder {
+                address: feeders[1].to_string(),
+            }),
+            Err(ContractError::InvalidQuorum(2))
+        );
+        assert_eq!(
+            execute_owner(OraclesExecuteMsg::RemoveFeeder {
+                address: feeders[0].to_string(),
+            }),
+            Err(ContractError::NotFeeder)
+        );
+    }
+
+    #[test]
+    fn missed_rounds_and_history() {
+        let (mut deps, feeders) = set_up();
+        let height = mock_env().block.height;
+
+        submit(&mut deps, height, &feeders[0], 100).unwrap();
+        submit(&mut deps, height, &feeders[1], 100).unwrap();
+        submit(&mut deps, height + 10, &feeders[0], 110).unwrap();
+        submit(&mut deps, height + 20, &feeders[1], 120).unwrap();
+
+        let missed = |deps: &MockDeps,
+                      feeder: &Addr,
+                      start_after: Option<u64>,
+                      limit: Option<u32>|
+         -> MissedRoundsResponse {
+            from_json(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    OracleQueryMsg::MissedRounds {
+                        feeder: feeder.to_string(),
+                        kind: FeedKind::Price,
+                        index: 0,
+                        start_after,
+                        limit,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(missed(&deps, &feeders[0], None, None).missed_rounds, [2]);
+        assert_eq!(missed(&deps, &feeders[1], None, None).missed_rounds, [1]);
+        assert_eq!(
+            missed(&deps, &feeders[2], None, None).missed_rounds,
+            [0, 1, 2]
+        );
+
+        // the limit bounds the rounds scanned, not the rounds returned
+        assert_eq!(
+            missed(&deps, &feeders[1], None, Some(2)),
+            MissedRoundsResponse {
+                missed_rounds: vec![1],
+                last_round: Some(1),
+            }
+        );
+        assert_eq!(
+            missed(&deps, &feeders[1], Some(1), Some(2)),
+            MissedRoundsResponse {
+                missed_rounds: vec![],
+                last_round: Some(2),
+            }
+        );
+        assert_eq!(
+            missed(&deps, &feeders[1], Some(2), Some(2)),
+            MissedRoundsResponse {
+                missed_rounds: vec![],
+                last_round: None,
+            }
+        );
+
+        let submissions: Vec<Submission> = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                OracleQueryMsg::FeederSubmissions {
+                    feeder: feeders[0].to_string(),
+                    kind: FeedKind::Price,
+                    start_after: Some((0, 0)),
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            submissions,
+            vec![Submission {
+                index: 0,
+                round: 1,
+                price: Decimal::from_ratio(110_u64, 1_u64),
+            }]
+        );
+    }
+}
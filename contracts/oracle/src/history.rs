@@ -0,0 +1,212 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal, Deps, StdError, StdResult, Storage};
+
+use crate::{contract::Price, feeders::Feed, native::assert_pushed_mode};
+
+/// Number of past prices kept per index, older ones are overwritten.
+pub const HISTORY_SIZE: u64 = 100;
+
+#[cw_serde]
+pub struct PriceRange {
+    pub high: Decimal,
+    pub low: Decimal,
+}
+
+/// Publishes the price of the index and appends it to the index history.
+pub(crate) fn save_price(
+    storage: &mut dyn Storage,
+    feed: &Feed,
+    index: u64,
+    price: Decimal,
+    height: u64,
+) -> StdResult<()> {
+    let previous = feed.prices.may_load(storage, index)?;
+    let price = Price::updated(previous, price, height);
+    feed.prices.save(storage, index, &price)?;
+
+    let count = feed.history_counts.may_load(storage, index)?.unwrap_or(0);
+    feed.history
+        .save(storage, (index, count % HISTORY_SIZE), &price)?;
+    feed.history_counts.save(storage, index, &(count + 1))
+}
+
+/// Past prices of the index, newest first. Exchange rates read in native
+/// mode are not recorded, so there is no history to query then.
+fn history<'a>(
+    deps: Deps<'a>,
+    feed: &'a Feed,
+    index: u64,
+) -> StdResult<impl Iterator<Item = StdResult<Price>> + 'a> {
+    assert_pushed_mode(deps.storage)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    let count = feed
+        .history_counts
+        .may_load(deps.storage, index)?
+        .unwrap_or(0);
+    Ok((count.saturating_sub(HISTORY_SIZE)..count)
+        .rev()
+        .map(move |i| {
+            feed.history.load(deps.storage, (index, i % HISTORY_SIZE))
+        }))
+}
+
+pub fn query_last_prices(
+    deps: Deps,
+    feed: &Feed,
+    index: u64,
+    count: u32,
+) -> StdResult<Vec<Price>> {
+    history(deps, feed, index)?.take(count as usize).collect()
+}
+
+/// Average of the prices over the last `window_blocks` blocks, each weighted
+/// by the blocks it was the published price for. Only the span covered by
+/// the history counts when it is shorter than the window.
+pub fn query_twap(
+    deps: Deps,
+    height: u64,
+    feed: &Feed,
+    index: u64,
+    window_blocks: u64,
+) -> StdResult<Decimal> {
+    let start = height.saturating_sub(window_blocks);
+    let mut end = height;
+    let mut weighted_sum = Decimal::zero();
+    let mut total_blocks = 0_u64;
+    let mut latest = None;
+
+    for price in history(deps, feed, index)? {
+        let price = price?;
+        latest.get_or_insert(price.price);
+
+        let from = price.last_update_block.max(start);
+        if end > from {
+            weighted_sum = weighted_sum.checked_add(
+                price
+                    .price
+                    .checked_mul(Decimal::from_ratio(end - from, 1_u64))?,
+            )?;
+            total_blocks += end - from;
+        }
+        if price.last_update_block <= start {
+            break;
+        }
+        end = end.min(price.last_update_block);
+    }
+
+    match latest {
+        None => Err(StdError::not_found("price history")),
+        // every price in the window was published at the current block
+        Some(latest) if total_blocks == 0 => Ok(latest),
+        Some(_) => Ok(weighted_sum / Decimal::from_ratio(total_blocks, 1_u64)),
+    }
+}
+
+/// Highest and lowest prices published since `since_block`, counting the
+/// price that was published at that block.
+pub fn query_price_range(
+    deps: Deps,
+    feed: &Feed,
+    index: u64,
+    since_block: u64,
+) -> StdResult<PriceRange> {
+    let mut range: Option<PriceRange> = None;
+    for price in history(deps, feed, index)? {
+        let price = price?;
+        range = Some(match range {
+            None => PriceRange {
+                high: price.price,
+                low: price.price,
+            },
+            Some(range) => PriceRange {
+                high: range.high.max(price.price),
+                low: range.low.min(price.price),
+            },
+        });
+        if price.last_update_block <= since_block {
+            break;
+        }
+    }
+    range.ok_or_else(|| StdError::not_found("price history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeders::PRICE_FEED;
+    use cosmwasm_std::testing::mock_dependencies;
+
+    fn dec(value: u64) -> Decimal {
+        Decimal::from_ratio(value, 1_u64)
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut deps = mock_dependencies();
+        for i in 0..HISTORY_SIZE + 5 {
+            save_price(deps.as_mut().storage, &PRICE_FEED, 0, dec(i + 1), i)
+                .unwrap();
+        }
+
+        let prices =
+            query_last_prices(deps.as_ref(), &PRICE_FEED, 0, 1_000).unwrap();
+        assert_eq!(prices.len() as u64, HISTORY_SIZE);
+        assert_eq!(prices[0].price, dec(HISTORY_SIZE + 5));
+        assert_eq!(prices[0].previous_price, Some(dec(HISTORY_SIZE + 4)));
+        assert_eq!(prices.last().unwrap().price, dec(6));
+    }
+
+    #[test]
+    fn twap_and_range() {
+        let mut deps = mock_dependencies();
+        // 100 from block 10, 200 from block 20 and 110 from block 30
+        for (height, price) in [(10, 100), (20, 200), (30, 110)] {
+            save_price(
+                deps.as_mut().storage,
+                &PRICE_FEED,
+                0,
+                dec(price),
+                height,
+            )
+            .unwrap();
+        }
+
+        let twap = |window_blocks| {
+            query_twap(deps.as_ref(), 40, &PRICE_FEED, 0, window_blocks).unwrap()
+        };
+        assert_eq!(twap(0), dec(110));
+        assert_eq!(twap(10), dec(110));
+        // 10 blocks at 110 and 5 at 200
+        assert_eq!(twap(15), dec(140));
+        // the history only covers the last 30 blocks
+        assert_eq!(twap(1_000), dec(410) / dec(3));
+
+        let range = |since_block| {
+            query_price_range(deps.as_ref(), &PRICE_FEED, 0, since_block)
+                .unwrap()
+        };
+        assert_eq!(
+            range(30),
+            PriceRange {
+                high: dec(110),
+                low: dec(110),
+            }
+        );
+        assert_eq!(
+            range(25),
+            PriceRange {
+                high: dec(200),
+                low: dec(110),
+            }
+        );
+        assert_eq!(
+            range(0),
+            PriceRange {
+                high: dec(200),
+                low: dec(100),
+            }
+        );
+
+        assert!(query_twap(deps.as_ref(), 40, &PRICE_FEED, 1, 10).is_err());
+    }
+}
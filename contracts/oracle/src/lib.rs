@@ -1 +1,5 @@
 pub mod contract;
+pub mod error;
+pub mod feeders;
+pub mod history;
+pub mod native;
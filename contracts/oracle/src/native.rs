@@ -0,0 +1,141 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{
+    to_json_vec, ContractResult, Decimal, Deps, DepsMut, Empty, Env, Order,
+    QueryRequest, Response, StdError, StdResult, Storage, SystemResult, Uint128,
+};
+use cw_storage_plus::{Bound, Item};
+use nibiru_std::proto::nibiru::oracle::QueryExchangeRateRequest;
+use prost::Message;
+
+use crate::{contract::Price, error::ContractError, feeders::Feed};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+pub const EXCHANGE_RATE_PATH: &str = "/nibiru.oracle.v1.Query/ExchangeRate";
+
+/// Where the prices come from: pushed by the owner or the feeders, or read
+/// from the exchange rates voted by the validators in the `x/oracle` module.
+#[cw_serde]
+#[derive(Default)]
+pub enum OracleMode {
+    #[default]
+    Pushed,
+    Native,
+}
+
+pub const ORACLE_MODE: Item<OracleMode> = Item::new("oracle_mode");
+
+/// `QueryExchangeRateResponse` of `x/oracle` with the block the rate was
+/// voted at, which versions of the module without it leave to zero.
+#[derive(Clone, PartialEq, Message)]
+pub struct ExchangeRateResponse {
+    #[prost(string, tag = "1")]
+    pub exchange_rate: String,
+    #[prost(int64, tag = "2")]
+    pub block_timestamp_ms: i64,
+    #[prost(uint64, tag = "3")]
+    pub block_height: u64,
+}
+
+pub fn set_oracle_mode(
+    deps: DepsMut,
+    mode: OracleMode,
+) -> Result<Response, ContractError> {
+    ORACLE_MODE.save(deps.storage, &mode)?;
+    Ok(Response::new().add_attribute("method", "SetOracleMode"))
+}
+
+/// Maps price indices to `x/oracle` pairs, eg. "ubtc:uusd".
+pub fn set_exchange_rate_keys(
+    deps: DepsMut,
+    feed: &Feed,
+    keys: Vec<(u64, String)>,
+) -> Result<(), ContractError> {
+    for (index, key) in keys {
+        feed.exchange_rate_keys.save(deps.storage, index, &key)?;
+    }
+    Ok(())
+}
+
+/// Prices can only be pushed, and their history is only kept, while the
+/// contract is not reading them from the chain.
+pub(crate) fn assert_pushed_mode(
+    storage: &dyn Storage,
+) -> Result<(), ContractError> {
+    if ORACLE_MODE.may_load(storage)?.unwrap_or_default() == OracleMode::Native {
+        return Err(ContractError::NativeMode);
+    }
+    Ok(())
+}
+
+/// Current price of the index. In native mode the exchange rate is read at
+/// every query and reported as updated at the block it was voted at, or at
+/// block 0 when the module does not tell, so that staleness checks fail
+/// closed. Native prices have no previous price.
+pub fn load_price(
+    deps: Deps,
+    env: &Env,
+    feed: &Feed,
+    index: u64,
+) -> StdResult<Price> {
+    if ORACLE_MODE.may_load(deps.storage)?.unwrap_or_default()
+        == OracleMode::Pushed
+    {
+        return feed.prices.load(deps.storage, index);
+    }
+
+    let pair = feed.exchange_rate_keys.load(deps.storage, index)?;
+    let response = query_exchange_rate(deps, pair)?;
+    // the rate is a legacy dec, serialized as its 18 decimals atomic value
+    Ok(Price {
+        last_update_block: response.block_height.min(env.block.height),
+        price: Decimal::new(response.exchange_rate.parse::<Uint128>()?),
+        previous_price: None,
+    })
+}
+
+fn query_exchange_rate(
+    deps: Deps,
+    pair: String,
+) -> StdResult<ExchangeRateResponse> {
+    #[allow(deprecated)]
+    let request: QueryRequest<Empty> = QueryRequest::Stargate {
+        path: EXCHANGE_RATE_PATH.to_string(),
+        data: QueryExchangeRateRequest { pair }.encode_to_vec().into(),
+    };
+    let response = match deps.querier.raw_query(&to_json_vec(&request)?) {
+        SystemResult::Err(err) => {
+            return Err(StdError::generic_err(format!(
+                "querier system error: {err}"
+            )))
+        }
+        SystemResult::Ok(ContractResult::Err(err)) => {
+            return Err(StdError::generic_err(format!(
+                "querier contract error: {err}"
+            )))
+        }
+        SystemResult::Ok(ContractResult::Ok(response)) => response,
+    };
+
+    ExchangeRateResponse::decode(response.as_slice())
+        .map_err(|err| StdError::parse_err("QueryExchangeRateResponse", err))
+}
+
+pub fn query_exchange_rate_keys(
+    deps: Deps,
+    feed: &Feed,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(u64, String)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    feed.exchange_rate_keys
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect()
+}
@@ -1,8 +1,11 @@
-use cosmwasm_std::{Addr, BlockInfo, Decimal, Deps, Storage, Uint128};
+use cosmwasm_std::{
+    Addr, BlockInfo, Decimal, Deps, Int128, SignedDecimal, Storage, Uint128,
+};
 use state::{
     BorrowingData, BorrowingFeeInput, BorrowingInitialAccFees,
-    BorrowingPairGroup, OpenInterest, PendingBorrowingAccFeesInput, GROUPS,
-    GROUP_OIS, INITIAL_ACC_FEES, PAIRS, PAIR_GROUPS, PAIR_OIS,
+    BorrowingPairGroup, FundingData, OpenInterest, PendingBorrowingAccFeesInput,
+    GROUPS, GROUP_OIS, INITIAL_ACC_FEES, PAIRS, PAIR_FUNDINGS, PAIR_GROUPS,
+    PAIR_OIS,
 };
 
 use crate::{
@@ -11,7 +14,7 @@ use crate::{
     fees::calculate_fee_amount,
     pairs::state::FEES,
     trading::{state::Trade, utils::get_collateral_price},
-    utils::u128_to_dec,
+    utils::{dec_to_sdec, u128_to_dec, u128_to_i128, u128_to_sdec},
 };
 
 pub mod state;
@@ -83,6 +86,7 @@ pub fn get_trade_borrowing_fees(
     Ok(fee_amount_collateral)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn get_borrowing_pair_group_acc_fees_deltas(
     storage: &dyn Storage,
     collateral_index: u64,
@@ -153,6 +157,7 @@ fn get_borrowing_pair_group_acc_fees_deltas(
     Ok((delta_group, delta_pair, before_trade_open))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_trade_borrowing(
     block: &BlockInfo,
     sender: Addr,
@@ -180,6 +185,12 @@ pub fn handle_trade_borrowing(
         group_index,
         block_number,
     )?;
+    set_pair_pending_acc_funding_fees(
+        storage,
+        collateral_index,
+        pair_index,
+        block_number,
+    )?;
 
     update_pair_oi(
         storage,
@@ -213,6 +224,7 @@ pub fn handle_trade_borrowing(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn reset_trade_borrowing_fees(
     sender: Addr,
     storage: &mut dyn Storage,
@@ -237,6 +249,13 @@ fn reset_trade_borrowing_fees(
         current_block,
     )?;
 
+    let pair_funding_data = get_pair_pending_acc_funding_fees(
+        storage,
+        collateral_index,
+        pair_index,
+        current_block,
+    )?;
+
     INITIAL_ACC_FEES.save(
         storage,
         (collateral_index, sender, trade_index),
@@ -252,6 +271,11 @@ fn reset_trade_borrowing_fees(
                 group_borrowing_data.acc_fee_short
             },
             block: current_block,
+            acc_funding_fee: if long {
+                pair_funding_data.acc_per_oi_long
+            } else {
+                pair_funding_data.acc_per_oi_short
+            },
         },
     )?;
 
@@ -513,6 +537,116 @@ fn get_borrowing_pending_acc_fees(
     Ok((acc_fee_long, acc_fee_short, delta))
 }
 
+/// Funding fees of the trade since it was opened, positive when the trade
+/// pays and negative when it receives funding.
+pub fn get_trade_funding_fees(
+    deps: &Deps,
+    block: &BlockInfo,
+    input: BorrowingFeeInput,
+) -> Result<Int128, ContractError> {
+    let initial_fees = INITIAL_ACC_FEES.load(
+        deps.storage,
+        (input.collateral_index, input.trader, input.index),
+    )?;
+
+    let funding = get_pair_pending_acc_funding_fees(
+        deps.storage,
+        input.collateral_index,
+        input.pair_index,
+        block.height,
+    )?;
+    let acc_funding_fee = if input.long {
+        funding.acc_per_oi_long
+    } else {
+        funding.acc_per_oi_short
+    };
+
+    Ok(u128_to_sdec(input.collateral.checked_mul(input.leverage)?)?
+        .checked_mul(acc_funding_fee.checked_sub(initial_fees.acc_funding_fee)?)?
+        .to_int_ceil())
+}
+
+fn set_pair_pending_acc_funding_fees(
+    storage: &mut dyn Storage,
+    collateral_index: u64,
+    pair_index: u64,
+    block_number: u64,
+) -> Result<(), ContractError> {
+    let funding = get_pair_pending_acc_funding_fees(
+        storage,
+        collateral_index,
+        pair_index,
+        block_number,
+    )?;
+
+    Ok(PAIR_FUNDINGS.save(storage, (collateral_index, pair_index), &funding)?)
+}
+
+/// Accrues the pair funding at the current fee before replacing the fee, so
+/// that open trades keep what they already paid or received.
+pub(crate) fn set_pair_funding_fee_per_block(
+    storage: &mut dyn Storage,
+    collateral_index: u64,
+    pair_index: u64,
+    block_number: u64,
+    fee_per_block: Decimal,
+) -> Result<(), ContractError> {
+    let mut funding = get_pair_pending_acc_funding_fees(
+        storage,
+        collateral_index,
+        pair_index,
+        block_number,
+    )?;
+    funding.fee_per_block = fee_per_block;
+
+    Ok(PAIR_FUNDINGS.save(storage, (collateral_index, pair_index), &funding)?)
+}
+
+/// The side with the most open interest pays `fee_per_block` of the skew
+/// every block, shared by the other side pro rata of its open interest.
+fn get_pair_pending_acc_funding_fees(
+    storage: &dyn Storage,
+    collateral_index: u64,
+    pair_index: u64,
+    block_number: u64,
+) -> Result<FundingData, ContractError> {
+    let mut funding = PAIR_FUNDINGS
+        .may_load(storage, (collateral_index, pair_index))?
+        .unwrap_or_default();
+
+    if block_number < funding.acc_last_updated_block {
+        return Err(ContractError::BlockOrder);
+    }
+
+    let (oi_long, oi_short) = get_pair_ois_collateral(
+        storage,
+        collateral_index,
+        pair_index,
+        block_number,
+    )?;
+
+    let paid_by_longs = u128_to_sdec(oi_long)?
+        .checked_sub(u128_to_sdec(oi_short)?)?
+        .checked_mul(dec_to_sdec(funding.fee_per_block)?)?
+        .checked_mul(u128_to_sdec(
+            (block_number - funding.acc_last_updated_block).into(),
+        )?)?;
+
+    if !oi_long.is_zero() {
+        funding.acc_per_oi_long = funding
+            .acc_per_oi_long
+            .checked_add(paid_by_longs.checked_div(u128_to_sdec(oi_long)?)?)?;
+    }
+    if !oi_short.is_zero() {
+        funding.acc_per_oi_short = funding
+            .acc_per_oi_short
+            .checked_sub(paid_by_longs.checked_div(u128_to_sdec(oi_short)?)?)?;
+    }
+    funding.acc_last_updated_block = block_number;
+
+    Ok(funding)
+}
+
 fn get_borrowing_pair_group_index(
     storage: &dyn Storage,
     collateral_index: u64,
@@ -534,22 +668,26 @@ pub fn get_trade_liquidation_price_with_fees(
         crate::pairs::state::PAIRS.load(deps.storage, trade.pair_index)?;
     let fee = FEES.load(deps.storage, pair.fee_index)?;
 
-    let closing_fees_collateral = Decimal::from_ratio(
-        get_position_size_collateral_basis(
+    let closing_fees_collateral = calculate_fee_amount(
+        deps,
+        block,
+        &trade.user,
+        u128_to_dec(get_position_size_collateral_basis(
             deps,
             trade.collateral_index,
-            trade.collateral_amount,
+            trade.get_position_size_collateral(),
             fee.min_position_size_usd,
-        )?,
-        0_u64,
-    )
-    .checked_mul(fee.close_fee_p.checked_add(fee.trigger_order_fee_p)?)?
-    .to_uint_floor();
+        )?)?
+        .checked_mul(fee.close_fee_p.checked_add(fee.trigger_order_fee_p)?)?
+        .to_uint_floor(),
+    )?;
 
-    let borrowing_fees_collateral = if use_borrowing_fees {
-        calculate_fee_amount(deps, block, &trade.user, closing_fees_collateral)?
+    // funding is signed, trades receiving funding move away from liquidation
+    let holding_fees_collateral = if use_borrowing_fees {
+        u128_to_i128(trade.get_trade_borrowing_fees_collateral(deps, block)?)?
+            .checked_add(trade.get_trade_funding_fees_collateral(deps, block)?)?
     } else {
-        Uint128::zero()
+        Int128::zero()
     };
 
     get_trade_liquidation_price(
@@ -557,7 +695,8 @@ pub fn get_trade_liquidation_price_with_fees(
         trade.long,
         trade.collateral_amount,
         trade.leverage,
-        closing_fees_collateral.checked_add(borrowing_fees_collateral)?,
+        u128_to_i128(closing_fees_collateral)?
+            .checked_add(holding_fees_collateral)?,
     )
 }
 
@@ -585,34 +724,108 @@ fn get_min_position_size_collateral(
     collateral_index: u64,
     min_position_size_usd: Uint128,
 ) -> Result<Uint128, ContractError> {
-    Ok(Decimal::from_ratio(min_position_size_usd, 0_u64)
+    Ok(u128_to_dec(min_position_size_usd)?
         .checked_div(get_collateral_price(deps, &collateral_index)?)?
         .to_uint_floor())
 }
 
+/// Price at which the trade loses `LIQ_THRESHOLD_P` of its collateral once
+/// `fees_collateral` are paid. Fees above that threshold put the liquidation
+/// price on the profitable side of the open price.
 pub fn get_trade_liquidation_price(
     open_price: Decimal,
     long: bool,
     collateral: Uint128,
     leverage: Uint128,
-    fees_collateral: Uint128,
+    fees_collateral: Int128,
 ) -> Result<Decimal, ContractError> {
-    let collateral_liq_negative_pnl =
-        LIQ_THRESHOLD_P.checked_mul(Decimal::from_ratio(collateral, 0_u64))?;
+    let liq_pnl_collateral =
+        dec_to_sdec(LIQ_THRESHOLD_P.checked_mul(u128_to_dec(collateral)?)?)?
+            .checked_sub(
+                SignedDecimal::from_atomics(fees_collateral, 0)
+                    .map_err(|_| ContractError::ConversionOverflow)?,
+            )?;
 
     let liq_price_distance = open_price
-        .checked_mul(
-            collateral_liq_negative_pnl
-                .checked_sub(Decimal::from_ratio(fees_collateral, 0_u64))?,
-        )?
-        .checked_div(Decimal::from_ratio(collateral, 0_u64))?
-        .checked_div(Decimal::from_ratio(leverage, 0_u64))?;
-
-    let liq_price = if long {
-        open_price.checked_sub(liq_price_distance)?
+        .checked_mul(liq_pnl_collateral.abs_diff(SignedDecimal::zero()))?
+        .checked_div(u128_to_dec(collateral)?)?
+        .checked_div(u128_to_dec(leverage)?)?;
+
+    let liq_price = if long != liq_pnl_collateral.is_negative() {
+        open_price.saturating_sub(liq_price_distance)
     } else {
         open_price.checked_add(liq_price_distance)?
     };
 
     Ok(liq_price)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct LiqPriceTestCase {
+        description: &'static str,
+        long: bool,
+        fees_collateral: Int128,
+        expected_result: Result<Decimal, ContractError>,
+    }
+
+    #[test]
+    fn test_get_trade_liquidation_price_cases() {
+        let test_cases = vec![
+            LiqPriceTestCase {
+                description: "Long without fees",
+                long: true,
+                fees_collateral: Int128::zero(),
+                expected_result: Ok("91".parse::<Decimal>().unwrap()),
+            },
+            LiqPriceTestCase {
+                description: "Long with fees",
+                long: true,
+                fees_collateral: Int128::new(100),
+                expected_result: Ok("92".parse::<Decimal>().unwrap()),
+            },
+            LiqPriceTestCase {
+                description: "Short with fees",
+                long: false,
+                fees_collateral: Int128::new(100),
+                expected_result: Ok("108".parse::<Decimal>().unwrap()),
+            },
+            LiqPriceTestCase {
+                description: "Long receiving more than it pays in fees",
+                long: true,
+                fees_collateral: Int128::new(-100),
+                expected_result: Ok("90".parse::<Decimal>().unwrap()),
+            },
+            LiqPriceTestCase {
+                description: "Long with fees above the liquidation threshold",
+                long: true,
+                fees_collateral: Int128::new(1_000),
+                expected_result: Ok("101".parse::<Decimal>().unwrap()),
+            },
+            LiqPriceTestCase {
+                description: "Short with fees above the liquidation threshold",
+                long: false,
+                fees_collateral: Int128::new(1_000),
+                expected_result: Ok("99".parse::<Decimal>().unwrap()),
+            },
+        ];
+
+        for test in test_cases {
+            // 1000 collateral at 10x opened at 100
+            let result = get_trade_liquidation_price(
+                "100".parse::<Decimal>().unwrap(),
+                test.long,
+                Uint128::new(1_000),
+                Uint128::new(10),
+                test.fees_collateral,
+            );
+            assert_eq!(
+                result, test.expected_result,
+                "Failed test: {}",
+                test.description
+            );
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Decimal, Uint128};
+use cosmwasm_std::{Addr, Decimal, SignedDecimal, Uint128};
 use cw_storage_plus::Map;
 
 pub const PAIRS: Map<(u64, u64), BorrowingData> = Map::new("borrowing_data");
@@ -10,6 +10,8 @@ pub const GROUPS: Map<(u64, u64), BorrowingData> = Map::new("borrowing_data");
 pub const GROUP_OIS: Map<(u64, u64), OpenInterest> = Map::new("group_ois");
 pub const INITIAL_ACC_FEES: Map<(u64, Addr, u64), BorrowingInitialAccFees> =
     Map::new("initial_acc_fees");
+pub const PAIR_FUNDINGS: Map<(u64, u64), FundingData> =
+    Map::new("pair_fundings");
 
 #[cw_serde]
 pub struct BorrowingData {
@@ -20,6 +22,17 @@ pub struct BorrowingData {
     pub fee_exponent: u32,
 }
 
+/// Funding paid by the side with the most open interest to the other side,
+/// accumulated per unit of open interest.
+#[cw_serde]
+#[derive(Default)]
+pub struct FundingData {
+    pub fee_per_block: Decimal, // % of the oi skew
+    pub acc_per_oi_long: SignedDecimal,
+    pub acc_per_oi_short: SignedDecimal,
+    pub acc_last_updated_block: u64,
+}
+
 #[cw_serde]
 pub struct BorrowingPairGroup {
     pub group_index: u64,
@@ -44,6 +57,8 @@ pub struct BorrowingInitialAccFees {
     pub acc_pair_fee: Decimal,  // %
     pub acc_group_fee: Decimal, // %
     pub block: u64,
+    #[serde(default)]
+    pub acc_funding_fee: SignedDecimal, // per oi
 }
 
 #[cw_serde]
@@ -5,3 +5,5 @@ pub const MAX_PNL_P: Decimal = Decimal::percent(900);
 pub const LIQ_THRESHOLD_P: Decimal = Decimal::percent(90);
 pub const MAX_OPEN_NEGATIVE_PNL_P: Decimal = Decimal::percent(40);
 pub const GOV_PRICE_COLLATERAL_INDEX: u64 = 0;
+pub const DEFAULT_MAX_TRADES_PER_PAIR: u64 = 3;
+pub const DEFAULT_MAX_PENDING_ORDERS: u64 = 5;
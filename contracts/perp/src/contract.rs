@@ -1,27 +1,45 @@
 use std::str::FromStr;
 
 use anyhow::Result;
-use cosmwasm_std::{Addr, Decimal, DepsMut, Env, MessageInfo, Response};
+use cosmwasm_std::{
+    from_json, Addr, BlockInfo, Decimal, DepsMut, Env, MessageInfo, Order,
+    Response, StdResult, Uint128,
+};
 
 use crate::{
-    borrowing::state::{GROUP_OIS, PAIR_OIS},
-    fees::state::{FEE_TIERS, PENDING_GOV_FEES, TRADER_DAILY_INFOS},
+    borrowing::{
+        set_pair_funding_fee_per_block,
+        state::{GROUP_OIS, PAIR_OIS},
+    },
+    events::event_funding_fee_per_block_p_updated,
+    fees::{
+        claim_gov_fees,
+        state::{
+            FEE_TIERS, GROUP_VOLUME_MULTIPLIERS, PENDING_GOV_FEES,
+            TRADER_DAILY_INFOS, VAULT_CLOSING_FEE_P,
+        },
+    },
+    keepers::{register_keeper, state::KEEPER_CONFIG, unregister_keeper},
     msgs::AdminExecuteMsg,
     pairs::state::{
         FEES, GROUPS, ORACLE_ADDRESS, PAIRS, PAIR_CUSTOM_MAX_LEVERAGE,
-        STAKING_ADDRESS,
+        PAIR_PRICE_GUARDS, REFERRALS_ADDRESS, STAKING_ADDRESS, TREASURY_ADDRESS,
+        VAULT_ADDRESS,
     },
     trade::{
-        cancel_open_order, close_trade, open_trade, trigger_trade,
-        update_open_order, update_sl, update_tp,
+        assert_collateral_sent, cancel_open_order, close_trade_market,
+        increase_position, open_trade, register_potential_referrer,
+        trigger_trade, trigger_trades, update_open_order, update_sl, update_tp,
+        update_trade_margin,
     },
+    trading::state::{migrate_trades, save_trade_at, Trade, COLLATERALS},
 };
 
 use cw2::set_contract_version;
 
 use crate::{
     error::ContractError,
-    msgs::{ExecuteMsg, InstantiateMsg},
+    msgs::{ExecuteMsg, InstantiateMsg, MigrateMsg},
 };
 
 pub const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
@@ -47,10 +65,62 @@ pub fn instantiate(
     if let Some(staking_address) = msg.staking_address {
         STAKING_ADDRESS.save(deps.storage, &Addr::unchecked(staking_address))?;
     }
+    if let Some(vault_address) = msg.vault_address {
+        VAULT_ADDRESS.save(deps.storage, &Addr::unchecked(vault_address))?;
+    }
+    if let Some(treasury_address) = msg.treasury_address {
+        TREASURY_ADDRESS
+            .save(deps.storage, &Addr::unchecked(treasury_address))?;
+    }
+    if let Some(referrals_address) = msg.referrals_address {
+        REFERRALS_ADDRESS
+            .save(deps.storage, &Addr::unchecked(referrals_address))?;
+    }
 
     Ok(Response::default())
 }
 
+/// Pending gov fees used to be stored in the "fees" namespace of the pair
+/// fees, under the collateral index. The balances found there are moved to
+/// `PENDING_GOV_FEES`, the pair fees they overwrote have to be set again.
+/// The trades stored under their pair index are moved under their trade
+/// index, and the trade counters and the open trades and pending orders
+/// indices are rebuilt from the stored trades.
+#[cfg_attr(not(feature = "library"), cosmwasm_std::entry_point)]
+pub fn migrate(
+    deps: DepsMut,
+    _env: Env,
+    _msg: MigrateMsg,
+) -> Result<Response, ContractError> {
+    let indices = FEES
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for index in indices {
+        let Some(value) = deps.storage.get(&FEES.key(index)) else {
+            continue;
+        };
+        if let Ok(amount) = from_json::<Uint128>(value) {
+            PENDING_GOV_FEES.update(
+                deps.storage,
+                index,
+                |pending| -> StdResult<_> {
+                    Ok(pending.unwrap_or_default().checked_add(amount)?)
+                },
+            )?;
+            FEES.remove(deps.storage, index);
+        }
+    }
+
+    migrate_trades(deps.storage)?;
+
+    set_contract_version(
+        deps.storage,
+        format!("crates.io:{CONTRACT_NAME}"),
+        CONTRACT_VERSION,
+    )?;
+    Ok(Response::new().add_attribute("method", "migrate"))
+}
+
 #[cfg_attr(not(feature = "library"), cosmwasm_std::entry_point)]
 pub fn execute(
     mut deps: DepsMut,
@@ -63,17 +133,56 @@ pub fn execute(
             trade,
             order_type,
             slippage_p,
-            referral: _,
-        } => open_trade(
+            referral,
+        } => {
+            // the trade is opened for the sender with the collateral it sent
+            let trade = Trade {
+                user: info.sender.clone(),
+                ..trade
+            };
+            assert_collateral_sent(
+                &info,
+                &COLLATERALS.load(deps.storage, trade.collateral_index)?,
+                trade.collateral_amount,
+            )?;
+            let (referrer, referral_msg) = register_potential_referrer(
+                &deps.as_ref(),
+                &trade.user,
+                &referral,
+            )?;
+            Ok(open_trade(
+                &mut deps,
+                &env.block,
+                trade,
+                order_type,
+                Decimal::from_str(slippage_p.as_str())?,
+                referrer.as_ref(),
+            )?
+            .add_messages(referral_msg))
+        }
+        ExecuteMsg::CloseTradeMarket {
+            index,
+            collateral_delta,
+        } => close_trade_market(
             &mut deps,
             &env.block,
-            trade,
-            order_type,
-            Decimal::from_str(slippage_p.as_str())?,
+            info,
+            index,
+            collateral_delta,
         ),
-        ExecuteMsg::CloseTradeMarket { index } => {
-            close_trade(&mut deps, &env.block, info, index)
+        ExecuteMsg::IncreasePosition {
+            index,
+            collateral,
+            leverage,
+        } => {
+            increase_position(&mut deps, &env, info, index, collateral, leverage)
         }
+        ExecuteMsg::DepositMargin { index, leverage } => update_trade_margin(
+            &mut deps, &env.block, info, index, leverage, true,
+        ),
+        ExecuteMsg::WithdrawMargin { index, leverage } => update_trade_margin(
+            &mut deps, &env.block, info, index, leverage, false,
+        ),
         ExecuteMsg::UpdateOpenLimitOrder {
             index,
             price,
@@ -99,9 +208,15 @@ pub fn execute(
         } => {
             trigger_trade(&mut deps, &env.block, trader, info, index, order_type)
         }
+        ExecuteMsg::TriggerTrades { orders } => {
+            trigger_trades(&mut deps, &env.block, info, orders)
+        }
+        ExecuteMsg::ClaimGovFees {} => claim_gov_fees(&mut deps, info),
+        ExecuteMsg::RegisterKeeper {} => register_keeper(&mut deps, info),
+        ExecuteMsg::UnregisterKeeper {} => unregister_keeper(&mut deps, info),
         ExecuteMsg::AdminMsg { msg } => {
             nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
-            execute_admin(&mut deps, msg)
+            execute_admin(&mut deps, &env.block, msg)
         }
     }
 }
@@ -109,6 +224,7 @@ pub fn execute(
 // todo: add event to each responses
 pub(crate) fn execute_admin(
     deps: &mut DepsMut,
+    block: &BlockInfo,
     msg: AdminExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
@@ -142,6 +258,15 @@ pub(crate) fn execute_admin(
             }
             Ok(Response::new())
         }
+        AdminExecuteMsg::SetPairPriceGuards { pair_price_guards } => {
+            for (index, price_guard) in pair_price_guards.iter() {
+                if price_guard.max_deviation_p > Decimal::one() {
+                    return Err(ContractError::InvalidPercentage);
+                }
+                PAIR_PRICE_GUARDS.save(deps.storage, *index, price_guard)?;
+            }
+            Ok(Response::new())
+        }
         AdminExecuteMsg::UpdateOracleAddress { oracle_address } => {
             ORACLE_ADDRESS
                 .save(deps.storage, &Addr::unchecked(oracle_address))?;
@@ -152,10 +277,45 @@ pub(crate) fn execute_admin(
                 .save(deps.storage, &Addr::unchecked(staking_address))?;
             Ok(Response::new())
         }
+        AdminExecuteMsg::UpdateVaultAddress { vault_address } => {
+            VAULT_ADDRESS.save(deps.storage, &Addr::unchecked(vault_address))?;
+            Ok(Response::new())
+        }
+        AdminExecuteMsg::UpdateTreasuryAddress { treasury_address } => {
+            TREASURY_ADDRESS
+                .save(deps.storage, &Addr::unchecked(treasury_address))?;
+            Ok(Response::new())
+        }
+        AdminExecuteMsg::UpdateReferralsAddress { referrals_address } => {
+            REFERRALS_ADDRESS
+                .save(deps.storage, &Addr::unchecked(referrals_address))?;
+            Ok(Response::new())
+        }
+        AdminExecuteMsg::UpdateVaultClosingFeeP {
+            vault_closing_fee_p,
+        } => {
+            if vault_closing_fee_p > Decimal::one() {
+                return Err(ContractError::InvalidPercentage);
+            }
+            VAULT_CLOSING_FEE_P.save(deps.storage, &vault_closing_fee_p)?;
+            Ok(Response::new())
+        }
         AdminExecuteMsg::UpdateFeeTiers { fee_tiers } => {
             FEE_TIERS.save(deps.storage, &fee_tiers)?;
             Ok(Response::new())
         }
+        AdminExecuteMsg::UpdateGroupVolumeMultipliers {
+            group_volume_multipliers,
+        } => {
+            for (index, multiplier) in group_volume_multipliers.iter() {
+                GROUP_VOLUME_MULTIPLIERS.save(
+                    deps.storage,
+                    *index,
+                    multiplier,
+                )?;
+            }
+            Ok(Response::new())
+        }
         AdminExecuteMsg::UpdatePendingGovFees { pending_gov_fees } => {
             for (index, fee) in pending_gov_fees.iter() {
                 PENDING_GOV_FEES.save(deps.storage, *index, fee)?;
@@ -210,6 +370,25 @@ pub(crate) fn execute_admin(
             }
             Ok(Response::new())
         }
+        AdminExecuteMsg::UpdatePairFundingFees { pair_funding_fees } => {
+            let mut events = vec![];
+            for ((collateral_index, pair_index), fee_per_block) in
+                pair_funding_fees
+            {
+                set_pair_funding_fee_per_block(
+                    deps.storage,
+                    collateral_index,
+                    pair_index,
+                    block.height,
+                    fee_per_block,
+                )?;
+                events.push(event_funding_fee_per_block_p_updated(
+                    &pair_index,
+                    &fee_per_block.atomics().u128(),
+                ));
+            }
+            Ok(Response::new().add_events(events))
+        }
         AdminExecuteMsg::UpdateOiWindowsSettings {
             oi_windows_settings,
         } => {
@@ -249,11 +428,7 @@ pub(crate) fn execute_admin(
         }
         AdminExecuteMsg::UpdateTrades { trades } => {
             for (index, trade) in trades.iter() {
-                crate::trading::state::TRADES.save(
-                    deps.storage,
-                    index.clone(),
-                    trade,
-                )?;
+                save_trade_at(deps.storage, index.clone(), trade)?;
             }
             Ok(Response::new())
         }
@@ -282,5 +457,183 @@ pub(crate) fn execute_admin(
                 .save(deps.storage, &trading_activated)?;
             Ok(Response::new())
         }
+        AdminExecuteMsg::UpdateMaxTradesPerPair {
+            max_trades_per_pair,
+        } => {
+            crate::trading::state::MAX_TRADES_PER_PAIR
+                .save(deps.storage, &max_trades_per_pair)?;
+            Ok(Response::new())
+        }
+        AdminExecuteMsg::UpdateInsuranceFundFeeP {
+            insurance_fund_fee_p,
+        } => {
+            if insurance_fund_fee_p > Decimal::one() {
+                return Err(ContractError::InvalidPercentage);
+            }
+            crate::fees::state::INSURANCE_FUND_FEE_P
+                .save(deps.storage, &insurance_fund_fee_p)?;
+            Ok(Response::new())
+        }
+        AdminExecuteMsg::UpdateMaxPendingOrders { max_pending_orders } => {
+            crate::trading::state::MAX_PENDING_ORDERS
+                .save(deps.storage, &max_pending_orders)?;
+            Ok(Response::new())
+        }
+        AdminExecuteMsg::UpdateKeeperConfig { keeper_config } => {
+            if keeper_config.reward_p > Decimal::one() {
+                return Err(ContractError::InvalidPercentage);
+            }
+            KEEPER_CONFIG.save(deps.storage, &keeper_config)?;
+            Ok(Response::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::borrowing::state::{BorrowingInitialAccFees, INITIAL_ACC_FEES};
+    use crate::test_utils::{default_trade, mock_deps, setup_market};
+    use crate::trading::state::{
+        TradeInfo, TradeType, TraderPairCounts, OPEN_TRADES, PENDING_ORDERS,
+        TRADER_PAIR_COUNTS, TRADES, TRADE_INFOS, USER_COUNTERS,
+    };
+    use cosmwasm_std::{testing::mock_env, to_json_vec, SignedDecimal};
+
+    #[test]
+    fn test_migrate_moves_pending_gov_fees() {
+        let mut deps = mock_deps(Decimal::one());
+        setup_market(&mut deps.as_mut());
+        // a pending gov fee balance stored over the fee of index 1
+        deps.as_mut()
+            .storage
+            .set(&FEES.key(1), &to_json_vec(&Uint128::new(500)).unwrap());
+        PENDING_GOV_FEES
+            .save(deps.as_mut().storage, 1, &Uint128::new(100))
+            .unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        assert_eq!(
+            PENDING_GOV_FEES.load(deps.as_ref().storage, 1).unwrap(),
+            Uint128::new(600)
+        );
+        assert!(!FEES.has(deps.as_ref().storage, 1));
+        assert!(FEES.has(deps.as_ref().storage, 0));
+    }
+
+    #[test]
+    fn test_migrate_indexes_open_trades() {
+        let mut deps = mock_deps(Decimal::one());
+        setup_market(&mut deps.as_mut());
+        let mut closed = default_trade(0);
+        closed.is_open = false;
+        let mut limit_order = default_trade(1);
+        limit_order.trade_type = TradeType::Limit;
+        let open = default_trade(2);
+        // trades stored before the indices existed
+        for trade in [&closed, &limit_order, &open] {
+            TRADES
+                .save(
+                    deps.as_mut().storage,
+                    (trade.user.clone(), trade.index),
+                    trade,
+                )
+                .unwrap();
+        }
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        let user = open.user;
+        for (index, is_open_trade, is_pending_order) in
+            [(0, false, false), (1, false, true), (2, true, false)]
+        {
+            let key = (user.clone(), index);
+            assert_eq!(
+                OPEN_TRADES.has(deps.as_ref().storage, key.clone()),
+                is_open_trade,
+                "index {index}"
+            );
+            assert_eq!(
+                PENDING_ORDERS.has(deps.as_ref().storage, key),
+                is_pending_order,
+                "index {index}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_migrate_rekeys_legacy_trades() {
+        let mut deps = mock_deps(Decimal::one());
+        setup_market(&mut deps.as_mut());
+        let trade_info = TradeInfo {
+            created_block: 1,
+            tp_last_updated_block: 1,
+            sl_last_updated_block: 1,
+            max_slippage_p: Decimal::percent(1),
+            last_oi_update_ts: mock_env().block.time,
+            collateral_price_usd: Decimal::one(),
+        };
+        // trades used to be stored under their pair index with the counter
+        // of open trades as index, the close of a first trade on pair 0 gave
+        // index 2 to both the pair 1 trade and the next pair 0 trade
+        let pair_0_trade = default_trade(2);
+        let mut pair_1_trade = default_trade(2);
+        pair_1_trade.pair_index = 1;
+        let user = pair_0_trade.user.clone();
+        for trade in [&pair_0_trade, &pair_1_trade] {
+            let key = (user.clone(), trade.pair_index);
+            TRADES
+                .save(deps.as_mut().storage, key.clone(), trade)
+                .unwrap();
+            TRADE_INFOS
+                .save(deps.as_mut().storage, key, &trade_info)
+                .unwrap();
+        }
+        USER_COUNTERS
+            .save(deps.as_mut().storage, user.clone(), &2)
+            .unwrap();
+        INITIAL_ACC_FEES
+            .save(
+                deps.as_mut().storage,
+                (0, user.clone(), 2),
+                &BorrowingInitialAccFees {
+                    acc_pair_fee: Decimal::zero(),
+                    acc_group_fee: Decimal::zero(),
+                    block: 1,
+                    acc_funding_fee: SignedDecimal::zero(),
+                },
+            )
+            .unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        let storage = deps.as_ref().storage;
+        for (index, pair_index) in [(2, 0), (3, 1)] {
+            let key = (user.clone(), index);
+            let trade = TRADES.load(storage, key.clone()).unwrap();
+            assert_eq!(trade.index, index);
+            assert_eq!(trade.pair_index, pair_index);
+            assert_eq!(
+                TRADE_INFOS.load(storage, key.clone()).unwrap(),
+                trade_info
+            );
+            assert!(OPEN_TRADES.has(storage, key));
+            assert!(INITIAL_ACC_FEES.has(storage, (0, user.clone(), index)));
+            assert_eq!(
+                TRADER_PAIR_COUNTS
+                    .load(storage, (user.clone(), pair_index))
+                    .unwrap(),
+                TraderPairCounts {
+                    trades: 1,
+                    pending_orders: 0,
+                }
+            );
+        }
+        for index in [0, 1] {
+            assert!(!TRADES.has(storage, (user.clone(), index)));
+            assert!(!TRADE_INFOS.has(storage, (user.clone(), index)));
+        }
+        assert_eq!(USER_COUNTERS.load(storage, user).unwrap(), 4);
     }
 }
@@ -106,6 +106,42 @@ pub enum ContractError {
     #[error("invalid trigger price")]
     InvalidTriggerPrice,
 
+    #[error("invalid collateral delta")]
+    InvalidCollateralDelta,
+
+    #[error("sent funds do not match the collateral amount")]
+    InvalidFunds,
+
+    #[error("the trade would be past its liquidation price")]
+    LiquidationPriceReached,
+
+    #[error("only the owner or the treasury can claim the gov fees")]
+    Unauthorized,
+
+    #[error("no treasury address set")]
+    TreasuryNotSet,
+
+    #[error("no pending gov fees to claim")]
+    NoPendingGovFees,
+
+    #[error("the keeper is not registered with enough bond")]
+    KeeperNotRegistered,
+
+    #[error("the keeper bond is below the minimum bond")]
+    InsufficientKeeperBond,
+
+    #[error("the oracle price is older than the pair allows")]
+    StalePrice,
+
+    #[error("the last oracle price update moved more than the pair allows")]
+    PriceDeviationTooHigh,
+
+    #[error("percentage must be between 0 and 1")]
+    InvalidPercentage,
+
+    #[error("the vault is undercollateralized, no new trades can be opened")]
+    VaultUndercollateralized,
+
     #[error("invalid conversion")]
     ConversionOverflow,
 
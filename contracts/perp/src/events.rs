@@ -1,10 +1,21 @@
-use cosmwasm_std::Event;
+use cosmwasm_std::{Coin, Event};
 
 pub fn event_toggle_halt(is_halted: &bool) -> Event {
     Event::new("broker_bank/toggle_halt")
         .add_attribute("new_is_halted", is_halted.to_string())
 }
 
+pub fn event_gov_fees_claimed(
+    treasury: &str,
+    collateral_index: &u64,
+    amount: &Coin,
+) -> Event {
+    Event::new("gov_fees_claimed")
+        .add_attribute("treasury", treasury)
+        .add_attribute("collateral_index", collateral_index.to_string())
+        .add_attribute("amount", amount.to_string())
+}
+
 pub fn event_manager_updated(manager: &str) -> Event {
     Event::new("manager_updated").add_attribute("manager", manager)
 }
@@ -100,6 +111,7 @@ pub fn event_fees_charged(
         .add_attribute("funding_fees", funding_fees.to_string())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn event_trade_market_executed(
     order_id: &u64,
     trader: &str,
@@ -123,6 +135,7 @@ pub fn event_trade_market_executed(
         .add_attribute("dai_sent_to_trader", dai_sent_to_trader.to_string())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn event_limit_executed(
     order_id: &u64,
     limit_index: &u64,
@@ -1,21 +1,38 @@
 use crate::{
-    constants::GOV_PRICE_COLLATERAL_INDEX,
     error::ContractError,
-    fees::state::{PENDING_GOV_FEES, VAULT_CLOSING_FEE_P},
+    events::event_gov_fees_claimed,
+    fees::state::{
+        BAD_DEBTS, FEE_TIERS, GROUP_VOLUME_MULTIPLIERS, INSURANCE_FUNDS,
+        INSURANCE_FUND_FEE_P, PENDING_GOV_FEES, TRADER_INFOS,
+        TRAILING_PERIOD_DAYS, VAULT_CLOSING_FEE_P,
+    },
+    keepers::{credit_keeper_reward, get_keeper_reward},
+    msgs::TraderFeeTierResponse,
     pairs::state::{
-        FEES, ORACLE_ADDRESS, PAIRS, STAKING_ADDRESS, VAULT_ADDRESS,
+        FEES, PAIRS, REFERRALS_ADDRESS, STAKING_ADDRESS, TREASURY_ADDRESS,
+        VAULT_ADDRESS,
+    },
+    trading::state::{PendingOrderType, Trade, COLLATERALS},
+    trading::utils::{
+        get_guarded_collateral_price, get_position_size_collateral_basis,
     },
-    trade::get_token_price,
-    trading::state::{OpenOrderType, PendingOrderType, Trade, COLLATERALS},
-    trading::utils::get_position_size_collateral_basis,
     utils::u128_to_dec,
 };
 
 use cosmwasm_std::{
-    Addr, BankMsg, BlockInfo, Coin, Decimal, Deps, DepsMut, Timestamp, Uint128,
+    to_json_binary, Addr, BankMsg, BlockInfo, Coin, Coins, CosmosMsg, Decimal,
+    Deps, DepsMut, MessageInfo, Order, Response, StdResult, Storage, Timestamp,
+    Uint128, WasmMsg,
 };
 
-use state::{TraderDailyInfo, TRADER_DAILY_INFOS};
+use cw_storage_plus::Bound;
+use referrals::{
+    contract::ReferralsExecuteMsg,
+    query::ReferralsQueryMsg,
+    state::{Tier, BASIS_POINTS},
+};
+use state::{TraderDailyInfo, TraderInfo, TRADER_DAILY_INFOS};
+use vault::contract::VaultExecuteMsg;
 
 pub mod state;
 
@@ -45,15 +62,135 @@ fn get_current_day(time: Timestamp) -> u64 {
     time.seconds() / 86400
 }
 
+/// Adds the points earned by trading `volume_collateral` on the pair to the
+/// points of the day of the trader. The first trade of the day also caches
+/// the fee multiplier of the day, picked from the trailing points.
+pub(crate) fn update_trader_points(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    trade: &Trade,
+    volume_collateral: Uint128,
+) -> Result<(), ContractError> {
+    let current_day = get_current_day(block.time);
+    let key = (trade.user.to_string(), current_day);
+    let mut trader_daily_info = TRADER_DAILY_INFOS
+        .may_load(deps.storage, key.clone())?
+        .unwrap_or_default();
+
+    if trader_daily_info.fee_multiplier_cache.is_zero() {
+        let trader_info =
+            roll_up_trailing_points(deps.storage, &trade.user, current_day)?;
+        TRADER_INFOS.save(deps.storage, trade.user.to_string(), &trader_info)?;
+        trader_daily_info.fee_multiplier_cache =
+            get_fee_tier_multiplier(deps.storage, trader_info.trailing_points)?;
+    }
+
+    let group_index = PAIRS.load(deps.storage, trade.pair_index)?.group_index;
+    let volume_multiplier = GROUP_VOLUME_MULTIPLIERS
+        .may_load(deps.storage, group_index)?
+        .unwrap_or_default();
+    if !volume_multiplier.is_zero() {
+        let volume_usd = get_guarded_collateral_price(
+            &deps.as_ref(),
+            block,
+            trade.collateral_index,
+            trade.pair_index,
+        )?
+        .checked_mul(u128_to_dec(volume_collateral)?)?;
+        trader_daily_info.points +=
+            volume_usd.checked_mul(volume_multiplier)?.to_uint_floor();
+    }
+
+    TRADER_DAILY_INFOS.save(deps.storage, key, &trader_daily_info)?;
+    Ok(())
+}
+
+/// Sums the points of the trader over the TRAILING_PERIOD_DAYS before
+/// `current_day`, at most once per day.
+fn roll_up_trailing_points(
+    storage: &dyn Storage,
+    trader: &Addr,
+    current_day: u64,
+) -> StdResult<TraderInfo> {
+    if let Some(trader_info) =
+        TRADER_INFOS.may_load(storage, trader.to_string())?
+    {
+        if trader_info.last_day_updated == current_day {
+            return Ok(trader_info);
+        }
+    }
+
+    let trailing_points = TRADER_DAILY_INFOS
+        .prefix(trader.to_string())
+        .range(
+            storage,
+            Some(Bound::inclusive(
+                current_day.saturating_sub(TRAILING_PERIOD_DAYS),
+            )),
+            Some(Bound::exclusive(current_day)),
+            Order::Ascending,
+        )
+        .map(|item| item.map(|(_, daily_info)| daily_info.points))
+        .sum::<StdResult<Uint128>>()?;
+    Ok(TraderInfo {
+        last_day_updated: current_day,
+        trailing_points,
+    })
+}
+
+/// Multiplier of the highest fee tier whose threshold the trailing points
+/// reach, no discount below the first tier. Tiers without multiplier are
+/// unset.
+fn get_fee_tier_multiplier(
+    storage: &dyn Storage,
+    trailing_points: Uint128,
+) -> StdResult<Decimal> {
+    let Some(fee_tiers) = FEE_TIERS.may_load(storage)? else {
+        return Ok(Decimal::one());
+    };
+    Ok(fee_tiers
+        .iter()
+        .rev()
+        .filter(|fee_tier| !fee_tier.fee_multiplier.is_zero())
+        .find(|fee_tier| trailing_points >= fee_tier.points_treshold)
+        .map_or(Decimal::one(), |fee_tier| fee_tier.fee_multiplier))
+}
+
+pub fn query_trader_fee_tier(
+    deps: &Deps,
+    block: &BlockInfo,
+    trader: &Addr,
+) -> Result<TraderFeeTierResponse, ContractError> {
+    let current_day = get_current_day(block.time);
+    let trader_daily_info = TRADER_DAILY_INFOS
+        .may_load(deps.storage, (trader.to_string(), current_day))?
+        .unwrap_or_default();
+    let trader_info =
+        roll_up_trailing_points(deps.storage, trader, current_day)?;
+
+    let fee_multiplier = if trader_daily_info.fee_multiplier_cache.is_zero() {
+        get_fee_tier_multiplier(deps.storage, trader_info.trailing_points)?
+    } else {
+        trader_daily_info.fee_multiplier_cache
+    };
+    Ok(TraderFeeTierResponse {
+        trailing_points: trader_info.trailing_points,
+        points_today: trader_daily_info.points,
+        fee_multiplier,
+    })
+}
+
+/// Charges the opening fees of the trade. `keeper` is the keeper that
+/// triggered the order, which earns its share of the trigger fee.
 pub(crate) fn process_opening_fees(
     deps: &mut DepsMut,
     block: &BlockInfo,
     trade: Trade,
     position_size_collateral: Uint128,
-    order_type: OpenOrderType,
-) -> Result<(Vec<BankMsg>, Uint128), ContractError> {
-    let gov_price_collateral =
-        get_token_price(&deps.as_ref(), &GOV_PRICE_COLLATERAL_INDEX)?;
+    keeper: Option<&Addr>,
+    referrer: Option<&Addr>,
+) -> Result<(Vec<CosmosMsg>, Uint128), ContractError> {
+    update_trader_points(deps, block, &trade, position_size_collateral)?;
 
     let position_size_collateral = get_position_size_collateral_basis(
         &deps.as_ref(),
@@ -62,12 +199,32 @@ pub(crate) fn process_opening_fees(
         position_size_collateral,
     )?;
 
-    // todo: fee tier points
+    let mut msgs: Vec<CosmosMsg> = vec![];
     let mut total_fees_collateral = Uint128::zero();
-    let reward1 = Uint128::zero();
-    if false {
-        // handle referral fees
-        total_fees_collateral += reward1;
+    let mut reward1 = Uint128::zero();
+    if let Some(referrer) = referrer {
+        // the referral fee comes out of the gov and staking parts of the open
+        // fee, the trader discount is the part of it that is not charged
+        let open_fee_collateral = get_gov_fee_collateral(
+            &deps.as_ref(),
+            block,
+            trade.user.clone(),
+            trade.pair_index,
+            position_size_collateral,
+        )?
+        .checked_mul(2_u64.into())?;
+        let referrer_rebate_collateral;
+        (reward1, referrer_rebate_collateral) =
+            get_referral_fees(&deps.as_ref(), referrer, open_fee_collateral)?;
+
+        msgs.push(distribute_referrer_rebate(
+            deps,
+            referrer,
+            referrer_rebate_collateral,
+            position_size_collateral,
+            &trade,
+        )?);
+        total_fees_collateral += referrer_rebate_collateral;
     }
 
     let gov_fee_collateral: Uint128 = distribute_gov_fee_collateral(
@@ -77,7 +234,6 @@ pub(crate) fn process_opening_fees(
         trade.user.clone(),
         &trade.pair_index,
         position_size_collateral,
-        gov_price_collateral,
         Decimal::from_ratio(reward1, 2_u64).to_uint_floor(),
     )?;
 
@@ -96,34 +252,43 @@ pub(crate) fn process_opening_fees(
     total_fees_collateral +=
         gov_fee_collateral.checked_mul(2_u64.into())? + reward2;
 
-    let mut msgs: Vec<BankMsg> = vec![];
-    let reward3: Uint128;
-    if order_type != OpenOrderType::MARKET {
-        reward3 =
-            Decimal::from_ratio(reward2.checked_mul(2_u64.into())?, 10_u64)
-                .to_uint_floor();
-
-        msgs.push(distribute_trigger_reward(deps, reward3, trade.clone())?);
-    } else {
-        reward3 = Uint128::zero();
+    let mut reward3 = Uint128::zero();
+    if let Some(keeper) = keeper {
+        reward3 = get_keeper_reward(deps.storage, reward2)?;
+        msgs.extend(
+            distribute_trigger_reward(deps, keeper, reward3, &trade)?
+                .map(CosmosMsg::from),
+        );
     }
 
-    msgs.push(distribute_staking_reward(
-        deps,
-        gov_fee_collateral + reward2 - reward3,
-        &trade,
-    )?);
+    msgs.extend(
+        distribute_staking_reward(
+            deps,
+            gov_fee_collateral + reward2 - reward3,
+            &trade,
+        )?
+        .map(CosmosMsg::from),
+    );
 
     Ok((msgs, total_fees_collateral))
 }
 
+/// Charges the closing fees of the trade. The trigger fee of orders triggered
+/// by a `keeper` is shared between the keeper and the stakers.
 pub(crate) fn process_closing_fees(
     deps: &mut DepsMut,
     block: &BlockInfo,
     trade: Trade,
     position_size_collateral: Uint128,
     order_type: PendingOrderType,
-) -> Result<(Vec<BankMsg>, Uint128, Uint128, Uint128, Uint128), ContractError> {
+    keeper: Option<&Addr>,
+) -> Result<(Vec<CosmosMsg>, Uint128, Uint128, Uint128, Uint128), ContractError>
+{
+    // liquidations neither earn points nor get a fee tier discount
+    if order_type != PendingOrderType::LiqClose {
+        update_trader_points(deps, block, &trade, position_size_collateral)?;
+    }
+
     // 1. Calculate closing fees
     let position_size_collateral = get_position_size_collateral_basis(
         &deps.as_ref(),
@@ -155,7 +320,6 @@ pub(crate) fn process_closing_fees(
         closing_fee_collateral
     };
 
-    // todo: fee tier points
     if order_type != PendingOrderType::LiqClose {
         closing_fee_collateral = calculate_fee_amount(
             &deps.as_ref(),
@@ -182,28 +346,73 @@ pub(crate) fn process_closing_fees(
 
     // 4. If trade collateral is enough to pay min fee, distribute closing fees (otherwise charged as negative PnL)
     let mut collateral_left_in_storage = trade.collateral_amount;
-    let mut msgs: Vec<BankMsg> = vec![];
+    let mut msgs: Vec<CosmosMsg> = vec![];
 
-    let total_fees = gov_staking_fee_collateral + vault_closing_fee_collateral;
+    let total_fees = get_total_closing_fees_collateral(
+        vault_closing_fee_collateral,
+        gov_staking_fee_collateral,
+        trigger_fee_collateral,
+        &order_type,
+    );
 
     if collateral_left_in_storage >= total_fees {
-        msgs.push(distribute_vault_reward(
+        // the insurance fund share stays in the contract
+        let insurance_fund_fee_p = INSURANCE_FUND_FEE_P
+            .may_load(deps.storage)?
+            .unwrap_or_default();
+        let vault_insurance_fee_collateral =
+            u128_to_dec(vault_closing_fee_collateral)?
+                .checked_mul(insurance_fund_fee_p)?
+                .to_uint_floor();
+        let gov_insurance_fee_collateral =
+            u128_to_dec(gov_staking_fee_collateral)?
+                .checked_mul(insurance_fund_fee_p)?
+                .to_uint_floor();
+
+        msgs.extend(distribute_vault_reward(
             deps,
-            vault_closing_fee_collateral,
+            vault_closing_fee_collateral - vault_insurance_fee_collateral,
             &trade,
         )?);
-        msgs.push(distribute_staking_reward(
+        msgs.extend(
+            distribute_staking_reward(
+                deps,
+                gov_staking_fee_collateral - gov_insurance_fee_collateral,
+                &trade,
+            )?
+            .map(CosmosMsg::from),
+        );
+        if let Some(message) = fund_insurance(
             deps,
-            gov_staking_fee_collateral,
             &trade,
-        )?);
+            vault_insurance_fee_collateral + gov_insurance_fee_collateral,
+        )? {
+            msgs.push(message);
+        }
 
         if order_type != PendingOrderType::Market {
-            msgs.push(distribute_trigger_reward(
-                deps,
-                trigger_fee_collateral,
-                trade,
-            )?);
+            let mut keeper_reward = Uint128::zero();
+            if let Some(keeper) = keeper {
+                keeper_reward =
+                    get_keeper_reward(deps.storage, trigger_fee_collateral)?;
+                msgs.extend(
+                    distribute_trigger_reward(
+                        deps,
+                        keeper,
+                        keeper_reward,
+                        &trade,
+                    )?
+                    .map(CosmosMsg::from),
+                );
+            }
+            msgs.extend(
+                distribute_staking_reward(
+                    deps,
+                    trigger_fee_collateral - keeper_reward,
+                    &trade,
+                )?
+                .map(CosmosMsg::from),
+            );
         }
 
         collateral_left_in_storage =
@@ -219,53 +428,263 @@ pub(crate) fn process_closing_fees(
     ))
 }
 
-fn distribute_vault_reward(
+/// Adds `amount` to the insurance fund of the trade collateral and covers
+/// the outstanding bad debt with it.
+fn fund_insurance(
+    deps: &mut DepsMut,
+    trade: &Trade,
+    amount: Uint128,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    INSURANCE_FUNDS.update(
+        deps.storage,
+        trade.collateral_index,
+        |fund| -> Result<_, ContractError> {
+            Ok(fund.unwrap_or_default().checked_add(amount)?)
+        },
+    )?;
+    cover_bad_debt(deps, trade)
+}
+
+/// Records the loss a trade could not cover with its collateral and covers
+/// it with the insurance fund as far as the fund allows.
+pub(crate) fn register_bad_debt(
+    deps: &mut DepsMut,
+    trade: &Trade,
+    amount: Uint128,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    BAD_DEBTS.update(
+        deps.storage,
+        trade.collateral_index,
+        |bad_debt| -> Result<_, ContractError> {
+            let mut bad_debt = bad_debt.unwrap_or_default();
+            bad_debt.cumulative = bad_debt.cumulative.checked_add(amount)?;
+            Ok(bad_debt)
+        },
+    )?;
+    cover_bad_debt(deps, trade)
+}
+
+/// Pays the uncovered bad debt to the vault, which absorbed the loss, out of
+/// the insurance fund.
+fn cover_bad_debt(
+    deps: &mut DepsMut,
+    trade: &Trade,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    let mut fund = INSURANCE_FUNDS
+        .may_load(deps.storage, trade.collateral_index)?
+        .unwrap_or_default();
+    let mut bad_debt = BAD_DEBTS
+        .may_load(deps.storage, trade.collateral_index)?
+        .unwrap_or_default();
+
+    let covered = fund.min(bad_debt.cumulative - bad_debt.covered);
+    if covered.is_zero() {
+        return Ok(None);
+    }
+
+    fund -= covered;
+    bad_debt.covered += covered;
+    INSURANCE_FUNDS.save(deps.storage, trade.collateral_index, &fund)?;
+    BAD_DEBTS.save(deps.storage, trade.collateral_index, &bad_debt)?;
+
+    distribute_vault_reward(deps, covered, trade)
+}
+
+/// Sends `reward` to the vault, nothing is sent for a zero reward.
+pub(crate) fn distribute_vault_reward(
     deps: &mut DepsMut,
     reward: Uint128,
     trade: &Trade,
-) -> Result<BankMsg, ContractError> {
-    Ok(BankMsg::Send {
-        to_address: VAULT_ADDRESS.load(deps.storage)?.to_string(),
-        amount: vec![Coin::new(
-            reward,
-            COLLATERALS.load(deps.storage, trade.collateral_index)?,
-        )],
-    })
+) -> Result<Option<CosmosMsg>, ContractError> {
+    send_to_vault(
+        deps.storage,
+        reward,
+        &COLLATERALS.load(deps.storage, trade.collateral_index)?,
+    )
+}
+
+/// Adds `amount` of `denom` to the vault assets, which only counts what the
+/// perp contract sends with `ReceiveAssets`.
+pub(crate) fn send_to_vault(
+    storage: &dyn Storage,
+    amount: Uint128,
+    denom: &str,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    Ok(Some(
+        WasmMsg::Execute {
+            contract_addr: VAULT_ADDRESS.load(storage)?.to_string(),
+            msg: to_json_binary(&VaultExecuteMsg::ReceiveAssets {})?,
+            funds: vec![Coin::new(amount, denom)],
+        }
+        .into(),
+    ))
+}
+
+/// Pays `amount` of `denom` out of the vault assets to `receiver`, nothing
+/// is requested for a zero amount.
+pub(crate) fn send_from_vault(
+    storage: &dyn Storage,
+    amount: Uint128,
+    denom: &str,
+    receiver: &Addr,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    Ok(Some(
+        WasmMsg::Execute {
+            contract_addr: VAULT_ADDRESS.load(storage)?.to_string(),
+            msg: to_json_binary(&VaultExecuteMsg::SendAssets {
+                denom: denom.to_string(),
+                amount,
+                receiver: receiver.to_string(),
+            })?,
+            funds: vec![],
+        }
+        .into(),
+    ))
+}
+
+/// Referrer bound to the trader in the referrals contract, if any.
+pub(crate) fn get_trader_referrer(
+    deps: &Deps,
+    trader: &Addr,
+) -> Result<Option<Addr>, ContractError> {
+    let Some(referrals_address) = REFERRALS_ADDRESS.may_load(deps.storage)?
+    else {
+        return Ok(None);
+    };
+    Ok(deps.querier.query_wasm_smart(
+        referrals_address,
+        &ReferralsQueryMsg::TraderReferrer {
+            account: trader.to_string(),
+        },
+    )?)
+}
+
+/// Part of the open fee going to the referral program according to the tier
+/// of the referrer, and the rebate of the referrer out of it. The rest is the
+/// trader discount.
+fn get_referral_fees(
+    deps: &Deps,
+    referrer: &Addr,
+    open_fee_collateral: Uint128,
+) -> Result<(Uint128, Uint128), ContractError> {
+    let referrals_address = REFERRALS_ADDRESS.load(deps.storage)?;
+    // referrers without a tier get nothing
+    let Some(tier_id) = deps.querier.query_wasm_smart::<Option<Uint128>>(
+        &referrals_address,
+        &ReferralsQueryMsg::ReferrerTier {
+            account: referrer.to_string(),
+        },
+    )?
+    else {
+        return Ok((Uint128::zero(), Uint128::zero()));
+    };
+    let tier: Tier = deps.querier.query_wasm_smart(
+        &referrals_address,
+        &ReferralsQueryMsg::GetTier { tier_id },
+    )?;
+
+    let referral_fee_collateral =
+        open_fee_collateral.multiply_ratio(tier.total_rebate, BASIS_POINTS);
+    let discount_collateral = referral_fee_collateral
+        .multiply_ratio(tier.discount_share, BASIS_POINTS);
+    Ok((
+        referral_fee_collateral,
+        referral_fee_collateral - discount_collateral,
+    ))
+}
+
+/// Credits the rebate to the referrer in the referrals contract, along with
+/// the volume of its referred trader.
+fn distribute_referrer_rebate(
+    deps: &mut DepsMut,
+    referrer: &Addr,
+    rebate: Uint128,
+    volume: Uint128,
+    trade: &Trade,
+) -> Result<CosmosMsg, ContractError> {
+    let denom = COLLATERALS.load(deps.storage, trade.collateral_index)?;
+    let funds = if rebate.is_zero() {
+        vec![]
+    } else {
+        vec![Coin::new(rebate, denom.clone())]
+    };
+    Ok(WasmMsg::Execute {
+        contract_addr: REFERRALS_ADDRESS.load(deps.storage)?.to_string(),
+        msg: to_json_binary(&ReferralsExecuteMsg::CreditRebate {
+            referrer: referrer.to_string(),
+            denom,
+            volume,
+        })?,
+        funds,
+    }
+    .into())
 }
 
 fn distribute_staking_reward(
     deps: &mut DepsMut,
     reward: Uint128,
     trade: &Trade,
-) -> Result<BankMsg, ContractError> {
-    Ok(BankMsg::Send {
+) -> Result<Option<BankMsg>, ContractError> {
+    if reward.is_zero() {
+        return Ok(None);
+    }
+    Ok(Some(BankMsg::Send {
         to_address: STAKING_ADDRESS.load(deps.storage)?.to_string(),
         amount: vec![Coin::new(
             reward,
             COLLATERALS.load(deps.storage, trade.collateral_index)?,
         )],
-    })
+    }))
 }
 
+/// Pays the keeper that triggered the order its reward, in the trade
+/// collateral the fee was charged in.
 fn distribute_trigger_reward(
     deps: &mut DepsMut,
-    trigger_fee_collateral: Uint128,
-    trade: Trade,
-) -> Result<BankMsg, ContractError> {
-    let gov_price_collateral =
-        get_token_price(&deps.as_ref(), &GOV_PRICE_COLLATERAL_INDEX)?;
-    let trigger_fee_gov = gov_price_collateral
-        .checked_div(u128_to_dec(trigger_fee_collateral)?)?
-        .to_uint_floor();
-
-    let message = BankMsg::Send {
-        to_address: ORACLE_ADDRESS.load(deps.storage)?.to_string(),
+    keeper: &Addr,
+    reward: Uint128,
+    trade: &Trade,
+) -> Result<Option<BankMsg>, ContractError> {
+    if reward.is_zero() {
+        return Ok(None);
+    }
+    credit_keeper_reward(deps.storage, keeper, trade.collateral_index, reward)?;
+    Ok(Some(BankMsg::Send {
+        to_address: keeper.to_string(),
         amount: vec![Coin::new(
-            trigger_fee_gov,
+            reward,
             COLLATERALS.load(deps.storage, trade.collateral_index)?,
         )],
-    };
-    Ok(message)
+    }))
+}
+
+/// Sum of the closing fees returned by `process_closing_fees`, to take out of
+/// the trade value. The trigger fee of market orders is already the gov
+/// staking fee.
+pub(crate) fn get_total_closing_fees_collateral(
+    vault_closing_fee_collateral: Uint128,
+    gov_staking_fee_collateral: Uint128,
+    trigger_fee_collateral: Uint128,
+    order_type: &PendingOrderType,
+) -> Uint128 {
+    let total_fees = vault_closing_fee_collateral + gov_staking_fee_collateral;
+    if *order_type == PendingOrderType::Market {
+        total_fees
+    } else {
+        total_fees + trigger_fee_collateral
+    }
 }
 
 fn get_closing_fees_collateral(
@@ -299,7 +718,6 @@ fn distribute_gov_fee_collateral(
     user: Addr,
     pair_index: &u64,
     position_size_collateral: Uint128,
-    gov_price_collateral: Decimal,
     referral_fee_collateral: Uint128,
 ) -> Result<Uint128, ContractError> {
     let gov_fee_collateral = get_gov_fee_collateral(
@@ -308,7 +726,6 @@ fn distribute_gov_fee_collateral(
         user.clone(),
         *pair_index,
         position_size_collateral,
-        gov_price_collateral,
     )? - referral_fee_collateral;
 
     distribute_exact_gov_fee_collateral(
@@ -325,7 +742,6 @@ fn get_gov_fee_collateral(
     user: Addr,
     pair_index: u64,
     position_size_collateral: Uint128,
-    _gov_price_collateral: Decimal,
 ) -> Result<Uint128, ContractError> {
     let pair = PAIRS.load(deps.storage, pair_index)?;
     let fee = FEES.load(deps.storage, pair.fee_index)?;
@@ -346,11 +762,68 @@ fn distribute_exact_gov_fee_collateral(
     _user: Addr,
     gov_fee_collateral: Uint128,
 ) -> Result<Uint128, ContractError> {
-    let mut pending_gov_fees =
-        PENDING_GOV_FEES.load(deps.as_ref().storage, collateral_index)?;
+    let mut pending_gov_fees = PENDING_GOV_FEES
+        .may_load(deps.storage, collateral_index)?
+        .unwrap_or_default();
     pending_gov_fees += gov_fee_collateral;
     PENDING_GOV_FEES.save(deps.storage, collateral_index, &pending_gov_fees)?;
-    Ok(pending_gov_fees)
+    Ok(gov_fee_collateral)
+}
+
+/// Sends the pending gov fees of every collateral to the treasury and resets
+/// them.
+pub(crate) fn claim_gov_fees(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let treasury = TREASURY_ADDRESS
+        .may_load(deps.storage)?
+        .ok_or(ContractError::TreasuryNotSet)?;
+    if info.sender != treasury
+        && nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())
+            .is_err()
+    {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let pending_gov_fees = PENDING_GOV_FEES
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    // collaterals can share a denom, which a bank send takes once
+    let mut amount = Coins::default();
+    let mut events = vec![];
+    for (collateral_index, pending) in pending_gov_fees {
+        if pending.is_zero() {
+            continue;
+        }
+        PENDING_GOV_FEES.save(
+            deps.storage,
+            collateral_index,
+            &Uint128::zero(),
+        )?;
+
+        let coin = Coin::new(
+            pending,
+            COLLATERALS.load(deps.storage, collateral_index)?,
+        );
+        events.push(event_gov_fees_claimed(
+            treasury.as_str(),
+            &collateral_index,
+            &coin,
+        ));
+        amount.add(coin)?;
+    }
+    if amount.is_empty() {
+        return Err(ContractError::NoPendingGovFees);
+    }
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: treasury.to_string(),
+            amount: amount.into_vec(),
+        })
+        .add_events(events))
 }
 
 fn pair_trigger_order_fee(
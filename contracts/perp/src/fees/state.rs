@@ -3,17 +3,27 @@ use cosmwasm_std::{Decimal, Uint128};
 use cw_storage_plus::{Item, Map};
 
 pub const FEE_TIERS: Item<[FeeTier; 8]> = Item::new("fee_tiers");
-pub const PENDING_GOV_FEES: Map<u64, Uint128> = Map::new("fees");
+pub const PENDING_GOV_FEES: Map<u64, Uint128> = Map::new("pending_gov_fees");
 pub const VAULT_CLOSING_FEE_P: Item<Decimal> = Item::new("vault_closing_fee_p");
+/// Share of the closing and liquidation fees kept in the insurance fund.
+pub const INSURANCE_FUND_FEE_P: Item<Decimal> =
+    Item::new("insurance_fund_fee_p");
+// collateral index -> insurance fund balance
+pub const INSURANCE_FUNDS: Map<u64, Uint128> = Map::new("insurance_funds");
+// collateral index -> BadDebt
+pub const BAD_DEBTS: Map<u64, BadDebt> = Map::new("bad_debts");
 // trader -> day -> TraderDailyInfo
 pub const TRADER_DAILY_INFOS: Map<(String, u64), TraderDailyInfo> =
     Map::new("trader_daily_infos");
 
-// todo: why not used
-// pub const TRADER_INFOS: Map<(u64, String), TraderInfo> =
-// Map::new("trader_infos");
-// pub const GROUP_VOLUME_MULTIPLIERS: Map<u64, Decimal> =
-//     Map::new("group_volume_multipliers");
+// trader -> TraderInfo
+pub const TRADER_INFOS: Map<String, TraderInfo> = Map::new("trader_infos");
+// group index -> points earned per usd of volume
+pub const GROUP_VOLUME_MULTIPLIERS: Map<u64, Decimal> =
+    Map::new("group_volume_multipliers");
+
+/// Days of points summed up to pick the fee tier of a trader.
+pub const TRAILING_PERIOD_DAYS: u64 = 30;
 
 #[cw_serde]
 pub struct FeeTier {
@@ -22,8 +32,20 @@ pub struct FeeTier {
 }
 
 #[cw_serde]
+#[derive(Default)]
+pub struct BadDebt {
+    /// Losses trades could not cover with their collateral.
+    pub cumulative: Uint128,
+    /// Part of the cumulative bad debt paid back by the insurance fund.
+    pub covered: Uint128,
+}
+
+#[cw_serde]
+#[derive(Default)]
 pub struct TraderInfo {
-    pub last_day_updated: Uint128,
+    /// Day the trailing points were last rolled up.
+    pub last_day_updated: u64,
+    /// Points earned over the TRAILING_PERIOD_DAYS before `last_day_updated`.
     pub trailing_points: Uint128,
 }
 
@@ -0,0 +1,158 @@
+use cosmwasm_std::{
+    Addr, BankMsg, BlockInfo, Coin, Deps, DepsMut, MessageInfo, Order, Response,
+    StdResult, Storage, Uint128,
+};
+
+use crate::{
+    error::ContractError, msgs::KeeperResponse,
+    trading::state::PendingOrderType, utils::u128_to_dec,
+};
+use state::{KEEPERS, KEEPER_CONFIG, KEEPER_REWARDS};
+
+pub mod state;
+
+/// Registers the sender as a keeper, bonding the funds sent. Registered
+/// keepers can top up their bond the same way, as long as it is still in the
+/// bond denom of the config.
+pub(crate) fn register_keeper(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config = KEEPER_CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    if info
+        .funds
+        .iter()
+        .any(|coin| coin.denom != config.bond_denom)
+    {
+        return Err(ContractError::InvalidFunds);
+    }
+    let bonded: Uint128 = info.funds.iter().map(|coin| coin.amount).sum();
+
+    let mut keeper = KEEPERS
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+    if !keeper.bond.is_zero() && keeper.bond_denom != config.bond_denom {
+        return Err(ContractError::InvalidFunds);
+    }
+    keeper.bond = keeper.bond.checked_add(bonded)?;
+    keeper.bond_denom = config.bond_denom;
+    if keeper.bond < config.min_bond {
+        return Err(ContractError::InsufficientKeeperBond);
+    }
+    keeper.registered = true;
+    KEEPERS.save(deps.storage, info.sender.clone(), &keeper)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_keeper")
+        .add_attribute("keeper", info.sender.to_string())
+        .add_attribute("bond", keeper.bond.to_string()))
+}
+
+/// Unregisters the sender and sends its bond back. Its stats are kept.
+pub(crate) fn unregister_keeper(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut keeper = KEEPERS
+        .may_load(deps.storage, info.sender.clone())?
+        .filter(|keeper| keeper.registered)
+        .ok_or(ContractError::KeeperNotRegistered)?;
+    let bond = Coin::new(keeper.bond, keeper.bond_denom);
+    keeper.registered = false;
+    keeper.bond = Uint128::zero();
+    keeper.bond_denom = String::new();
+    KEEPERS.save(deps.storage, info.sender.clone(), &keeper)?;
+
+    let mut response = Response::new()
+        .add_attribute("action", "unregister_keeper")
+        .add_attribute("keeper", info.sender.to_string());
+    if !bond.amount.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![bond],
+        });
+    }
+    Ok(response)
+}
+
+/// Refuses keepers that are not registered with enough bond in the bond
+/// denom when the registration is required.
+pub(crate) fn check_keeper(
+    deps: &Deps,
+    keeper: &Addr,
+) -> Result<(), ContractError> {
+    let config = KEEPER_CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    if !config.registration_required {
+        return Ok(());
+    }
+    match KEEPERS.may_load(deps.storage, keeper.clone())? {
+        Some(keeper)
+            if keeper.registered
+                && keeper.bond_denom == config.bond_denom
+                && keeper.bond >= config.min_bond =>
+        {
+            Ok(())
+        }
+        _ => Err(ContractError::KeeperNotRegistered),
+    }
+}
+
+/// Counts an order triggered by the keeper.
+pub(crate) fn record_trigger(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    keeper: &Addr,
+    order_type: &PendingOrderType,
+) -> StdResult<()> {
+    let mut stats = KEEPERS
+        .may_load(storage, keeper.clone())?
+        .unwrap_or_default();
+    stats.triggered_orders += 1;
+    if *order_type == PendingOrderType::LiqClose {
+        stats.liquidations += 1;
+    }
+    stats.last_trigger_block = block.height;
+    KEEPERS.save(storage, keeper.clone(), &stats)
+}
+
+/// Part of the trigger fee paid to the keeper.
+pub(crate) fn get_keeper_reward(
+    storage: &dyn Storage,
+    trigger_fee_collateral: Uint128,
+) -> Result<Uint128, ContractError> {
+    let config = KEEPER_CONFIG.may_load(storage)?.unwrap_or_default();
+    Ok(u128_to_dec(trigger_fee_collateral)?
+        .checked_mul(config.reward_p)?
+        .to_uint_floor())
+}
+
+/// Adds the reward paid to the keeper to its earnings in the collateral.
+pub(crate) fn credit_keeper_reward(
+    storage: &mut dyn Storage,
+    keeper: &Addr,
+    collateral_index: u64,
+    reward: Uint128,
+) -> Result<(), ContractError> {
+    KEEPER_REWARDS.update(
+        storage,
+        (keeper.clone(), collateral_index),
+        |total| -> Result<_, ContractError> {
+            Ok(total.unwrap_or_default().checked_add(reward)?)
+        },
+    )?;
+    Ok(())
+}
+
+pub fn query_keeper(
+    deps: &Deps,
+    keeper: Addr,
+) -> Result<KeeperResponse, ContractError> {
+    let rewards = KEEPER_REWARDS
+        .prefix(keeper.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(KeeperResponse {
+        keeper: KEEPERS.may_load(deps.storage, keeper)?.unwrap_or_default(),
+        rewards,
+    })
+}
@@ -0,0 +1,46 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw_storage_plus::{Item, Map};
+
+pub const KEEPER_CONFIG: Item<KeeperConfig> = Item::new("keeper_config");
+// keeper -> Keeper
+pub const KEEPERS: Map<Addr, Keeper> = Map::new("keepers");
+// (keeper, collateral index) -> trigger rewards earned
+pub const KEEPER_REWARDS: Map<(Addr, u64), Uint128> = Map::new("keeper_rewards");
+
+#[cw_serde]
+pub struct KeeperConfig {
+    /// Share of the trigger fee paid to the keeper triggering the order, the
+    /// rest goes to the stakers.
+    pub reward_p: Decimal,
+    /// When set, only registered keepers bonding at least `min_bond` can
+    /// trigger orders.
+    pub registration_required: bool,
+    pub bond_denom: String,
+    pub min_bond: Uint128,
+}
+
+impl Default for KeeperConfig {
+    fn default() -> Self {
+        Self {
+            reward_p: Decimal::percent(20),
+            registration_required: false,
+            bond_denom: String::new(),
+            min_bond: Uint128::zero(),
+        }
+    }
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Keeper {
+    pub registered: bool,
+    pub bond: Uint128,
+    /// Denom the bond was made in, it is sent back in it even if the config
+    /// changed since.
+    pub bond_denom: String,
+    /// Orders triggered, liquidations included.
+    pub triggered_orders: u64,
+    pub liquidations: u64,
+    pub last_trigger_block: u64,
+}
@@ -1,14 +1,19 @@
 pub mod borrowing;
 pub mod constants;
 pub mod fees;
+pub mod keepers;
 pub mod msgs;
 pub mod pairs;
 pub mod price_impact;
 pub mod query;
+pub mod simulate;
 pub mod trade;
 pub mod trading;
 pub mod utils;
 
+#[cfg(test)]
+mod test_utils;
+
 // #[cfg(not(feature = "library"))]
 // When imported with the "library" feature, contract.rs will not be compiled.
 // This prevents errors related to entry the smart contract's entrypoints,
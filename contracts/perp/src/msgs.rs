@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Decimal, Uint128};
+use cosmwasm_std::{Addr, Decimal, Int128, SignedDecimal, Uint128};
 
 use crate::{
     borrowing::state::{BorrowingData, BorrowingPairGroup, OpenInterest},
-    fees::state::{FeeTier, TraderDailyInfo},
-    pairs::state::{Fee, Group, Pair},
+    fees::state::{BadDebt, FeeTier, TraderDailyInfo},
+    keepers::state::{Keeper, KeeperConfig},
+    pairs::state::{Fee, Group, Pair, PriceGuard},
     price_impact::state::{OiWindowsSettings, PairDepth, PairOi},
     trading::state::{
         OpenOrderType, PendingOrderType, Trade, TradeInfo, TradingActivated,
@@ -21,7 +22,12 @@ pub enum ExecuteMsg {
     /// - order_type: The type of order (e.g., legacy, reversal, momentum).
     /// - spread_reduction_id: ID for any spread reduction applicable.
     /// - slippage_p: Slippage percentage for market orders.
-    /// - referral: Referral address for tracking referral rewards.
+    /// - referral: Referral code the trader is bound to on its first trade
+    ///   with one, ignored once the trader has a referrer.
+    ///
+    /// The trade is opened for the sender, whatever `trade.user` says, and
+    /// the message must carry exactly `trade.collateral_amount` of the
+    /// collateral.
     OpenTrade {
         trade: Trade,
         order_type: OpenOrderType,
@@ -32,7 +38,40 @@ pub enum ExecuteMsg {
     /// Closes an open trade for the specified pair index and trade index.
     /// Parameters:
     /// - index: The index of the trade to be closed.
-    CloseTradeMarket { index: u64 },
+    /// - collateral_delta: Collateral to close. The whole trade is closed
+    ///   when omitted, otherwise the remaining position stays open.
+    CloseTradeMarket {
+        index: u64,
+        collateral_delta: Option<Uint128>,
+    },
+
+    /// Adds size to an open trade. The trade open price becomes the size
+    /// weighted average of the current and the added position.
+    /// Parameters:
+    /// - index: The index of the trade to increase.
+    /// - collateral: Collateral added to the trade, sent along with the message.
+    /// - leverage: Leverage applied to the added collateral.
+    IncreasePosition {
+        index: u64,
+        collateral: Uint128,
+        leverage: Uint128,
+    },
+
+    /// Deposits collateral into an open trade, lowering its leverage while
+    /// keeping the position size.
+    /// Parameters:
+    /// - index: The index of the trade.
+    /// - leverage: New leverage of the trade. The collateral needed to reach
+    ///   it must be sent along with the message.
+    DepositMargin { index: u64, leverage: Uint128 },
+
+    /// Withdraws collateral from an open trade, raising its leverage while
+    /// keeping the position size.
+    /// Parameters:
+    /// - index: The index of the trade.
+    /// - leverage: New leverage of the trade. The collateral above what it
+    ///   needs is sent back to the trader.
+    WithdrawMargin { index: u64, leverage: Uint128 },
 
     /// Updates the open limit order with new parameters.
     /// Parameters:
@@ -77,12 +116,35 @@ pub enum ExecuteMsg {
         order_type: PendingOrderType,
     },
 
+    /// Triggers a batch of orders, each as with `TriggerTrade`. The orders
+    /// that fail are skipped and reported in the `TriggerTradesResponse`
+    /// data instead of reverting the batch.
+    /// Parameters:
+    /// - orders: The trader, index and pending order type of each order.
+    TriggerTrades {
+        orders: Vec<(Addr, u64, PendingOrderType)>,
+    },
+
+    /// Sends the pending gov fees of every collateral to the treasury.
+    /// Only the owner or the treasury can claim them.
+    ClaimGovFees {},
+
+    /// Registers the sender as a keeper, bonding the funds sent in the
+    /// keeper bond denom. Also tops up the bond of a registered keeper.
+    RegisterKeeper {},
+
+    /// Unregisters the sender as a keeper and sends its bond back.
+    UnregisterKeeper {},
+
     /// Admin executes the specified message.
     /// Parameters:
     /// - msg: The admin message to execute.
     AdminMsg { msg: AdminExecuteMsg },
 }
 
+/// Maps keyed by tuples are sent as lists of `(key, value)` pairs, JSON
+/// objects only take string keys.
+#[allow(clippy::large_enum_variant)]
 #[cw_serde]
 pub enum AdminExecuteMsg {
     // Pairs
@@ -98,39 +160,61 @@ pub enum AdminExecuteMsg {
     SetPairCustomMaxLeverage {
         pair_custom_max_leverage: HashMap<u64, Uint128>,
     },
+    SetPairPriceGuards {
+        pair_price_guards: HashMap<u64, PriceGuard>,
+    },
     UpdateOracleAddress {
         oracle_address: String,
     },
     UpdateStakingAddress {
         staking_address: String,
     },
-
+    UpdateVaultAddress {
+        vault_address: String,
+    },
+    UpdateTreasuryAddress {
+        treasury_address: String,
+    },
+    UpdateReferralsAddress {
+        referrals_address: String,
+    },
+    UpdateVaultClosingFeeP {
+        vault_closing_fee_p: Decimal,
+    },
     // Fees
     UpdateFeeTiers {
         fee_tiers: [FeeTier; 8],
     },
+    UpdateGroupVolumeMultipliers {
+        group_volume_multipliers: HashMap<u64, Decimal>,
+    },
     UpdatePendingGovFees {
         pending_gov_fees: HashMap<u64, Uint128>,
     },
     UpdateTraderDailyInfos {
-        trader_daily_infos: HashMap<(String, u64), TraderDailyInfo>,
+        trader_daily_infos: Vec<((String, u64), TraderDailyInfo)>,
     },
 
     // Borrowing
     UpdateBorrowingPairs {
-        borrowing_pairs: HashMap<(u64, u64), BorrowingData>,
+        borrowing_pairs: Vec<((u64, u64), BorrowingData)>,
     },
     UpdateBorrowingPairGroups {
-        pair_groups: HashMap<(u64, u64), Vec<BorrowingPairGroup>>,
+        pair_groups: Vec<((u64, u64), Vec<BorrowingPairGroup>)>,
     },
     UpdateBorrowingPairOis {
-        pair_ois: HashMap<(u64, u64), OpenInterest>,
+        pair_ois: Vec<((u64, u64), OpenInterest)>,
     },
     UpdateBorrowingGroups {
-        groups: HashMap<(u64, u64), BorrowingData>,
+        groups: Vec<((u64, u64), BorrowingData)>,
     },
     UpdateBorrowingGroupOis {
-        group_ois: HashMap<(u64, u64), OpenInterest>,
+        group_ois: Vec<((u64, u64), OpenInterest)>,
+    },
+    /// Accrues the funding of each pair up to the current block and sets
+    /// its new funding fee per block.
+    UpdatePairFundingFees {
+        pair_funding_fees: Vec<((u64, u64), Decimal)>,
     },
 
     // Price impact
@@ -138,7 +222,7 @@ pub enum AdminExecuteMsg {
         oi_windows_settings: OiWindowsSettings,
     },
     UpdateWindows {
-        windows: HashMap<(u64, u64, u64), PairOi>,
+        windows: Vec<((u64, u64, u64), PairOi)>,
     },
     UpdatePairDepths {
         pair_depths: HashMap<u64, PairDepth>,
@@ -149,10 +233,10 @@ pub enum AdminExecuteMsg {
         collaterals: HashMap<u64, String>,
     },
     UpdateTrades {
-        trades: HashMap<(Addr, u64), Trade>,
+        trades: Vec<((Addr, u64), Trade)>,
     },
     UpdateTradeInfos {
-        trade_infos: HashMap<(Addr, u64), TradeInfo>,
+        trade_infos: Vec<((Addr, u64), TradeInfo)>,
     },
     UpdateTraderStored {
         trader_stored: HashMap<Addr, bool>,
@@ -160,6 +244,20 @@ pub enum AdminExecuteMsg {
     UpdateTradingActivated {
         trading_activated: TradingActivated,
     },
+    UpdateMaxTradesPerPair {
+        max_trades_per_pair: u64,
+    },
+    UpdateMaxPendingOrders {
+        max_pending_orders: u64,
+    },
+    UpdateInsuranceFundFeeP {
+        insurance_fund_fee_p: Decimal,
+    },
+
+    // Keepers
+    UpdateKeeperConfig {
+        keeper_config: KeeperConfig,
+    },
 }
 
 #[cw_serde]
@@ -168,8 +266,14 @@ pub struct InstantiateMsg {
     pub owner: Option<String>,
     pub staking_address: Option<String>,
     pub oracle_address: Option<String>,
+    pub vault_address: Option<String>,
+    pub treasury_address: Option<String>,
+    pub referrals_address: Option<String>,
 }
 
+#[cw_serde]
+pub struct MigrateMsg {}
+
 #[derive(QueryResponses)]
 #[cw_serde]
 pub enum QueryMsg {
@@ -180,6 +284,248 @@ pub enum QueryMsg {
         pair_index: u64,
         index: u64,
     },
+
+    /// Trade returns a trade or pending order of the trader with its info.
+    #[returns(TradeResponse)]
+    Trade { address: String, index: u64 },
+
+    /// OpenTrades returns the open trades of the trader, ordered by index.
+    #[returns(TradesResponse)]
+    OpenTrades {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// PendingOrders returns the open limit and stop orders of the trader,
+    /// ordered by index.
+    #[returns(TradesResponse)]
+    PendingOrders {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// SimulateOpenTrade runs `OpenTrade` without writing state and returns
+    /// the trade it would open or the error it would fail with.
+    #[returns(SimulateOpenTradeResponse)]
+    SimulateOpenTrade {
+        trade: Trade,
+        order_type: OpenOrderType,
+        slippage_p: String,
+    },
+
+    /// SimulateCloseTrade runs `CloseTradeMarket` for the trader without
+    /// writing state and returns the PnL breakdown of the closed collateral.
+    #[returns(SimulateCloseTradeResponse)]
+    SimulateCloseTrade {
+        address: String,
+        index: u64,
+        collateral_delta: Option<Uint128>,
+    },
+
+    /// Config returns the contract addresses and the market wide settings.
+    #[returns(ConfigResponse)]
+    Config {},
+
+    /// Pairs returns the pairs, ordered by pair index.
+    #[returns(Vec<(u64, Pair)>)]
+    Pairs {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Groups returns the pair groups, ordered by group index.
+    #[returns(Vec<(u64, Group)>)]
+    Groups {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Fees returns the pair fees, ordered by fee index.
+    #[returns(Vec<(u64, Fee)>)]
+    Fees {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// PairCustomMaxLeverages returns the pairs max leverage overrides,
+    /// ordered by pair index.
+    #[returns(Vec<(u64, Uint128)>)]
+    PairCustomMaxLeverages {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// PairPriceGuards returns the oracle price guards of the pairs, ordered
+    /// by pair index.
+    #[returns(Vec<(u64, PriceGuard)>)]
+    PairPriceGuards {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Collaterals returns the collateral denoms, ordered by collateral
+    /// index.
+    #[returns(Vec<(u64, String)>)]
+    Collaterals {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// InsuranceFund returns the insurance fund balance of the collateral.
+    #[returns(Uint128)]
+    InsuranceFund { collateral_index: u64 },
+
+    /// BadDebt returns the bad debt realized on the collateral and how much
+    /// of it the insurance fund covered.
+    #[returns(BadDebt)]
+    BadDebt { collateral_index: u64 },
+
+    /// OpenPnl returns the aggregate unrealized PnL of the open trades in the
+    /// collateral denom at the oracle prices, positive when traders are in
+    /// profit. The vault queries it to tell its collateralization.
+    #[returns(Int128)]
+    OpenPnl { denom: String },
+
+    /// TraderFeeTier returns the trailing points of the trader and the fee
+    /// multiplier applied to its fees today.
+    #[returns(TraderFeeTierResponse)]
+    TraderFeeTier { address: String },
+
+    /// PendingGovFees returns the gov fees not yet claimed by the treasury,
+    /// ordered by collateral index.
+    #[returns(Vec<(u64, Uint128)>)]
+    PendingGovFees {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// PairDepths returns the pairs 1% depths, ordered by pair index.
+    #[returns(Vec<(u64, PairDepth)>)]
+    PairDepths {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// KeeperConfig returns the keeper reward share and bonding settings.
+    #[returns(KeeperConfig)]
+    KeeperConfig {},
+
+    /// Keeper returns the registration and stats of a keeper, and the
+    /// trigger rewards it earned per collateral index.
+    #[returns(KeeperResponse)]
+    Keeper { address: String },
+}
+
+#[cw_serde]
+pub struct SimulateOpenTradeResponse {
+    pub quote: Option<OpenTradeQuote>,
+    pub error: Option<String>,
+}
+
+#[cw_serde]
+pub struct OpenTradeQuote {
+    /// The trade as it would be stored.
+    pub trade: Trade,
+    pub market_price: Decimal,
+    /// Open price after spread and price impact.
+    pub execution_price: Decimal,
+    /// Opening fees, including the trader fee tier multiplier.
+    pub opening_fees_collateral: Uint128,
+    /// Collateral left in the trade once the opening fees are paid.
+    pub collateral_amount: Uint128,
+    pub liquidation_price: Decimal,
+}
+
+#[cw_serde]
+pub struct SimulateCloseTradeResponse {
+    pub quote: Option<CloseTradeQuote>,
+    pub error: Option<String>,
+}
+
+#[cw_serde]
+pub struct CloseTradeQuote {
+    pub price: Decimal,
+    pub pnl_p: SignedDecimal,
+    /// Collateral being closed.
+    pub collateral_amount: Uint128,
+    pub vault_closing_fee_collateral: Uint128,
+    pub gov_staking_fee_collateral: Uint128,
+    pub trigger_fee_collateral: Uint128,
+    pub borrowing_fee_collateral: Uint128,
+    /// Negative when the trade receives funding.
+    pub funding_fee_collateral: Int128,
+    /// Collateral value of the closed part after PnL and fees, negative when
+    /// the losses exceed the collateral.
+    pub trade_value_collateral: Int128,
+}
+
+#[cw_serde]
+pub struct ConfigResponse {
+    pub oracle_address: Option<Addr>,
+    pub staking_address: Option<Addr>,
+    pub vault_address: Option<Addr>,
+    pub treasury_address: Option<Addr>,
+    pub referrals_address: Option<Addr>,
+    pub trading_activated: Option<TradingActivated>,
+    pub fee_tiers: Option<[FeeTier; 8]>,
+    pub oi_windows_settings: Option<OiWindowsSettings>,
+    pub vault_closing_fee_p: Option<Decimal>,
+    pub insurance_fund_fee_p: Option<Decimal>,
+    pub max_trades_per_pair: u64,
+    pub max_pending_orders: u64,
+}
+
+#[cw_serde]
+pub struct TraderFeeTierResponse {
+    pub trailing_points: Uint128,
+    pub points_today: Uint128,
+    pub fee_multiplier: Decimal,
+}
+
+/// Data of the `TriggerTrades` response, one result per order in order.
+#[cw_serde]
+pub struct TriggerTradesResponse {
+    pub results: Vec<TriggerTradeResult>,
+}
+
+#[cw_serde]
+pub struct TriggerTradeResult {
+    pub trader: Addr,
+    pub index: u64,
+    /// Why the order was not triggered, unset when it was.
+    pub error: Option<String>,
+}
+
+#[cw_serde]
+pub struct KeeperResponse {
+    pub keeper: Keeper,
+    pub rewards: Vec<(u64, Uint128)>,
+}
+
+#[cw_serde]
+pub struct TradeResponse {
+    pub trade: Trade,
+    pub trade_info: TradeInfo,
+    /// Only set for open trades, pending orders have no live position.
+    pub live: Option<LiveTradeInfo>,
+}
+
+/// Values derived from the current oracle price and borrowing fees.
+#[cw_serde]
+pub struct LiveTradeInfo {
+    pub price: Decimal,
+    pub pnl_p: SignedDecimal,
+    pub borrowing_fee_collateral: Uint128,
+    /// Negative when the trade receives funding.
+    pub funding_fee_collateral: Int128,
+    pub liquidation_price: Decimal,
+}
+
+#[cw_serde]
+pub struct TradesResponse {
+    pub trades: Vec<TradeResponse>,
 }
 
 impl AdminExecuteMsg {
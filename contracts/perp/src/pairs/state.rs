@@ -2,13 +2,15 @@ use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{Addr, Decimal, Uint128};
 use cw_storage_plus::{Item, Map};
 
-use crate::error::ContractError;
+use crate::{error::ContractError, utils::u128_to_dec};
 
 pub const PAIRS: Map<u64, Pair> = Map::new("pairs");
 pub const GROUPS: Map<u64, Group> = Map::new("groups");
 pub const FEES: Map<u64, Fee> = Map::new("fees");
 pub const PAIR_CUSTOM_MAX_LEVERAGE: Map<u64, Uint128> =
     Map::new("pair_custom_max_leverage");
+pub const PAIR_PRICE_GUARDS: Map<u64, PriceGuard> =
+    Map::new("pair_price_guards");
 
 // todo: check why it's not used
 // pub const IS_PAIR_LISTED: Map<String, HashMap<String, bool>> =
@@ -20,6 +22,9 @@ pub const PAIR_CUSTOM_MAX_LEVERAGE: Map<u64, Uint128> =
 pub const ORACLE_ADDRESS: Item<Addr> = Item::new("oracle_address");
 pub const STAKING_ADDRESS: Item<Addr> = Item::new("staking_address");
 pub const VAULT_ADDRESS: Item<Addr> = Item::new("vault_address");
+pub const REFERRALS_ADDRESS: Item<Addr> = Item::new("referrals_address");
+/// Receiver of the gov fees accrued in PENDING_GOV_FEES.
+pub const TREASURY_ADDRESS: Item<Addr> = Item::new("treasury_address");
 
 #[cw_serde]
 pub struct Pair {
@@ -59,10 +64,20 @@ impl Fee {
         let one = Decimal::one();
         let two = one + one;
 
-        Ok((Decimal::from_ratio(self.min_position_size_usd, 0_u64)
-            .checked_mul(
-                self.open_fee_p.checked_mul(two)? + self.trigger_order_fee_p,
-            )?)
+        Ok((u128_to_dec(self.min_position_size_usd)?.checked_mul(
+            self.open_fee_p.checked_mul(two)? + self.trigger_order_fee_p,
+        )?)
         .to_uint_floor())
     }
 }
+
+/// Limits on the oracle prices a pair trades at, a zero value disables the
+/// corresponding check.
+#[cw_serde]
+pub struct PriceGuard {
+    /// Blocks since the last oracle update after which the price is stale.
+    pub max_age_blocks: u64,
+    /// Largest move of the last oracle update, relative to the price it
+    /// replaced.
+    pub max_deviation_p: Decimal,
+}
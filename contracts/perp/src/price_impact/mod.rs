@@ -4,12 +4,17 @@ use cosmwasm_std::{
     Addr, BlockInfo, Decimal, DepsMut, Storage, Timestamp, Uint128,
 };
 
-use state::{OiWindowsSettings, OI_WINDOWS_SETTINGS, PAIR_DEPTHS, WINDOWS};
+use state::{
+    OiWindowsSettings, TradePriceImpactInfo, OI_WINDOWS_SETTINGS, PAIR_DEPTHS,
+    TRADE_PRICE_IMPACT_INFOS, WINDOWS,
+};
 
 use crate::{
     error::ContractError,
-    trade::get_token_price,
-    trading::state::{Trade, TradeInfo, TRADE_INFOS},
+    trading::{
+        state::{Trade, TradeInfo, TRADE_INFOS},
+        utils::get_collateral_price,
+    },
     utils::u128_to_dec,
 };
 
@@ -30,11 +35,7 @@ fn get_earliest_active_window_id(
     current_window_id: u64,
     windows_count: u64,
 ) -> u64 {
-    if current_window_id > windows_count - 1 {
-        current_window_id - (windows_count - 1)
-    } else {
-        0
-    }
+    current_window_id.saturating_sub(windows_count - 1)
 }
 
 fn _get_trade_price_impact(
@@ -83,7 +84,8 @@ fn get_price_impact_oi(
     let mut active_oi = Uint128::zero();
     for window_id in earliest_active_window_id..=current_window_id {
         let windows = WINDOWS
-            .load(storage, (pair_index, window_id, settings.windows_count))?;
+            .may_load(storage, (pair_index, window_id, settings.windows_count))?
+            .unwrap_or_default();
 
         active_oi += if long {
             windows.oi_long_usd
@@ -133,59 +135,61 @@ pub fn add_price_impact_open_interest(
     position_collateral: Uint128,
 ) -> Result<(), ContractError> {
     let oi_window_settings = OI_WINDOWS_SETTINGS.load(deps.storage)?;
+    if oi_window_settings.windows_count == 0 {
+        return Ok(());
+    }
+
     let current_window_id =
         get_current_window_id(&oi_window_settings, block.time);
 
     let current_collateral_price =
-        get_token_price(&deps.as_ref(), &trade.collateral_index)?;
+        get_collateral_price(&deps.as_ref(), &trade.collateral_index)?;
 
     let mut oi_delta_usd = convert_collateral_to_usd(
-        &trade.collateral_index,
-        &trade.pair_index,
         position_collateral,
         current_collateral_price,
     )?;
 
     let is_partial = trade_info.last_oi_update_ts > Timestamp::from_nanos(0);
+    let last_window_id =
+        get_window_id(trade_info.last_oi_update_ts, &oi_window_settings);
 
+    // move the oi still counted in the last updated window to the current one
     if is_partial
-        && (get_window_id(trade_info.last_oi_update_ts, &oi_window_settings)
+        && last_window_id
             >= get_earliest_active_window_id(
                 current_window_id,
                 oi_window_settings.windows_count,
-            ))
+            )
     {
-        let last_window_oi_usd =
-            get_trade_last_window_oi_usd(&trade.user, &trade.pair_index);
-        remove_price_impact_open_interest(
-            deps,
-            block,
-            trade.clone(),
-            Uint128::from(last_window_oi_usd),
+        let last_window_oi_usd = get_trade_last_window_oi_usd(
+            deps.storage,
+            &trade.user,
+            &trade.index,
+        )?;
+        update_window_oi(
+            deps.storage,
+            &trade,
+            (last_window_id, oi_window_settings.windows_count),
+            last_window_oi_usd,
+            false,
         )?;
 
         oi_delta_usd += current_collateral_price
-            .checked_mul(u128_to_dec(last_window_oi_usd.into())?)?
+            .checked_mul(u128_to_dec(last_window_oi_usd)?)?
             .checked_div(trade_info.collateral_price_usd)?
             .to_uint_floor();
     }
 
     // add oi to current window
-    let mut current_window = WINDOWS.load(
+    update_window_oi(
         deps.storage,
-        (
-            trade.pair_index,
-            current_window_id,
-            oi_window_settings.windows_count,
-        ),
+        &trade,
+        (current_window_id, oi_window_settings.windows_count),
+        oi_delta_usd,
+        true,
     )?;
 
-    if trade.long {
-        current_window.oi_long_usd += oi_delta_usd;
-    } else {
-        current_window.oi_short_usd += oi_delta_usd;
-    }
-
     // update trade info
     let mut trade_info = trade_info;
     trade_info.last_oi_update_ts = block.time;
@@ -196,15 +200,12 @@ pub fn add_price_impact_open_interest(
         (trade.user.clone(), trade.index),
         &trade_info,
     )?;
-
-    WINDOWS.save(
+    TRADE_PRICE_IMPACT_INFOS.save(
         deps.storage,
-        (
-            trade.pair_index,
-            current_window_id,
-            oi_window_settings.windows_count,
-        ),
-        &current_window,
+        (trade.user.clone(), trade.index),
+        &TradePriceImpactInfo {
+            last_window_oi_usd: oi_delta_usd,
+        },
     )?;
 
     Ok(())
@@ -222,6 +223,7 @@ pub fn remove_price_impact_open_interest(
     let oi_window_settings = OI_WINDOWS_SETTINGS.load(deps.storage)?;
 
     if oi_delta_collateral.is_zero()
+        || oi_window_settings.windows_count == 0
         || trade_info.last_oi_update_ts == Timestamp::from_nanos(0)
     {
         return Ok(());
@@ -234,59 +236,68 @@ pub fn remove_price_impact_open_interest(
     let not_outdated =
         is_window_potentially_active(add_window_id, current_window_id);
 
-    let mut oi_delta_usd = convert_collateral_to_usd(
-        &trade.collateral_index,
-        &trade.pair_index,
-        oi_delta_collateral,
-        get_token_price(&deps.as_ref(), &trade.collateral_index)?,
-    )?;
-
     if not_outdated {
-        let mut window = WINDOWS.load(
+        let last_window_oi_usd = get_trade_last_window_oi_usd(
             deps.storage,
-            (
-                trade.pair_index,
-                current_window_id,
-                oi_window_settings.windows_count,
-            ),
+            &trade.user,
+            &trade.index,
         )?;
 
-        let last_window_oi_usd =
-            get_trade_last_window_oi_usd(&trade.user, &trade.pair_index);
+        // oi is removed at the collateral price it was added with
+        let oi_delta_usd = Uint128::min(
+            convert_collateral_to_usd(
+                oi_delta_collateral,
+                trade_info.collateral_price_usd,
+            )?,
+            last_window_oi_usd,
+        );
 
-        oi_delta_usd = if oi_delta_usd > Uint128::from(last_window_oi_usd) {
-            Uint128::from(last_window_oi_usd)
-        } else {
-            oi_delta_usd
-        };
-
-        if trade.long {
-            window.oi_long_usd = if oi_delta_usd < window.oi_long_usd {
-                window.oi_long_usd - oi_delta_usd
-            } else {
-                Uint128::zero()
-            };
-        } else {
-            window.oi_short_usd = if oi_delta_usd < window.oi_short_usd {
-                window.oi_short_usd - oi_delta_usd
-            } else {
-                Uint128::zero()
-            };
-        }
-        WINDOWS.save(
+        update_window_oi(
             deps.storage,
-            (
-                trade.pair_index,
-                current_window_id,
-                oi_window_settings.windows_count,
-            ),
-            &window,
+            &trade,
+            (add_window_id, oi_window_settings.windows_count),
+            oi_delta_usd,
+            false,
+        )?;
+        TRADE_PRICE_IMPACT_INFOS.save(
+            deps.storage,
+            (trade.user.clone(), trade.index),
+            &TradePriceImpactInfo {
+                last_window_oi_usd: last_window_oi_usd - oi_delta_usd,
+            },
         )?;
     }
 
     Ok(())
 }
 
+/// Adds or removes `oi_delta_usd` from the trade side of a pair window,
+/// keyed by `(window_id, windows_count)`.
+fn update_window_oi(
+    storage: &mut dyn Storage,
+    trade: &Trade,
+    (window_id, windows_count): (u64, u64),
+    oi_delta_usd: Uint128,
+    increase: bool,
+) -> Result<(), ContractError> {
+    let key = (trade.pair_index, window_id, windows_count);
+    let mut window = WINDOWS.may_load(storage, key)?.unwrap_or_default();
+
+    let oi_usd = if trade.long {
+        &mut window.oi_long_usd
+    } else {
+        &mut window.oi_short_usd
+    };
+    *oi_usd = if increase {
+        oi_usd.checked_add(oi_delta_usd)?
+    } else {
+        oi_usd.saturating_sub(oi_delta_usd)
+    };
+
+    WINDOWS.save(storage, key, &window)?;
+    Ok(())
+}
+
 fn is_window_potentially_active(
     add_window_id: u64,
     current_window_id: u64,
@@ -294,15 +305,22 @@ fn is_window_potentially_active(
     current_window_id - add_window_id < MAX_WINDOW_COUNT
 }
 
-fn get_trade_last_window_oi_usd(_trader: &Addr, _index: &u64) -> u64 {
-    todo!()
+fn get_trade_last_window_oi_usd(
+    storage: &dyn Storage,
+    trader: &Addr,
+    index: &u64,
+) -> Result<Uint128, ContractError> {
+    Ok(TRADE_PRICE_IMPACT_INFOS
+        .may_load(storage, (trader.clone(), *index))?
+        .unwrap_or_default()
+        .last_window_oi_usd)
 }
 
 fn convert_collateral_to_usd(
-    _collateral_index: &u64,
-    _pair_index: &u64,
-    _position_collateral: Uint128,
-    _current_collateral_price: Decimal,
+    position_collateral: Uint128,
+    collateral_price: Decimal,
 ) -> Result<Uint128, ContractError> {
-    todo!()
+    Ok(collateral_price
+        .checked_mul(u128_to_dec(position_collateral)?)?
+        .to_uint_floor())
 }
@@ -6,11 +6,9 @@ pub const OI_WINDOWS_SETTINGS: Item<OiWindowsSettings> =
     Item::new("oi_windows_settings");
 pub const WINDOWS: Map<(u64, u64, u64), PairOi> = Map::new("windows");
 pub const PAIR_DEPTHS: Map<u64, PairDepth> = Map::new("pair_depths");
-
-// todo: check why it's not used
-// pub const TRADE_PRICE_IMPACT_INFOS: Item<
-//     HashMap<(Addr, u32), TradePriceImpactInfo>,
-// > = Item::new("trade_price_impact_infos");
+// (trader, index) -> TradePriceImpactInfo
+pub const TRADE_PRICE_IMPACT_INFOS: Map<(Addr, u64), TradePriceImpactInfo> =
+    Map::new("trade_price_impact_infos");
 
 #[cw_serde]
 pub struct OiWindowsSettings {
@@ -19,6 +17,7 @@ pub struct OiWindowsSettings {
     pub windows_count: u64,
 }
 
+#[derive(Default)]
 #[cw_serde]
 pub struct PairOi {
     pub oi_long_usd: Uint128,
@@ -42,8 +41,8 @@ pub struct PairDepth {
     pub one_percent_depth_below_usd: u128, // USD
 }
 
+#[derive(Default)]
 #[cw_serde]
 pub struct TradePriceImpactInfo {
-    last_window_oi_usd: u128, // 1e18 USD
-    placeholder: u128,
+    pub last_window_oi_usd: Uint128, // USD
 }
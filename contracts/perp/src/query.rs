@@ -1,20 +1,521 @@
-use cosmwasm_std::{Binary, Deps, Env};
+use std::str::FromStr;
 
-use crate::{error::ContractError, msgs::QueryMsg};
+use cosmwasm_std::{
+    to_json_binary, Addr, Binary, Decimal, Deps, Empty, Env, Int128, Order,
+    StdResult,
+};
+use cw_storage_plus::{Bound, Map};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    borrowing::get_trade_liquidation_price_with_fees,
+    constants::{DEFAULT_MAX_PENDING_ORDERS, DEFAULT_MAX_TRADES_PER_PAIR},
+    error::ContractError,
+    fees::{
+        query_trader_fee_tier,
+        state::{
+            BAD_DEBTS, FEE_TIERS, INSURANCE_FUNDS, INSURANCE_FUND_FEE_P,
+            PENDING_GOV_FEES, VAULT_CLOSING_FEE_P,
+        },
+    },
+    keepers::{query_keeper, state::KEEPER_CONFIG},
+    msgs::{
+        ConfigResponse, LiveTradeInfo, QueryMsg, TradeResponse, TradesResponse,
+    },
+    pairs::state::{
+        FEES, GROUPS, ORACLE_ADDRESS, PAIRS, PAIR_CUSTOM_MAX_LEVERAGE,
+        PAIR_PRICE_GUARDS, REFERRALS_ADDRESS, STAKING_ADDRESS, TREASURY_ADDRESS,
+        VAULT_ADDRESS,
+    },
+    price_impact::state::{OI_WINDOWS_SETTINGS, PAIR_DEPTHS},
+    simulate::{simulate_close_trade, simulate_open_trade},
+    trade::get_token_price,
+    trading::{
+        state::{
+            Trade, TradeType, COLLATERALS, MAX_PENDING_ORDERS,
+            MAX_TRADES_PER_PAIR, OPEN_TRADES, PAIR_OPEN_POSITIONS,
+            PENDING_ORDERS, TRADES, TRADE_INFOS, TRADING_ACTIVATED,
+        },
+        utils::get_pnl_percent,
+    },
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 #[cfg_attr(not(feature = "library"), cosmwasm_std::entry_point)]
 pub fn query(
-    _deps: Deps,
-    _env: Env,
+    deps: Deps,
+    env: Env,
     msg: QueryMsg,
 ) -> Result<Binary, ContractError> {
     match msg {
         QueryMsg::HasOpenLimitOrder {
-            address: _String,
-            pair_index: _,
-            index: _u64,
+            address,
+            pair_index,
+            index,
         } => {
-            todo!()
+            let trader = deps.api.addr_validate(&address)?;
+            let has_open_limit_order = TRADES
+                .may_load(deps.storage, (trader, index))?
+                .is_some_and(|trade| {
+                    trade.is_open
+                        && trade.trade_type != TradeType::Trade
+                        && trade.pair_index == pair_index
+                });
+            Ok(to_json_binary(&has_open_limit_order)?)
+        }
+        QueryMsg::Trade { address, index } => {
+            let trader = deps.api.addr_validate(&address)?;
+            let trade = TRADES.load(deps.storage, (trader, index))?;
+            Ok(to_json_binary(&query_trade(deps, &env, trade)?)?)
+        }
+        QueryMsg::OpenTrades {
+            address,
+            start_after,
+            limit,
+        } => Ok(to_json_binary(&query_trades(
+            deps,
+            &env,
+            deps.api.addr_validate(&address)?,
+            start_after,
+            limit,
+            OPEN_TRADES,
+        )?)?),
+        QueryMsg::PendingOrders {
+            address,
+            start_after,
+            limit,
+        } => Ok(to_json_binary(&query_trades(
+            deps,
+            &env,
+            deps.api.addr_validate(&address)?,
+            start_after,
+            limit,
+            PENDING_ORDERS,
+        )?)?),
+        QueryMsg::SimulateOpenTrade {
+            trade,
+            order_type,
+            slippage_p,
+        } => Ok(to_json_binary(&simulate_open_trade(
+            deps,
+            &env.block,
+            trade,
+            order_type,
+            Decimal::from_str(slippage_p.as_str())?,
+        )?)?),
+        QueryMsg::SimulateCloseTrade {
+            address,
+            index,
+            collateral_delta,
+        } => Ok(to_json_binary(&simulate_close_trade(
+            deps,
+            &env.block,
+            deps.api.addr_validate(&address)?,
+            index,
+            collateral_delta,
+        )?)?),
+        QueryMsg::Config {} => Ok(to_json_binary(&ConfigResponse {
+            oracle_address: ORACLE_ADDRESS.may_load(deps.storage)?,
+            staking_address: STAKING_ADDRESS.may_load(deps.storage)?,
+            vault_address: VAULT_ADDRESS.may_load(deps.storage)?,
+            treasury_address: TREASURY_ADDRESS.may_load(deps.storage)?,
+            referrals_address: REFERRALS_ADDRESS.may_load(deps.storage)?,
+            trading_activated: TRADING_ACTIVATED.may_load(deps.storage)?,
+            fee_tiers: FEE_TIERS.may_load(deps.storage)?,
+            oi_windows_settings: OI_WINDOWS_SETTINGS.may_load(deps.storage)?,
+            vault_closing_fee_p: VAULT_CLOSING_FEE_P.may_load(deps.storage)?,
+            insurance_fund_fee_p: INSURANCE_FUND_FEE_P.may_load(deps.storage)?,
+            max_trades_per_pair: MAX_TRADES_PER_PAIR
+                .may_load(deps.storage)?
+                .unwrap_or(DEFAULT_MAX_TRADES_PER_PAIR),
+            max_pending_orders: MAX_PENDING_ORDERS
+                .may_load(deps.storage)?
+                .unwrap_or(DEFAULT_MAX_PENDING_ORDERS),
+        })?),
+        QueryMsg::Pairs { start_after, limit } => {
+            query_map(deps, PAIRS, start_after, limit)
+        }
+        QueryMsg::Groups { start_after, limit } => {
+            query_map(deps, GROUPS, start_after, limit)
+        }
+        QueryMsg::Fees { start_after, limit } => {
+            query_map(deps, FEES, start_after, limit)
+        }
+        QueryMsg::PairCustomMaxLeverages { start_after, limit } => {
+            query_map(deps, PAIR_CUSTOM_MAX_LEVERAGE, start_after, limit)
+        }
+        QueryMsg::PairPriceGuards { start_after, limit } => {
+            query_map(deps, PAIR_PRICE_GUARDS, start_after, limit)
+        }
+        QueryMsg::Collaterals { start_after, limit } => {
+            query_map(deps, COLLATERALS, start_after, limit)
+        }
+        QueryMsg::InsuranceFund { collateral_index } => Ok(to_json_binary(
+            &INSURANCE_FUNDS
+                .may_load(deps.storage, collateral_index)?
+                .unwrap_or_default(),
+        )?),
+        QueryMsg::BadDebt { collateral_index } => Ok(to_json_binary(
+            &BAD_DEBTS
+                .may_load(deps.storage, collateral_index)?
+                .unwrap_or_default(),
+        )?),
+        QueryMsg::OpenPnl { denom } => {
+            Ok(to_json_binary(&query_open_pnl(&deps, &denom)?)?)
+        }
+        QueryMsg::TraderFeeTier { address } => {
+            Ok(to_json_binary(&query_trader_fee_tier(
+                &deps,
+                &env.block,
+                &deps.api.addr_validate(&address)?,
+            )?)?)
+        }
+        QueryMsg::PendingGovFees { start_after, limit } => {
+            query_map(deps, PENDING_GOV_FEES, start_after, limit)
+        }
+        QueryMsg::PairDepths { start_after, limit } => {
+            query_map(deps, PAIR_DEPTHS, start_after, limit)
+        }
+        QueryMsg::KeeperConfig {} => Ok(to_json_binary(
+            &KEEPER_CONFIG.may_load(deps.storage)?.unwrap_or_default(),
+        )?),
+        QueryMsg::Keeper { address } => Ok(to_json_binary(&query_keeper(
+            &deps,
+            deps.api.addr_validate(&address)?,
+        )?)?),
+    }
+}
+
+/// Lists the entries of a config map keyed by index.
+fn query_map<T>(
+    deps: Deps,
+    map: Map<u64, T>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Binary, ContractError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let entries = map
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(to_json_binary(&entries)?)
+}
+
+/// Sums the PnL of the open positions of every pair over the collaterals
+/// of `denom`, at the current oracle prices.
+fn query_open_pnl(deps: &Deps, denom: &str) -> Result<Int128, ContractError> {
+    let collateral_indices = COLLATERALS
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| item.as_ref().map_or(true, |(_, d)| d == denom))
+        .map(|item| item.map(|(index, _)| index))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut open_pnl = Int128::zero();
+    for collateral_index in collateral_indices {
+        let positions = PAIR_OPEN_POSITIONS
+            .prefix(collateral_index)
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (pair_index, positions) in positions {
+            let pair = PAIRS.load(deps.storage, pair_index)?;
+            let price = get_token_price(deps, &pair.oracle_index)?;
+            open_pnl = open_pnl.checked_add(positions.pnl_collateral(price)?)?;
+        }
+    }
+    Ok(open_pnl)
+}
+
+/// Lists the trades of `trader` found in `open_indices`, the open trades or
+/// pending orders, so that only these are read.
+fn query_trades(
+    deps: Deps,
+    env: &Env,
+    trader: Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    open_indices: Map<(Addr, u64), Empty>,
+) -> Result<TradesResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let trades = open_indices
+        .prefix(trader.clone())
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|index| {
+            let trade = TRADES.load(deps.storage, (trader.clone(), index?))?;
+            query_trade(deps, env, trade)
+        })
+        .collect::<Result<Vec<_>, ContractError>>()?;
+
+    Ok(TradesResponse { trades })
+}
+
+fn query_trade(
+    deps: Deps,
+    env: &Env,
+    trade: Trade,
+) -> Result<TradeResponse, ContractError> {
+    let trade_info =
+        TRADE_INFOS.load(deps.storage, (trade.user.clone(), trade.index))?;
+
+    let live = if trade.is_open && trade.trade_type == TradeType::Trade {
+        let pair = PAIRS.load(deps.storage, trade.pair_index)?;
+        let price = get_token_price(&deps, &pair.oracle_index)?;
+        Some(LiveTradeInfo {
+            price,
+            pnl_p: get_pnl_percent(
+                trade.open_price,
+                price,
+                trade.long,
+                trade.leverage,
+            )?,
+            borrowing_fee_collateral: trade
+                .get_trade_borrowing_fees_collateral(&deps, &env.block)?,
+            funding_fee_collateral: trade
+                .get_trade_funding_fees_collateral(&deps, &env.block)?,
+            liquidation_price: get_trade_liquidation_price_with_fees(
+                &deps,
+                &env.block,
+                trade.clone(),
+                true,
+            )?,
+        })
+    } else {
+        None
+    };
+
+    Ok(TradeResponse {
+        trade,
+        trade_info,
+        live,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairs::state::Pair;
+    use crate::test_utils::{
+        default_trade, mock_block, mock_deps, set_oracle_price, setup_market,
+        COLLATERAL_DENOM,
+    };
+    use crate::trade::open_trade;
+    use crate::trading::state::{save_trade, OpenOrderType, TradingActivated};
+    use cosmwasm_std::{
+        from_json, testing::mock_env, Decimal, SignedDecimal, Uint128,
+    };
+
+    #[test]
+    fn test_query_trades_and_pending_orders() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        let trader = deps.api.addr_make("trader");
+
+        let mut limit_order = default_trade(0);
+        limit_order.user = trader.clone();
+        limit_order.trade_type = TradeType::Limit;
+        limit_order.open_price = Decimal::from_ratio(90_u64, 1_u64);
+
+        let mut market_trade = default_trade(0);
+        market_trade.user = trader.clone();
+
+        for trade in [
+            market_trade.clone(),
+            limit_order,
+            market_trade.clone(),
+            market_trade,
+        ] {
+            open_trade(
+                &mut deps.as_mut(),
+                &block,
+                trade,
+                OpenOrderType::MARKET,
+                Decimal::percent(1),
+                None,
+            )
+            .unwrap();
+        }
+        // closed trades are not read and leave no gap in the pages
+        let mut closed = TRADES
+            .load(deps.as_ref().storage, (trader.clone(), 2))
+            .unwrap();
+        closed.is_open = false;
+        save_trade(deps.as_mut().storage, &closed).unwrap();
+        set_oracle_price(&mut deps, Decimal::from_ratio(110_u64, 1_u64));
+
+        let mut env = mock_env();
+        env.block = block;
+
+        let test_cases = vec![
+            ("first page", Some(1), None, vec![0]),
+            ("second page", Some(1), Some(0), vec![3]),
+            ("default limit", None, None, vec![0, 3]),
+        ];
+        for (description, limit, start_after, expected) in test_cases {
+            let resp: TradesResponse = from_json(
+                query(
+                    deps.as_ref(),
+                    env.clone(),
+                    QueryMsg::OpenTrades {
+                        address: trader.to_string(),
+                        start_after,
+                        limit,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            let indices: Vec<u64> =
+                resp.trades.iter().map(|t| t.trade.index).collect();
+            assert_eq!(indices, expected, "Failed test: {}", description);
+        }
+
+        let resp: TradesResponse = from_json(
+            query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::PendingOrders {
+                    address: trader.to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(resp.trades.len(), 1);
+        assert_eq!(resp.trades[0].trade.index, 1);
+        assert_eq!(resp.trades[0].live, None);
+
+        let resp: TradeResponse = from_json(
+            query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Trade {
+                    address: trader.to_string(),
+                    index: 0,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let live = resp.live.unwrap();
+        assert_eq!(live.price, Decimal::from_ratio(110_u64, 1_u64));
+        assert_eq!(live.pnl_p, SignedDecimal::one());
+        assert_eq!(live.borrowing_fee_collateral, Uint128::zero());
+        assert!(live.liquidation_price < Decimal::from_ratio(100_u64, 1_u64));
+
+        for (index, expected) in [(1, true), (0, false)] {
+            let has_open_limit_order: bool = from_json(
+                query(
+                    deps.as_ref(),
+                    env.clone(),
+                    QueryMsg::HasOpenLimitOrder {
+                        address: trader.to_string(),
+                        pair_index: 0,
+                        index,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(has_open_limit_order, expected, "index {}", index);
+        }
+    }
+
+    #[test]
+    fn test_query_config() {
+        let mut deps = mock_deps(Decimal::one());
+        setup_market(&mut deps.as_mut());
+
+        let resp: ConfigResponse = from_json(
+            query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(resp.vault_address, Some(Addr::unchecked("vault")));
+        assert_eq!(resp.trading_activated, Some(TradingActivated::Activated));
+        assert_eq!(resp.fee_tiers, None);
+        assert_eq!(resp.max_trades_per_pair, DEFAULT_MAX_TRADES_PER_PAIR);
+
+        let test_cases = vec![
+            ("first page", None, Some(1), vec![0]),
+            ("second page", Some(0), Some(1), vec![1]),
+            ("default limit", None, None, vec![0, 1]),
+            ("past the last pair", Some(1), None, vec![]),
+        ];
+        for (description, start_after, limit, expected) in test_cases {
+            let pairs: Vec<(u64, Pair)> = from_json(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::Pairs { start_after, limit },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            let indices: Vec<u64> = pairs.iter().map(|(i, _)| *i).collect();
+            assert_eq!(indices, expected, "Failed test: {}", description);
         }
+
+        let collaterals: Vec<(u64, String)> = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Collaterals {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(collaterals, vec![(0, COLLATERAL_DENOM.to_string())]);
+    }
+
+    #[test]
+    fn test_query_open_pnl() {
+        let mut deps = mock_deps(Decimal::from_ratio(110_u64, 1_u64));
+        setup_market(&mut deps.as_mut());
+        let env = mock_env();
+
+        // a 10_000_000 long and a 5_000_000 short opened at 100
+        let long = default_trade(0);
+        let mut short = default_trade(1);
+        short.long = false;
+        short.collateral_amount = Uint128::new(500_000);
+        let mut limit_order = default_trade(2);
+        limit_order.trade_type = TradeType::Limit;
+        for trade in [&long, &short, &limit_order] {
+            save_trade(deps.as_mut().storage, trade).unwrap();
+        }
+        let open_pnl = |deps: &crate::test_utils::MockDeps| -> Int128 {
+            from_json(
+                query(
+                    deps.as_ref(),
+                    env.clone(),
+                    QueryMsg::OpenPnl {
+                        denom: COLLATERAL_DENOM.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(open_pnl(&deps), Int128::new(500_000));
+
+        let mut closed = long.clone();
+        closed.is_open = false;
+        save_trade(deps.as_mut().storage, &closed).unwrap();
+        assert_eq!(open_pnl(&deps), Int128::new(-500_000));
     }
 }
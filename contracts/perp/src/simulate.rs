@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::ops::Bound as RangeBound;
+
+use cosmwasm_std::{
+    Addr, BlockInfo, Decimal, Deps, DepsMut, MessageInfo, Order, Record,
+    Storage, Uint128,
+};
+
+use crate::{
+    borrowing::get_trade_liquidation_price_with_fees,
+    error::ContractError,
+    fees::{
+        get_total_closing_fees_collateral, get_trader_referrer,
+        process_closing_fees,
+    },
+    msgs::{
+        CloseTradeQuote, OpenTradeQuote, SimulateCloseTradeResponse,
+        SimulateOpenTradeResponse,
+    },
+    pairs::state::PAIRS,
+    trade::{close_trade_market, get_token_price, open_trade},
+    trading::{
+        state::{OpenOrderType, PendingOrderType, Trade, TRADES, USER_COUNTERS},
+        utils::get_pnl_percent,
+    },
+};
+
+/// Storage that keeps writes in memory on top of a read only storage, so
+/// that execute logic can run from a query without persisting anything, or
+/// have its writes applied only when it succeeds.
+pub(crate) struct SimulationStorage<'a> {
+    base: &'a dyn Storage,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> SimulationStorage<'a> {
+    pub(crate) fn new(base: &'a dyn Storage) -> Self {
+        Self {
+            base,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Writes made on top of the base storage, `None` for removed keys.
+    pub(crate) fn into_writes(self) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+        self.writes
+    }
+}
+
+impl Storage for SimulationStorage<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.writes.get(key) {
+            Some(value) => value.clone(),
+            None => self.base.get(key),
+        }
+    }
+
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        let mut records: BTreeMap<Vec<u8>, Vec<u8>> =
+            self.base.range(start, end, Order::Ascending).collect();
+
+        let bounds = (
+            start.map_or(RangeBound::Unbounded, |s| {
+                RangeBound::Included(s.to_vec())
+            }),
+            end.map_or(RangeBound::Unbounded, |e| {
+                RangeBound::Excluded(e.to_vec())
+            }),
+        );
+        for (key, value) in self.writes.range(bounds) {
+            match value {
+                Some(value) => records.insert(key.clone(), value.clone()),
+                None => records.remove(key),
+            };
+        }
+
+        match order {
+            Order::Ascending => Box::new(records.into_iter()),
+            Order::Descending => Box::new(records.into_iter().rev()),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.writes.insert(key.to_vec(), None);
+    }
+}
+
+/// Runs `open_trade` on a throwaway copy of the state and reports the
+/// resulting trade, or the error the transaction would fail with.
+pub fn simulate_open_trade(
+    deps: Deps,
+    block: &BlockInfo,
+    trade: Trade,
+    order_type: OpenOrderType,
+    max_slippage_p: Decimal,
+) -> Result<SimulateOpenTradeResponse, ContractError> {
+    let mut storage = SimulationStorage::new(deps.storage);
+    let mut sim_deps = DepsMut {
+        storage: &mut storage,
+        api: deps.api,
+        querier: deps.querier,
+    };
+
+    match quote_open_trade(
+        &mut sim_deps,
+        block,
+        trade,
+        order_type,
+        max_slippage_p,
+    ) {
+        Ok(quote) => Ok(SimulateOpenTradeResponse {
+            quote: Some(quote),
+            error: None,
+        }),
+        Err(err) => Ok(SimulateOpenTradeResponse {
+            quote: None,
+            error: Some(err.to_string()),
+        }),
+    }
+}
+
+fn quote_open_trade(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    trade: Trade,
+    order_type: OpenOrderType,
+    max_slippage_p: Decimal,
+) -> Result<OpenTradeQuote, ContractError> {
+    let index = USER_COUNTERS
+        .may_load(deps.storage, trade.user.clone())?
+        .unwrap_or_default();
+    let pair = PAIRS
+        .load(deps.storage, trade.pair_index)
+        .map_err(|_| ContractError::PairNotFound(trade.pair_index))?;
+    let market_price = get_token_price(&deps.as_ref(), &pair.oracle_index)?;
+
+    let referrer = get_trader_referrer(&deps.as_ref(), &trade.user)?;
+    open_trade(
+        deps,
+        block,
+        trade.clone(),
+        order_type,
+        max_slippage_p,
+        referrer.as_ref(),
+    )?;
+
+    let opened_trade = TRADES.load(deps.storage, (trade.user.clone(), index))?;
+    Ok(OpenTradeQuote {
+        market_price,
+        execution_price: opened_trade.open_price,
+        opening_fees_collateral: trade
+            .collateral_amount
+            .checked_sub(opened_trade.collateral_amount)?,
+        collateral_amount: opened_trade.collateral_amount,
+        liquidation_price: get_trade_liquidation_price_with_fees(
+            &deps.as_ref(),
+            block,
+            opened_trade.clone(),
+            false,
+        )?,
+        trade: opened_trade,
+    })
+}
+
+/// Runs `close_trade_market` on a throwaway copy of the state and reports
+/// the PnL breakdown of the closed collateral.
+pub fn simulate_close_trade(
+    deps: Deps,
+    block: &BlockInfo,
+    trader: Addr,
+    index: u64,
+    collateral_delta: Option<Uint128>,
+) -> Result<SimulateCloseTradeResponse, ContractError> {
+    match quote_close_trade(deps, block, trader, index, collateral_delta) {
+        Ok(quote) => Ok(SimulateCloseTradeResponse {
+            quote: Some(quote),
+            error: None,
+        }),
+        Err(err) => Ok(SimulateCloseTradeResponse {
+            quote: None,
+            error: Some(err.to_string()),
+        }),
+    }
+}
+
+fn quote_close_trade(
+    deps: Deps,
+    block: &BlockInfo,
+    trader: Addr,
+    index: u64,
+    collateral_delta: Option<Uint128>,
+) -> Result<CloseTradeQuote, ContractError> {
+    // the close only tells whether the transaction would go through, the
+    // breakdown is computed on a separate copy of the state
+    close_trade_market(
+        &mut DepsMut {
+            storage: &mut SimulationStorage::new(deps.storage),
+            api: deps.api,
+            querier: deps.querier,
+        },
+        block,
+        MessageInfo {
+            sender: trader.clone(),
+            funds: vec![],
+        },
+        index,
+        collateral_delta,
+    )?;
+
+    let mut storage = SimulationStorage::new(deps.storage);
+    let mut sim_deps = DepsMut {
+        storage: &mut storage,
+        api: deps.api,
+        querier: deps.querier,
+    };
+
+    let trade = TRADES.load(sim_deps.storage, (trader, index))?;
+    let pair = PAIRS.load(sim_deps.storage, trade.pair_index)?;
+    let price = get_token_price(&deps, &pair.oracle_index)?;
+    let pnl_p =
+        get_pnl_percent(trade.open_price, price, trade.long, trade.leverage)?;
+
+    let mut closed_trade = trade.clone();
+    if let Some(collateral_delta) = collateral_delta {
+        closed_trade.collateral_amount = collateral_delta;
+    }
+
+    let (
+        _,
+        vault_closing_fee_collateral,
+        gov_staking_fee_collateral,
+        trigger_fee_collateral,
+        _,
+    ) = process_closing_fees(
+        &mut sim_deps,
+        block,
+        closed_trade.clone(),
+        closed_trade.get_position_size_collateral(),
+        PendingOrderType::Market,
+        None,
+    )?;
+    let funding_fee_collateral = closed_trade
+        .get_trade_funding_fees_collateral(&sim_deps.as_ref(), block)?;
+    let (trade_value_collateral, borrowing_fee_collateral) = closed_trade
+        .get_trade_value_collateral(
+            &sim_deps.as_ref(),
+            block,
+            pnl_p,
+            get_total_closing_fees_collateral(
+                vault_closing_fee_collateral,
+                gov_staking_fee_collateral,
+                trigger_fee_collateral,
+                &PendingOrderType::Market,
+            ),
+            PendingOrderType::Market,
+        )?;
+
+    Ok(CloseTradeQuote {
+        price,
+        pnl_p,
+        collateral_amount: closed_trade.collateral_amount,
+        vault_closing_fee_collateral,
+        gov_staking_fee_collateral,
+        trigger_fee_collateral,
+        borrowing_fee_collateral,
+        funding_fee_collateral,
+        trade_value_collateral,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        default_trade, mock_block, mock_deps, set_oracle_price, setup_market,
+        store_open_trade, TRADER,
+    };
+    use crate::utils::u128_to_i128;
+    use cosmwasm_std::{Int128, SignedDecimal};
+
+    #[test]
+    fn test_simulate_open_trade() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+
+        let mut invalid_leverage = default_trade(0);
+        invalid_leverage.leverage = Uint128::new(101);
+
+        let test_cases = vec![
+            ("valid trade", default_trade(0), None),
+            (
+                "leverage above the group max",
+                invalid_leverage,
+                Some(ContractError::InvalidLeverage.to_string()),
+            ),
+        ];
+
+        for (description, trade, expected_error) in test_cases {
+            let resp = simulate_open_trade(
+                deps.as_ref(),
+                &block,
+                trade,
+                OpenOrderType::MARKET,
+                Decimal::percent(1),
+            )
+            .unwrap();
+            assert_eq!(
+                resp.error, expected_error,
+                "Failed test: {}",
+                description
+            );
+        }
+
+        let quote = simulate_open_trade(
+            deps.as_ref(),
+            &block,
+            default_trade(0),
+            OpenOrderType::MARKET,
+            Decimal::percent(1),
+        )
+        .unwrap()
+        .quote
+        .unwrap();
+        assert_eq!(quote.execution_price, Decimal::from_ratio(100_u64, 1_u64));
+        // 0.1% open fee counted twice plus the 0.02% trigger fee
+        assert_eq!(quote.opening_fees_collateral, Uint128::new(22_000));
+        assert_eq!(quote.collateral_amount, Uint128::new(978_000));
+        assert!(quote.liquidation_price < quote.execution_price);
+
+        // nothing is written
+        assert!(!TRADES.has(deps.as_ref().storage, (Addr::unchecked(TRADER), 0)));
+        assert!(
+            !USER_COUNTERS.has(deps.as_ref().storage, Addr::unchecked(TRADER))
+        );
+    }
+
+    #[test]
+    fn test_simulate_close_trade() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+        set_oracle_price(&mut deps, Decimal::from_ratio(110_u64, 1_u64));
+
+        let resp = simulate_close_trade(
+            deps.as_ref(),
+            &block,
+            Addr::unchecked(TRADER),
+            1,
+            None,
+        )
+        .unwrap();
+        assert!(resp.quote.is_none());
+        assert!(resp.error.is_some());
+
+        let quote = simulate_close_trade(
+            deps.as_ref(),
+            &block,
+            Addr::unchecked(TRADER),
+            0,
+            Some(Uint128::new(500_000)),
+        )
+        .unwrap()
+        .quote
+        .unwrap();
+        assert_eq!(quote.pnl_p, SignedDecimal::one());
+        assert_eq!(quote.collateral_amount, Uint128::new(500_000));
+        assert_eq!(
+            quote.trade_value_collateral,
+            Int128::new(1_000_000)
+                - u128_to_i128(
+                    quote.vault_closing_fee_collateral
+                        + quote.trigger_fee_collateral
+                )
+                .unwrap()
+        );
+
+        let trade = TRADES
+            .load(deps.as_ref().storage, (Addr::unchecked(TRADER), 0))
+            .unwrap();
+        assert!(trade.is_open);
+        assert_eq!(trade.collateral_amount, Uint128::new(1_000_000));
+    }
+}
@@ -0,0 +1,268 @@
+use cosmwasm_std::{
+    from_json,
+    testing::{mock_dependencies, MockApi, MockQuerier, MockStorage},
+    to_json_binary, Addr, Binary, BlockInfo, ContractResult, Decimal, DepsMut,
+    Int128, OwnedDeps, StdResult, SystemError, SystemResult, Timestamp, Uint128,
+    WasmQuery,
+};
+use oracle::contract::{OracleQueryMsg, Price};
+use vault::query::CollateralizationResponse;
+
+use crate::{
+    borrowing::{
+        handle_trade_borrowing,
+        state::{BorrowingData, BorrowingPairGroup, OpenInterest},
+    },
+    contract::execute_admin,
+    fees::state::VAULT_CLOSING_FEE_P,
+    msgs::AdminExecuteMsg,
+    pairs::state::{Fee, ORACLE_ADDRESS, STAKING_ADDRESS, VAULT_ADDRESS},
+    price_impact::state::{OiWindowsSettings, PairDepth},
+    trading::state::{
+        save_trade, Trade, TradeInfo, TradeType, TraderPairCounts,
+        TradingActivated, TRADER_PAIR_COUNTS, TRADE_INFOS, USER_COUNTERS,
+    },
+};
+
+pub(crate) type MockDeps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+pub(crate) const TRADER: &str = "trader";
+pub(crate) const COLLATERAL_DENOM: &str = "usd";
+
+/// Mock dependencies whose oracle returns `price` for every pair and a
+/// collateral price of 1, with a fully collateralized vault.
+pub(crate) fn mock_deps(price: Decimal) -> MockDeps {
+    let mut deps = mock_dependencies();
+    set_oracle_price(&mut deps, price);
+    deps
+}
+
+pub(crate) fn set_oracle_price(deps: &mut MockDeps, price: Decimal) {
+    deps.querier.update_wasm(move |query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == "vault" => {
+            SystemResult::Ok(ContractResult::Ok(
+                to_json_binary(&CollateralizationResponse {
+                    open_pnl: Int128::zero(),
+                    collateralization_p: Decimal::one(),
+                    undercollateralized: false,
+                    withdraw_epochs_timelock: 2,
+                })
+                .unwrap(),
+            ))
+        }
+        WasmQuery::Smart { msg, .. } => {
+            let price = match from_json::<OracleQueryMsg>(msg) {
+                Ok(OracleQueryMsg::GetPrice { .. }) => price,
+                Ok(OracleQueryMsg::GetCollateralPrice { .. }) => Decimal::one(),
+                Ok(OracleQueryMsg::GetPriceData { .. }) => {
+                    return price_data(price)
+                }
+                Ok(OracleQueryMsg::GetCollateralPriceData { .. }) => {
+                    return price_data(Decimal::one())
+                }
+                _ => {
+                    return SystemResult::Err(SystemError::InvalidRequest {
+                        error: "unsupported oracle query".to_string(),
+                        request: msg.clone(),
+                    })
+                }
+            };
+            SystemResult::Ok(ContractResult::Ok(to_json_binary(&price).unwrap()))
+        }
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "non smart wasm query".to_string(),
+        }),
+    });
+}
+
+fn price_data(price: Decimal) -> SystemResult<ContractResult<Binary>> {
+    SystemResult::Ok(ContractResult::Ok(
+        to_json_binary(&Price {
+            last_update_block: 0,
+            price,
+            previous_price: None,
+        })
+        .unwrap(),
+    ))
+}
+
+pub(crate) fn mock_block(height: u64) -> BlockInfo {
+    BlockInfo {
+        height,
+        time: Timestamp::from_seconds(1_700_000_000 + height * 5),
+        chain_id: "cosmos-testnet-14002".to_string(),
+    }
+}
+
+/// Sets up pair 0 (btc-usd) with collateral 0, 0.1% open/close fees and a
+/// 0.02% trigger fee, no borrowing fees and no price impact.
+pub(crate) fn setup_market(deps: &mut DepsMut) {
+    ORACLE_ADDRESS
+        .save(deps.storage, &Addr::unchecked("oracle"))
+        .unwrap();
+    STAKING_ADDRESS
+        .save(deps.storage, &Addr::unchecked("staking"))
+        .unwrap();
+    VAULT_ADDRESS
+        .save(deps.storage, &Addr::unchecked("vault"))
+        .unwrap();
+    VAULT_CLOSING_FEE_P
+        .save(deps.storage, &Decimal::percent(50))
+        .unwrap();
+
+    let open_interest = OpenInterest {
+        long: Uint128::zero(),
+        short: Uint128::zero(),
+        max: Uint128::new(1_000_000_000),
+    };
+    let borrowing_data = BorrowingData {
+        fee_per_block: Decimal::zero(),
+        acc_fee_long: Decimal::zero(),
+        acc_fee_short: Decimal::zero(),
+        acc_last_updated_block: 0,
+        fee_exponent: 1,
+    };
+
+    let messages = vec![
+        AdminExecuteMsg::default_set_pairs(),
+        AdminExecuteMsg::default_set_groups(),
+        AdminExecuteMsg::SetFees {
+            fees: vec![(
+                0,
+                Fee {
+                    name: "default".to_string(),
+                    open_fee_p: Decimal::permille(1),
+                    close_fee_p: Decimal::permille(1),
+                    oracle_fee_p: Decimal::zero(),
+                    trigger_order_fee_p: Decimal::from_ratio(2_u64, 10_000_u64),
+                    min_position_size_usd: Uint128::zero(),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        },
+        AdminExecuteMsg::default_collaterals(),
+        AdminExecuteMsg::UpdateBorrowingPairs {
+            borrowing_pairs: vec![((0, 0), borrowing_data.clone())]
+                .into_iter()
+                .collect(),
+        },
+        AdminExecuteMsg::UpdateBorrowingPairGroups {
+            pair_groups: vec![(
+                (0, 0),
+                vec![BorrowingPairGroup {
+                    group_index: 0,
+                    block: 0,
+                    initial_acc_fee_long: Decimal::zero(),
+                    initial_acc_fee_short: Decimal::zero(),
+                    prev_group_acc_fee_long: Decimal::zero(),
+                    prev_group_acc_fee_short: Decimal::zero(),
+                    pair_acc_fee_long: Decimal::zero(),
+                    pair_acc_fee_short: Decimal::zero(),
+                }],
+            )]
+            .into_iter()
+            .collect(),
+        },
+        AdminExecuteMsg::UpdateBorrowingGroups {
+            groups: vec![((0, 0), borrowing_data)].into_iter().collect(),
+        },
+        AdminExecuteMsg::UpdateBorrowingPairOis {
+            pair_ois: vec![((0, 0), open_interest.clone())]
+                .into_iter()
+                .collect(),
+        },
+        AdminExecuteMsg::UpdateBorrowingGroupOis {
+            group_ois: vec![((0, 0), open_interest)].into_iter().collect(),
+        },
+        AdminExecuteMsg::UpdateOiWindowsSettings {
+            oi_windows_settings: OiWindowsSettings {
+                start_ts: 0,
+                windows_duration: 3600,
+                windows_count: 0,
+            },
+        },
+        AdminExecuteMsg::UpdatePairDepths {
+            pair_depths: vec![(
+                0,
+                PairDepth {
+                    one_percent_depth_above_usd: 0,
+                    one_percent_depth_below_usd: 0,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        },
+        AdminExecuteMsg::set_trading_activated(TradingActivated::Activated),
+    ];
+
+    for msg in messages {
+        execute_admin(deps, &mock_block(0), msg).unwrap();
+    }
+}
+
+pub(crate) fn default_trade(index: u64) -> Trade {
+    Trade {
+        user: Addr::unchecked(TRADER),
+        pair_index: 0,
+        index,
+        leverage: Uint128::new(10),
+        long: true,
+        is_open: true,
+        collateral_index: 0,
+        trade_type: TradeType::Trade,
+        collateral_amount: Uint128::new(1_000_000),
+        open_price: Decimal::from_ratio(100_u64, 1_u64),
+        tp: Decimal::zero(),
+        sl: Decimal::zero(),
+    }
+}
+
+/// Stores an already opened trade and registers its open interest.
+pub(crate) fn store_open_trade(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    trade: &Trade,
+) {
+    save_trade(deps.storage, trade).unwrap();
+    TRADE_INFOS
+        .save(
+            deps.storage,
+            (trade.user.clone(), trade.index),
+            &TradeInfo {
+                created_block: block.height,
+                tp_last_updated_block: block.height,
+                sl_last_updated_block: block.height,
+                max_slippage_p: Decimal::percent(1),
+                last_oi_update_ts: Timestamp::from_nanos(0),
+                collateral_price_usd: Decimal::one(),
+            },
+        )
+        .unwrap();
+    USER_COUNTERS
+        .save(deps.storage, trade.user.clone(), &(trade.index + 1))
+        .unwrap();
+    TRADER_PAIR_COUNTS
+        .update(
+            deps.storage,
+            (trade.user.clone(), trade.pair_index),
+            |counts| -> StdResult<_> {
+                let mut counts: TraderPairCounts = counts.unwrap_or_default();
+                counts.trades += 1;
+                Ok(counts)
+            },
+        )
+        .unwrap();
+    handle_trade_borrowing(
+        block,
+        trade.user.clone(),
+        trade.index,
+        deps.storage,
+        trade.collateral_index,
+        trade.pair_index,
+        trade.get_position_size_collateral(),
+        true,
+        trade.long,
+    )
+    .unwrap();
+}
@@ -1,39 +1,58 @@
 use crate::borrowing::{
     get_trade_liquidation_price_with_fees, handle_trade_borrowing,
 };
-use crate::constants::{MAX_OPEN_NEGATIVE_PNL_P};
+use crate::constants::{
+    DEFAULT_MAX_PENDING_ORDERS, DEFAULT_MAX_TRADES_PER_PAIR,
+    MAX_OPEN_NEGATIVE_PNL_P,
+};
 use crate::error::ContractError;
-use crate::fees::{process_closing_fees, process_opening_fees};
+use crate::fees::{
+    distribute_vault_reward, get_total_closing_fees_collateral,
+    get_trader_referrer, process_closing_fees, process_opening_fees,
+    register_bad_debt, send_from_vault, send_to_vault,
+};
+use crate::keepers::{check_keeper, record_trigger};
+use crate::msgs::{TriggerTradeResult, TriggerTradesResponse};
 use crate::pairs::state::{
     FEES, GROUPS, ORACLE_ADDRESS, PAIRS, PAIR_CUSTOM_MAX_LEVERAGE,
+    REFERRALS_ADDRESS, VAULT_ADDRESS,
 };
 use crate::price_impact::{
     add_price_impact_open_interest, get_trade_price_impact,
     remove_price_impact_open_interest,
 };
+use crate::simulate::SimulationStorage;
 use crate::trading::state::{
-    OpenOrderType, PendingOrderType, Trade, TradeInfo, TradeType,
-    TradingActivated, COLLATERALS, TRADER_STORED, TRADES, TRADE_INFOS,
-    TRADING_ACTIVATED, USER_COUNTERS,
+    save_trade, OpenOrderType, PendingOrderType, Trade, TradeInfo, TradeType,
+    TradingActivated, COLLATERALS, MAX_PENDING_ORDERS, MAX_TRADES_PER_PAIR,
+    TRADER_PAIR_COUNTS, TRADER_STORED, TRADES, TRADE_INFOS, TRADING_ACTIVATED,
+    USER_COUNTERS,
 };
 use crate::trading::utils::{
-    get_collateral_price, get_collateral_price_usd, get_pnl_percent,
-    get_position_size_collateral, limit_sl_distance, limit_tp_distance,
+    check_price_guard, get_collateral_price_usd, get_guarded_collateral_price,
+    get_pnl_percent, get_position_size_collateral, limit_sl_distance,
+    limit_tp_distance,
 };
-use crate::utils::{u128_to_dec};
+use crate::utils::{u128_to_dec, u128_to_i128};
+use std::collections::HashMap;
+
 use cosmwasm_std::{
-    Addr, BankMsg, BlockInfo, Coin, Decimal, Deps, DepsMut, Int128, MessageInfo,
-    Response, SignedDecimal, Uint128,
+    to_json_binary, Addr, BankMsg, BlockInfo, Coin, CosmosMsg, Decimal, Deps,
+    DepsMut, Env, Int128, MessageInfo, Response, SignedDecimal, Storage,
+    Uint128, WasmMsg,
 };
 
-use oracle::contract::OracleQueryMsg;
+use oracle::contract::{OracleQueryMsg, Price};
+use referrals::{contract::ReferralsExecuteMsg, query::ReferralsQueryMsg};
+use vault::query::{CollateralizationResponse, VaultQueryMsg};
 
 pub fn open_trade(
     deps: &mut DepsMut,
     block: &BlockInfo,
     trade: Trade,
-    order_type: OpenOrderType,
+    _order_type: OpenOrderType,
     max_slippage_p: Decimal,
+    referrer: Option<&Addr>,
 ) -> Result<Response, ContractError> {
     let mut trade = trade.clone();
 
@@ -41,21 +60,27 @@ pub fn open_trade(
         .load(deps.storage, trade.clone().pair_index)
         .map_err(|_| ContractError::PairNotFound(trade.pair_index))?;
 
-    let base_price = get_token_price(&deps.as_ref(), &pair.oracle_index)?;
+    let base_price = get_guarded_token_price(
+        &deps.as_ref(),
+        block,
+        pair.oracle_index,
+        trade.pair_index,
+    )?;
 
     let pair_fees = FEES.load(deps.storage, pair.fee_index)?;
-    let group = GROUPS.load(deps.storage, pair.group_index)?;
 
     let position_size_collateral =
         get_position_size_collateral(trade.collateral_amount, trade.leverage)?;
-    let collateral_price =
-        get_collateral_price(&deps.as_ref(), &trade.collateral_index.clone())?;
+    let collateral_price = get_guarded_collateral_price(
+        &deps.as_ref(),
+        block,
+        trade.collateral_index,
+        trade.pair_index,
+    )?;
 
     let position_size_usd =
         get_usd_normalized_value(collateral_price, position_size_collateral)?;
 
-    trade.open_price = base_price;
-
     // trade collateral usd value need to be >= 5x min trade fee usd
     // (collateral left after trade opened >= 80%)
     if position_size_usd.checked_div(trade.leverage)?
@@ -64,31 +89,18 @@ pub fn open_trade(
         return Err(ContractError::InsufficientCollateral);
     }
 
-    if trade.leverage < group.min_leverage || trade.leverage > group.max_leverage
-    {
-        return Err(ContractError::InvalidLeverage);
-    }
-
-    if let Some(pair_max_leverage) =
-        PAIR_CUSTOM_MAX_LEVERAGE.may_load(deps.storage, trade.pair_index)?
-    {
-        if trade.leverage > pair_max_leverage {
-            return Err(ContractError::InvalidLeverage);
-        }
-    }
+    check_leverage(&deps.as_ref(), trade.pair_index, trade.leverage)?;
+    check_vault_collateralization(&deps.as_ref(), trade.collateral_index)?;
 
     if trade.trade_type != TradeType::Trade {
         // limit orders are stored as such in the same state, we just don't
         // update the open interest since they are not "live"
-        return store_trade(
-            deps,
-            block,
-            trade.clone(),
-            None,
-            Some(max_slippage_p),
-        );
+        if trade.open_price.is_zero() {
+            return Err(ContractError::TradeInvalid);
+        }
+        store_trade(deps, block, trade.clone(), None, Some(max_slippage_p))
     } else {
-        trade.validate(
+        let (_, price_after_impact) = trade.validate(
             deps.as_ref(),
             block,
             position_size_usd,
@@ -96,6 +108,7 @@ pub fn open_trade(
             base_price,
             max_slippage_p,
         )?;
+        trade.open_price = price_after_impact;
         let height = block.height;
         let time = block.time;
 
@@ -108,24 +121,81 @@ pub fn open_trade(
             collateral_price_usd: collateral_price,
         };
 
-        return register_trade(
-            deps,
-            block,
-            trade.clone(),
-            trade_info,
-            order_type,
-        );
+        register_trade(deps, block, trade.clone(), trade_info, None, referrer)
+    }
+}
+
+/// Checks the leverage against the pair group bounds and the pair custom
+/// max leverage.
+fn check_leverage(
+    deps: &Deps,
+    pair_index: u64,
+    leverage: Uint128,
+) -> Result<(), ContractError> {
+    let pair = PAIRS.load(deps.storage, pair_index)?;
+    let group = GROUPS.load(deps.storage, pair.group_index)?;
+
+    if leverage < group.min_leverage || leverage > group.max_leverage {
+        return Err(ContractError::InvalidLeverage);
+    }
+
+    if let Some(pair_max_leverage) =
+        PAIR_CUSTOM_MAX_LEVERAGE.may_load(deps.storage, pair_index)?
+    {
+        if leverage > pair_max_leverage {
+            return Err(ContractError::InvalidLeverage);
+        }
+    }
+    Ok(())
+}
+
+/// Refuses new trades while the vault backing the collateral is
+/// undercollateralized.
+fn check_vault_collateralization(
+    deps: &Deps,
+    collateral_index: u64,
+) -> Result<(), ContractError> {
+    let collateralization: CollateralizationResponse =
+        deps.querier.query_wasm_smart(
+            VAULT_ADDRESS.load(deps.storage)?.to_string(),
+            &VaultQueryMsg::Collateralization {
+                denom: COLLATERALS.load(deps.storage, collateral_index)?,
+            },
+        )?;
+    if collateralization.undercollateralized {
+        return Err(ContractError::VaultUndercollateralized);
+    }
+    Ok(())
+}
+
+/// Ensures the message carries exactly `amount` of the collateral denom.
+pub(crate) fn assert_collateral_sent(
+    info: &MessageInfo,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let sent: Uint128 = info
+        .funds
+        .iter()
+        .filter(|coin| coin.denom == denom)
+        .map(|coin| coin.amount)
+        .sum();
+
+    if sent != amount || info.funds.iter().any(|coin| coin.denom != denom) {
+        return Err(ContractError::InvalidFunds);
     }
-    register_potential_referrer(&trade)?;
+    Ok(())
 }
 
-// Validate the trade and store it as a trade
+// Validate the trade and store it as a trade, `keeper` is set when the trade
+// comes from a triggered order
 fn register_trade(
     deps: &mut DepsMut,
     block: &BlockInfo,
     trade: Trade,
     trade_info: TradeInfo,
-    order_type: OpenOrderType,
+    keeper: Option<&Addr>,
+    referrer: Option<&Addr>,
 ) -> Result<Response, ContractError> {
     let mut final_trade = trade.clone();
     let (msgs, fees) = process_opening_fees(
@@ -133,7 +203,8 @@ fn register_trade(
         block,
         trade.clone(),
         get_position_size_collateral(trade.collateral_amount, trade.leverage)?,
-        order_type,
+        keeper,
+        referrer,
     )?;
     final_trade.collateral_amount -= fees;
     store_trade(deps, block, final_trade, Some(trade_info), None)?;
@@ -141,6 +212,47 @@ fn register_trade(
     Ok(Response::new().add_messages(msgs))
 }
 
+/// Binds the trader to the referral code on its first trade with one, and
+/// returns its referrer. The binding is only stored by the referrals contract
+/// after this trade, so the referrer of the code is returned for its fees.
+pub(crate) fn register_potential_referrer(
+    deps: &Deps,
+    trader: &Addr,
+    code: &str,
+) -> Result<(Option<Addr>, Option<WasmMsg>), ContractError> {
+    let Some(referrals_address) = REFERRALS_ADDRESS.may_load(deps.storage)?
+    else {
+        return Ok((None, None));
+    };
+    if let Some(referrer) = get_trader_referrer(deps, trader)? {
+        return Ok((Some(referrer), None));
+    }
+    if code.is_empty() {
+        return Ok((None, None));
+    }
+
+    // unknown codes have no owner
+    let owner: String = deps.querier.query_wasm_smart(
+        &referrals_address,
+        &ReferralsQueryMsg::GetCodeOwner {
+            code: code.to_string(),
+        },
+    )?;
+    if owner.is_empty() || owner == trader.as_str() {
+        return Err(ContractError::InvalidReferral);
+    }
+
+    let msg = WasmMsg::Execute {
+        contract_addr: referrals_address.to_string(),
+        msg: to_json_binary(&ReferralsExecuteMsg::SetTraderReferralCode {
+            account: trader.to_string(),
+            code: code.to_string(),
+        })?,
+        funds: vec![],
+    };
+    Ok((Some(Addr::unchecked(owner)), Some(msg)))
+}
+
 pub fn get_token_price(
     deps: &Deps,
     oracle_index: &u64,
@@ -153,8 +265,30 @@ pub fn get_token_price(
     )?)
 }
 
-fn register_potential_referrer(_trade: &Trade) -> Result<(), ContractError> {
-    todo!()
+/// Price of `oracle_index` for trading on `pair_index`, checked against the
+/// pair price guard.
+pub fn get_guarded_token_price(
+    deps: &Deps,
+    block: &BlockInfo,
+    oracle_index: u64,
+    pair_index: u64,
+) -> Result<Decimal, ContractError> {
+    let price = get_token_price_data(deps, oracle_index)?;
+    check_price_guard(deps.storage, block, pair_index, &price, true)?;
+    Ok(price.price)
+}
+
+/// Price of `oracle_index` with its last update block and previous price.
+fn get_token_price_data(
+    deps: &Deps,
+    oracle_index: u64,
+) -> Result<Price, ContractError> {
+    Ok(deps.querier.query_wasm_smart::<Price>(
+        ORACLE_ADDRESS.load(deps.storage)?.to_string(),
+        &OracleQueryMsg::GetPriceData {
+            index: oracle_index,
+        },
+    )?)
 }
 
 fn store_trade(
@@ -207,14 +341,12 @@ fn store_trade(
     )?;
     trade.index = counter;
 
-    TRADES.save(
-        deps.storage,
-        (trade.user.clone(), trade.pair_index),
-        &trade.clone(),
-    )?;
+    update_trader_pair_counts(deps.storage, &trade, true)?;
+
+    save_trade(deps.storage, &trade)?;
     TRADE_INFOS.save(
         deps.storage,
-        (trade.user.clone(), trade.pair_index),
+        (trade.user.clone(), trade.index),
         &trade_info,
     )?;
     TRADER_STORED.save(deps.storage, trade.user.clone(), &true)?;
@@ -226,6 +358,46 @@ fn store_trade(
     Ok(Response::new().add_attribute("action", "open_trade"))
 }
 
+/// Counts a trade or pending order of the trader on its pair in or out,
+/// enforcing the max trades per pair and max pending orders on the way in.
+fn update_trader_pair_counts(
+    storage: &mut dyn Storage,
+    trade: &Trade,
+    open: bool,
+) -> Result<(), ContractError> {
+    let key = (trade.user.clone(), trade.pair_index);
+    let mut counts = TRADER_PAIR_COUNTS
+        .may_load(storage, key.clone())?
+        .unwrap_or_default();
+
+    if trade.trade_type == TradeType::Trade {
+        if open {
+            let max_trades = MAX_TRADES_PER_PAIR
+                .may_load(storage)?
+                .unwrap_or(DEFAULT_MAX_TRADES_PER_PAIR);
+            if counts.trades >= max_trades {
+                return Err(ContractError::MaxTradesPerPair);
+            }
+            counts.trades += 1;
+        } else {
+            counts.trades = counts.trades.saturating_sub(1);
+        }
+    } else if open {
+        let max_pending_orders = MAX_PENDING_ORDERS
+            .may_load(storage)?
+            .unwrap_or(DEFAULT_MAX_PENDING_ORDERS);
+        if counts.pending_orders >= max_pending_orders {
+            return Err(ContractError::MaxPendingOrders);
+        }
+        counts.pending_orders += 1;
+    } else {
+        counts.pending_orders = counts.pending_orders.saturating_sub(1);
+    }
+
+    TRADER_PAIR_COUNTS.save(storage, key, &counts)?;
+    Ok(())
+}
+
 fn add_trade_oi_collateral(
     block: &BlockInfo,
     deps: &mut DepsMut,
@@ -325,6 +497,452 @@ pub fn close_trade(
         .add_attribute("action", "cance_open_order"))
 }
 
+/// Closes a live trade at market price. When `collateral_delta` is set and
+/// smaller than the trade collateral, only that part of the position is
+/// closed and the rest stays open.
+pub fn close_trade_market(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    info: MessageInfo,
+    index: u64,
+    collateral_delta: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let trade = TRADES.load(deps.storage, (info.sender.clone(), index))?;
+
+    if !trade.is_open {
+        return Err(ContractError::TradeClosed);
+    }
+    if trade.trade_type != TradeType::Trade {
+        return close_trade(deps, block, info, index);
+    }
+    if TRADING_ACTIVATED.load(deps.storage)? == TradingActivated::Paused {
+        return Err(ContractError::Paused);
+    }
+
+    let pair = PAIRS.load(deps.storage, trade.pair_index)?;
+    let price = get_guarded_token_price(
+        &deps.as_ref(),
+        block,
+        pair.oracle_index,
+        trade.pair_index,
+    )?;
+    if price.is_zero() {
+        return Err(ContractError::TradeInvalid);
+    }
+    let profit_p =
+        get_pnl_percent(trade.open_price, price, trade.long, trade.leverage)?;
+
+    match collateral_delta {
+        Some(delta) if delta.is_zero() || delta > trade.collateral_amount => {
+            Err(ContractError::InvalidCollateralDelta)
+        }
+        Some(delta) if delta < trade.collateral_amount => {
+            decrease_trade_collateral(deps, block, trade, delta, profit_p)
+        }
+        _ => Ok(unregister_trade(
+            deps,
+            block,
+            trade,
+            profit_p,
+            PendingOrderType::Market,
+            None,
+        )?
+        .add_attribute("action", "close_trade_market")),
+    }
+}
+
+/// Closes `collateral_delta` of an open trade. The closed part pays its
+/// pro-rata closing and borrowing fees and gets its PnL settled, while the
+/// remaining position keeps its open price, leverage, TP and SL.
+fn decrease_trade_collateral(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    trade: Trade,
+    collateral_delta: Uint128,
+    profit_p: SignedDecimal,
+) -> Result<Response, ContractError> {
+    let mut remaining_trade = trade.clone();
+    remaining_trade.collateral_amount =
+        trade.collateral_amount.checked_sub(collateral_delta)?;
+
+    // the remaining position must still be able to pay its closing fees
+    let pair_fees = FEES.load(
+        deps.storage,
+        PAIRS.load(deps.storage, trade.pair_index)?.fee_index,
+    )?;
+    let remaining_collateral_usd = get_usd_normalized_value(
+        get_guarded_collateral_price(
+            &deps.as_ref(),
+            block,
+            trade.collateral_index,
+            trade.pair_index,
+        )?,
+        remaining_trade.collateral_amount,
+    )?;
+    if remaining_collateral_usd
+        < pair_fees.get_min_fee_usd()?.checked_mul(5_u16.into())?
+    {
+        return Err(ContractError::InsufficientCollateral);
+    }
+
+    let mut closed_trade = trade.clone();
+    closed_trade.collateral_amount = collateral_delta;
+    let closed_position_collateral = closed_trade.get_position_size_collateral();
+
+    let (
+        mut msgs,
+        vault_closing_fee_collateral,
+        gov_staking_fee_collateral,
+        trigger_fee_collateral,
+        collateral_left_in_storage,
+    ) = process_closing_fees(
+        deps,
+        block,
+        closed_trade.clone(),
+        closed_position_collateral,
+        PendingOrderType::Market,
+        None,
+    )?;
+
+    let (trade_value_collateral, borrowing_fee_collateral) = closed_trade
+        .get_trade_value_collateral(
+            &deps.as_ref(),
+            block,
+            profit_p,
+            get_total_closing_fees_collateral(
+                vault_closing_fee_collateral,
+                gov_staking_fee_collateral,
+                trigger_fee_collateral,
+                &PendingOrderType::Market,
+            ),
+            PendingOrderType::Market,
+        )?;
+
+    let (bad_debt, pnl_msgs) = handle_trade_pnl(
+        &deps.as_ref(),
+        closed_trade.clone(),
+        trade_value_collateral,
+        u128_to_i128(collateral_left_in_storage)?,
+        borrowing_fee_collateral,
+    )?;
+
+    if let Some(message) = register_bad_debt(deps, &trade, bad_debt)? {
+        msgs.push(message);
+    }
+
+    remove_oi_collateral(
+        deps,
+        block,
+        trade.clone(),
+        closed_position_collateral,
+    )?;
+
+    save_trade(deps.storage, &remaining_trade)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_messages(pnl_msgs)
+        .add_attribute("action", "decrease_trade_collateral")
+        .add_attribute("index", trade.index.to_string())
+        .add_attribute("collateral_delta", collateral_delta.to_string())
+        .add_attribute(
+            "collateral_left",
+            remaining_trade.collateral_amount.to_string(),
+        ))
+}
+
+/// Moves collateral in (`deposit`) or out of an open trade so that it reaches
+/// `leverage` with the same position size. Withdrawals must keep the trade
+/// away from its liquidation price.
+pub fn update_trade_margin(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    info: MessageInfo,
+    index: u64,
+    leverage: Uint128,
+    deposit: bool,
+) -> Result<Response, ContractError> {
+    let mut trade = TRADES.load(deps.storage, (info.sender.clone(), index))?;
+
+    if !trade.is_open {
+        return Err(ContractError::TradeClosed);
+    }
+    if trade.trade_type != TradeType::Trade {
+        return Err(ContractError::InvalidTradeType);
+    }
+    if TRADING_ACTIVATED.load(deps.storage)? == TradingActivated::Paused {
+        return Err(ContractError::Paused);
+    }
+    if (deposit && leverage >= trade.leverage)
+        || (!deposit && leverage <= trade.leverage)
+    {
+        return Err(ContractError::InvalidLeverage);
+    }
+    check_leverage(&deps.as_ref(), trade.pair_index, leverage)?;
+
+    let collateral_denom =
+        COLLATERALS.load(deps.storage, trade.collateral_index)?;
+    let position_collateral = trade.get_position_size_collateral();
+    let new_collateral = position_collateral.checked_div(leverage)?;
+    let collateral_delta = if deposit {
+        new_collateral.checked_sub(trade.collateral_amount)?
+    } else {
+        trade.collateral_amount.checked_sub(new_collateral)?
+    };
+
+    trade.collateral_amount = new_collateral;
+    trade.leverage = leverage;
+    trade.tp =
+        limit_tp_distance(trade.open_price, leverage, trade.tp, trade.long)?;
+    trade.sl =
+        limit_sl_distance(trade.open_price, leverage, trade.sl, trade.long)?;
+
+    let mut msgs = vec![];
+    if deposit {
+        assert_collateral_sent(&info, &collateral_denom, collateral_delta)?;
+    } else {
+        let pair = PAIRS.load(deps.storage, trade.pair_index)?;
+        let pair_fees = FEES.load(deps.storage, pair.fee_index)?;
+        let collateral_usd = get_usd_normalized_value(
+            get_guarded_collateral_price(
+                &deps.as_ref(),
+                block,
+                trade.collateral_index,
+                trade.pair_index,
+            )?,
+            new_collateral,
+        )?;
+        if collateral_usd
+            < pair_fees.get_min_fee_usd()?.checked_mul(5_u16.into())?
+        {
+            return Err(ContractError::InsufficientCollateral);
+        }
+
+        let price = get_guarded_token_price(
+            &deps.as_ref(),
+            block,
+            pair.oracle_index,
+            trade.pair_index,
+        )?;
+        let liq_price = get_trade_liquidation_price_with_fees(
+            &deps.as_ref(),
+            block,
+            trade.clone(),
+            true,
+        )?;
+        if (trade.long && liq_price >= price)
+            || (!trade.long && liq_price <= price)
+        {
+            return Err(ContractError::LiquidationPriceReached);
+        }
+
+        msgs.push(BankMsg::Send {
+            to_address: trade.user.to_string(),
+            amount: vec![Coin {
+                denom: collateral_denom,
+                amount: collateral_delta,
+            }],
+        });
+    }
+
+    // leverage is a whole number so the stored size can be slightly smaller
+    let oi_delta_collateral =
+        position_collateral.checked_sub(trade.get_position_size_collateral())?;
+    if !oi_delta_collateral.is_zero() {
+        remove_oi_collateral(deps, block, trade.clone(), oi_delta_collateral)?;
+    }
+
+    save_trade(deps.storage, &trade)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute(
+            "action",
+            if deposit {
+                "deposit_margin"
+            } else {
+                "withdraw_margin"
+            },
+        )
+        .add_attribute("index", trade.index.to_string())
+        .add_attribute("collateral_delta", collateral_delta.to_string())
+        .add_attribute("collateral", trade.collateral_amount.to_string())
+        .add_attribute("leverage", trade.leverage.to_string()))
+}
+
+/// Adds `collateral` at `leverage` to an open trade. Accrued borrowing and
+/// funding fees are settled with the vault first, opening fees are only
+/// charged on the added size and the open price becomes the size weighted
+/// average of both positions.
+pub fn increase_position(
+    deps: &mut DepsMut,
+    env: &Env,
+    info: MessageInfo,
+    index: u64,
+    collateral: Uint128,
+    leverage: Uint128,
+) -> Result<Response, ContractError> {
+    let mut trade = TRADES.load(deps.storage, (info.sender.clone(), index))?;
+    let trade_info =
+        TRADE_INFOS.load(deps.storage, (trade.user.clone(), trade.index))?;
+
+    if !trade.is_open {
+        return Err(ContractError::TradeClosed);
+    }
+    if trade.trade_type != TradeType::Trade {
+        return Err(ContractError::InvalidTradeType);
+    }
+    if TRADING_ACTIVATED.load(deps.storage)? != TradingActivated::Activated {
+        return Err(ContractError::Paused);
+    }
+    if collateral.is_zero() {
+        return Err(ContractError::InvalidCollateralDelta);
+    }
+    let collateral_denom =
+        COLLATERALS.load(deps.storage, trade.collateral_index)?;
+    assert_collateral_sent(&info, &collateral_denom, collateral)?;
+    check_leverage(&deps.as_ref(), trade.pair_index, leverage)?;
+
+    let pair = PAIRS.load(deps.storage, trade.pair_index)?;
+    let price = get_guarded_token_price(
+        &deps.as_ref(),
+        &env.block,
+        pair.oracle_index,
+        trade.pair_index,
+    )?;
+    let collateral_price = get_guarded_collateral_price(
+        &deps.as_ref(),
+        &env.block,
+        trade.collateral_index,
+        trade.pair_index,
+    )?;
+
+    // the added size goes through the same checks as a new trade
+    let mut added_trade = trade.clone();
+    added_trade.collateral_amount = collateral;
+    added_trade.leverage = leverage;
+    let added_position_collateral = added_trade.get_position_size_collateral();
+
+    let (_, price_after_impact) = added_trade.validate(
+        deps.as_ref(),
+        &env.block,
+        get_usd_normalized_value(collateral_price, added_position_collateral)?,
+        price,
+        price,
+        trade_info.max_slippage_p,
+    )?;
+
+    let referrer = get_trader_referrer(&deps.as_ref(), &trade.user)?;
+    let (mut msgs, opening_fees_collateral) = process_opening_fees(
+        deps,
+        &env.block,
+        added_trade,
+        added_position_collateral,
+        None,
+        referrer.as_ref(),
+    )?;
+
+    // the borrowing and funding fees accrued so far are settled before the
+    // initial acc fees are reset for the new position size
+    let borrowing_fee_collateral =
+        trade.get_trade_borrowing_fees_collateral(&deps.as_ref(), &env.block)?;
+    msgs.extend(distribute_vault_reward(
+        deps,
+        borrowing_fee_collateral,
+        &trade,
+    )?);
+
+    // funding paid by the trader goes to the vault and funding owed to the
+    // trader is paid by the vault, the same way as the PnL at close
+    let funding_fee_collateral =
+        trade.get_trade_funding_fees_collateral(&deps.as_ref(), &env.block)?;
+    if funding_fee_collateral.is_negative() {
+        msgs.extend(send_from_vault(
+            deps.storage,
+            funding_fee_collateral.unsigned_abs(),
+            &collateral_denom,
+            &env.contract.address,
+        )?);
+    } else {
+        msgs.extend(send_to_vault(
+            deps.storage,
+            funding_fee_collateral.unsigned_abs(),
+            &collateral_denom,
+        )?);
+    }
+
+    let new_collateral = Uint128::try_from(
+        u128_to_i128(
+            trade
+                .collateral_amount
+                .checked_sub(borrowing_fee_collateral)?
+                .checked_add(collateral)?
+                .checked_sub(opening_fees_collateral)?,
+        )?
+        .checked_sub(funding_fee_collateral)?,
+    )
+    .map_err(|_| ContractError::InsufficientCollateral)?;
+
+    // leverage is a whole number so the stored size can be slightly smaller
+    // than the requested one, the open price is averaged over the stored size
+    let position_collateral = trade.get_position_size_collateral();
+    let new_leverage = position_collateral
+        .checked_add(added_position_collateral)?
+        .checked_div(new_collateral)?;
+    check_leverage(&deps.as_ref(), trade.pair_index, new_leverage)?;
+    let new_position_collateral = new_collateral.checked_mul(new_leverage)?;
+    if new_position_collateral <= position_collateral {
+        return Err(ContractError::InvalidPositionSize);
+    }
+    let oi_delta_collateral = new_position_collateral - position_collateral;
+
+    let new_open_price = trade
+        .open_price
+        .checked_mul(u128_to_dec(position_collateral)?)?
+        .checked_add(
+            price_after_impact.checked_mul(u128_to_dec(oi_delta_collateral)?)?,
+        )?
+        .checked_div(u128_to_dec(new_position_collateral)?)?;
+
+    trade.collateral_amount = new_collateral;
+    trade.leverage = new_leverage;
+    trade.open_price = new_open_price;
+    trade.tp =
+        limit_tp_distance(new_open_price, new_leverage, trade.tp, trade.long)?;
+    trade.sl =
+        limit_sl_distance(new_open_price, new_leverage, trade.sl, trade.long)?;
+
+    // the accrued fees are already settled out of the new collateral
+    let liq_price = get_trade_liquidation_price_with_fees(
+        &deps.as_ref(),
+        &env.block,
+        trade.clone(),
+        false,
+    )?;
+    if (trade.long && liq_price >= price) || (!trade.long && liq_price <= price)
+    {
+        return Err(ContractError::LiquidationPriceReached);
+    }
+
+    add_oi_collateral(
+        &env.block,
+        deps,
+        trade.clone(),
+        trade_info,
+        oi_delta_collateral,
+    )?;
+
+    save_trade(deps.storage, &trade)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "increase_position")
+        .add_attribute("index", trade.index.to_string())
+        .add_attribute("collateral", trade.collateral_amount.to_string())
+        .add_attribute("leverage", trade.leverage.to_string())
+        .add_attribute("open_price", trade.open_price.to_string()))
+}
+
 fn _close_trade(
     deps: &mut DepsMut,
     block: &BlockInfo,
@@ -338,9 +956,8 @@ fn _close_trade(
     }
 
     trade.is_open = false;
-    let counter = USER_COUNTERS.load(deps.storage, trader.clone())?;
-    USER_COUNTERS.save(deps.storage, trader.clone(), &(counter - 1))?;
-    TRADES.save(deps.storage, (trader.clone(), index), &trade)?;
+    update_trader_pair_counts(deps.storage, &trade, false)?;
+    save_trade(deps.storage, &trade)?;
 
     if trade.trade_type == TradeType::Trade {
         remove_trade_oi_collateral(block, deps, trade)?;
@@ -356,6 +973,7 @@ fn _close_trade(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_open_order(
     deps: &mut DepsMut,
     block: &BlockInfo,
@@ -415,10 +1033,10 @@ fn update_open_order_details(
     trade_info.tp_last_updated_block = block.height;
     trade_info.sl_last_updated_block = block.height;
 
-    TRADES.save(deps.storage, (trade.user.clone(), trade.pair_index), &trade)?;
+    save_trade(deps.storage, &trade)?;
     TRADE_INFOS.save(
         deps.storage,
-        (trade.user.clone(), trade.pair_index),
+        (trade.user.clone(), trade.index),
         &trade_info,
     )?;
     Ok(())
@@ -457,10 +1075,10 @@ pub fn update_tp(
     trade.tp = new_tp;
     trade_info.tp_last_updated_block = block.height;
 
-    TRADES.save(deps.storage, (trade.user.clone(), trade.pair_index), &trade)?;
+    save_trade(deps.storage, &trade)?;
     TRADE_INFOS.save(
         deps.storage,
-        (trade.user.clone(), trade.pair_index),
+        (trade.user.clone(), trade.index),
         &trade_info,
     )?;
     Ok(Response::new().add_attribute("action", "update_tp"))
@@ -490,22 +1108,102 @@ pub fn update_sl(
     trade.sl = new_sl;
     trade_info.sl_last_updated_block = block.height;
 
-    TRADES.save(deps.storage, (trade.user.clone(), trade.pair_index), &trade)?;
+    save_trade(deps.storage, &trade)?;
     TRADE_INFOS.save(
         deps.storage,
-        (trade.user.clone(), trade.pair_index),
+        (trade.user.clone(), trade.index),
         &trade_info,
     )?;
     Ok(Response::new().add_attribute("action", "update_sl"))
 }
 
 pub fn trigger_trade(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    trader: Addr,
+    info: MessageInfo,
+    index: u64,
+    pending_order_type: PendingOrderType,
+) -> Result<Response, ContractError> {
+    check_keeper(&deps.as_ref(), &info.sender)?;
+    trigger_order(
+        deps,
+        block,
+        trader,
+        info,
+        index,
+        pending_order_type,
+        &mut HashMap::new(),
+    )
+}
+
+/// Triggers each order on its own copy of the state, which is only kept when
+/// the order goes through. The failed orders are reported in the response
+/// data instead of reverting the batch.
+pub fn trigger_trades(
+    deps: &mut DepsMut,
+    block: &BlockInfo,
+    info: MessageInfo,
+    orders: Vec<(Addr, u64, PendingOrderType)>,
+) -> Result<Response, ContractError> {
+    check_keeper(&deps.as_ref(), &info.sender)?;
+
+    let mut prices = HashMap::new();
+    let mut response = Response::new();
+    let mut results = vec![];
+    for (trader, index, pending_order_type) in orders {
+        let mut storage = SimulationStorage::new(deps.storage);
+        let result = trigger_order(
+            &mut DepsMut {
+                storage: &mut storage,
+                api: deps.api,
+                querier: deps.querier,
+            },
+            block,
+            trader.clone(),
+            info.clone(),
+            index,
+            pending_order_type,
+            &mut prices,
+        );
+        let writes = storage.into_writes();
+
+        let error = match result {
+            Ok(order_response) => {
+                for (key, value) in writes {
+                    match value {
+                        Some(value) => deps.storage.set(&key, &value),
+                        None => deps.storage.remove(&key),
+                    }
+                }
+                response.messages.extend(order_response.messages);
+                response.attributes.extend(order_response.attributes);
+                response.events.extend(order_response.events);
+                None
+            }
+            Err(err) => Some(err.to_string()),
+        };
+        results.push(TriggerTradeResult {
+            trader,
+            index,
+            error,
+        });
+    }
+
+    Ok(response.set_data(to_json_binary(&TriggerTradesResponse { results })?))
+}
+
+/// Triggers an order at the price of its pair, taken from `prices` when it
+/// was already fetched. Only liquidations go through a price that moved more
+/// than the price guard of the pair allows.
+fn trigger_order(
     deps: &mut DepsMut,
     block: &BlockInfo,
     trader: Addr,
     info: MessageInfo,
     index: u64,
     mut pending_order_type: PendingOrderType,
+    prices: &mut HashMap<u64, Price>,
 ) -> Result<Response, ContractError> {
     let is_open_limit = pending_order_type == PendingOrderType::LimitOpen
         || pending_order_type == PendingOrderType::StopOpen;
@@ -564,11 +1262,31 @@ pub fn trigger_trade(
         }
     }
 
-    let trigger_price = get_token_price(&deps.as_ref(), &trade.pair_index)?;
+    let price = match prices.get(&trade.pair_index) {
+        Some(price) => price.clone(),
+        None => {
+            let oracle_index =
+                PAIRS.load(deps.storage, trade.pair_index)?.oracle_index;
+            let price = get_token_price_data(&deps.as_ref(), oracle_index)?;
+            prices.insert(trade.pair_index, price.clone());
+            price
+        }
+    };
+    // liquidations are due precisely when the price moves a lot
+    check_price_guard(
+        deps.storage,
+        block,
+        trade.pair_index,
+        &price,
+        pending_order_type != PendingOrderType::LiqClose,
+    )?;
+    let trigger_price = price.price;
     if trigger_price.is_zero() {
         return Err(ContractError::TradeInvalid);
     }
 
+    record_trigger(deps.storage, block, &info.sender, &pending_order_type)?;
+
     match pending_order_type {
         PendingOrderType::LimitOpen | PendingOrderType::StopOpen => {
             trigger_open_order(
@@ -597,7 +1315,7 @@ pub fn trigger_trade(
 fn trigger_close_order(
     deps: &mut DepsMut,
     block: &BlockInfo,
-    _info: MessageInfo,
+    info: MessageInfo,
     trade: Trade,
     price: Decimal,
     pending_order_type: PendingOrderType,
@@ -628,6 +1346,7 @@ fn trigger_close_order(
             trade.clone(),
             profit_p,
             pending_order_type,
+            Some(&info.sender),
         )?;
 
         Ok(response.add_attribute("action", "close_trade"))
@@ -642,11 +1361,12 @@ fn unregister_trade(
     trade: Trade,
     profit_p: SignedDecimal,
     pending_order_type: PendingOrderType,
+    keeper: Option<&Addr>,
 ) -> Result<Response, ContractError> {
     let (
         mut msgs,
         vault_closing_fee_collateral,
-        _gov_staking_fee_collateral,
+        gov_staking_fee_collateral,
         trigger_fee_collateral,
         collateral_left_in_storage,
     ) = process_closing_fees(
@@ -655,6 +1375,7 @@ fn unregister_trade(
         trade.clone(),
         trade.get_position_size_collateral(),
         pending_order_type.clone(),
+        keeper,
     )?;
 
     let (trade_value_collateral, borrowing_fee_collateral) = trade
@@ -662,33 +1383,40 @@ fn unregister_trade(
             &deps.as_ref(),
             block,
             profit_p,
-            vault_closing_fee_collateral + trigger_fee_collateral,
+            get_total_closing_fees_collateral(
+                vault_closing_fee_collateral,
+                gov_staking_fee_collateral,
+                trigger_fee_collateral,
+                &pending_order_type,
+            ),
             pending_order_type,
         )?;
 
-    let (_bad_debt, pnl_message) = handle_trade_pnl(
-        COLLATERALS.load(deps.storage, trade.collateral_index)?,
+    let (bad_debt, pnl_msgs) = handle_trade_pnl(
+        &deps.as_ref(),
         trade.clone(),
-        Int128::try_from(trade_value_collateral).unwrap(),
-        Int128::try_from(collateral_left_in_storage).unwrap(),
+        trade_value_collateral,
+        u128_to_i128(collateral_left_in_storage)?,
         borrowing_fee_collateral,
     )?;
 
-    if let Some(message) = pnl_message {
+    if let Some(message) = register_bad_debt(deps, &trade, bad_debt)? {
         msgs.push(message);
     }
 
     let resp = _close_trade(deps, block, trade.user.clone(), trade.index)?;
 
-    msgs.extend(resp);
-    Ok(Response::new().add_messages(msgs))
+    msgs.extend(resp.into_iter().map(CosmosMsg::from));
+    Ok(Response::new().add_messages(msgs).add_messages(pnl_msgs))
 }
 
 /// Handles PnL (Profit and Loss) transfers when (fully or partially) closing a trade.
 ///
-/// This function processes the collateral that needs to be sent to the trader when a trade is closed. It ensures
-/// that the appropriate amount of collateral is sent, considering the available collateral and borrowing fees.
-/// If the available collateral is insufficient, it records the debt the trader owes.
+/// The trader gets `collateral_sent_to_trader` out of the `available_collateral`
+/// held for the trade. The vault is the counterparty: it pays the part of a
+/// profit the contract does not hold and receives what is left of the
+/// collateral after a loss. When the loss exceeds the collateral, the missing
+/// amount is returned as the trader debt.
 ///
 /// # Arguments
 ///
@@ -698,39 +1426,77 @@ fn unregister_trade(
 /// * `available_collateral` - The part of `collateral_sent_to_trader` that is available in the system's balance (in collateral precision).
 /// * `borrowing_fee_collateral` - The collateral amount representing the borrowing fee.
 fn handle_trade_pnl(
-    collateral_denom: String,
+    deps: &Deps,
     trade: Trade,
     collateral_sent_to_trader: Int128,
     available_collateral: Int128,
     _borrowing_fee_collateral: Uint128,
-) -> Result<(Uint128, Option<BankMsg>), ContractError> {
+) -> Result<(Uint128, Vec<CosmosMsg>), ContractError> {
+    let collateral_denom =
+        COLLATERALS.load(deps.storage, trade.collateral_index)?;
     let mut trader_debt = Uint128::zero();
-    let mut message: Option<BankMsg> = None;
+    let mut msgs: Vec<CosmosMsg> = vec![];
 
     if collateral_sent_to_trader > available_collateral {
+        let mut vault_payout = collateral_sent_to_trader;
         if !available_collateral.is_negative() {
-            message = Some(BankMsg::Send {
-                to_address: trade.user.to_string(),
-                amount: vec![Coin::new(
-                    Uint128::try_from(available_collateral).unwrap(),
-                    collateral_denom.clone(),
-                )],
-            });
+            msgs.extend(send_collateral(
+                &trade.user,
+                available_collateral.unsigned_abs(),
+                &collateral_denom,
+            ));
+            vault_payout -= available_collateral;
         } else {
             trader_debt = available_collateral.unsigned_abs();
         }
+
+        if vault_payout > Int128::zero() {
+            msgs.extend(send_from_vault(
+                deps.storage,
+                vault_payout.unsigned_abs(),
+                &collateral_denom,
+                &trade.user,
+            )?);
+        }
     } else if !collateral_sent_to_trader.is_negative() {
-        message = Some(BankMsg::Send {
-            to_address: trade.user.to_string(),
-            amount: vec![Coin::new(
-                Uint128::try_from(collateral_sent_to_trader).unwrap(),
-                collateral_denom,
-            )],
-        });
+        msgs.extend(send_collateral(
+            &trade.user,
+            collateral_sent_to_trader.unsigned_abs(),
+            &collateral_denom,
+        ));
+        msgs.extend(send_to_vault(
+            deps.storage,
+            (available_collateral - collateral_sent_to_trader).unsigned_abs(),
+            &collateral_denom,
+        )?);
     } else {
         trader_debt = collateral_sent_to_trader.unsigned_abs();
+        if !available_collateral.is_negative() {
+            msgs.extend(send_to_vault(
+                deps.storage,
+                available_collateral.unsigned_abs(),
+                &collateral_denom,
+            )?);
+        }
+    }
+    Ok((trader_debt, msgs))
+}
+
+fn send_collateral(
+    to: &Addr,
+    amount: Uint128,
+    collateral_denom: &str,
+) -> Option<CosmosMsg> {
+    if amount.is_zero() {
+        return None;
     }
-    Ok((trader_debt, message))
+    Some(
+        BankMsg::Send {
+            to_address: to.to_string(),
+            amount: vec![Coin::new(amount, collateral_denom)],
+        }
+        .into(),
+    )
 }
 
 fn is_hit(
@@ -766,7 +1532,12 @@ fn trigger_open_order(
         deps.as_ref(),
         block,
         get_usd_normalized_value(
-            get_collateral_price_usd(&deps.as_ref(), trade.collateral_index)?,
+            get_guarded_collateral_price(
+                &deps.as_ref(),
+                block,
+                trade.collateral_index,
+                trade.pair_index,
+            )?,
             trade.get_position_size_collateral(),
         )?,
         trigger_price,
@@ -788,11 +1559,799 @@ fn trigger_open_order(
         return Err(ContractError::InvalidTriggerPrice);
     }
 
-    close_trade(deps, block, info, trade.index)?;
+    // the order collateral stays in the contract as the trade collateral
+    let mut order = trade.clone();
+    order.is_open = false;
+    update_trader_pair_counts(deps.storage, &order, false)?;
+    save_trade(deps.storage, &order)?;
 
     // register the market trade
     trade.open_price = price_after_impact;
     trade.trade_type = TradeType::Trade;
 
-    register_trade(deps, block, trade, trade_info, OpenOrderType::MARKET)
+    let referrer = get_trader_referrer(&deps.as_ref(), &trade.user)?;
+    register_trade(
+        deps,
+        block,
+        trade,
+        trade_info,
+        Some(&info.sender),
+        referrer.as_ref(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::borrowing::state::PAIR_OIS;
+    use crate::contract::execute_admin;
+    use crate::fees::state::{
+        BadDebt, BAD_DEBTS, INSURANCE_FUNDS, PENDING_GOV_FEES,
+    };
+    use crate::msgs::AdminExecuteMsg;
+    use crate::price_impact::state::{
+        OiWindowsSettings, OI_WINDOWS_SETTINGS, WINDOWS,
+    };
+    use crate::test_utils::{
+        default_trade, mock_block, mock_deps, set_oracle_price, setup_market,
+        store_open_trade, COLLATERAL_DENOM, TRADER,
+    };
+    use cosmwasm_std::from_json;
+    use cosmwasm_std::testing::{message_info, mock_env};
+    use vault::contract::VaultExecuteMsg;
+
+    /// Collateral sent by the contract, by receiver, including what is sent
+    /// to the vault assets.
+    fn bank_sends(resp: &Response) -> Vec<(String, Uint128)> {
+        resp.messages
+            .iter()
+            .filter_map(|msg| match &msg.msg {
+                CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => {
+                    assert_eq!(amount[0].denom, COLLATERAL_DENOM);
+                    Some((to_address.clone(), amount[0].amount))
+                }
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr,
+                    msg,
+                    funds,
+                }) => match from_json(msg) {
+                    Ok(VaultExecuteMsg::ReceiveAssets {}) => {
+                        assert_eq!(funds[0].denom, COLLATERAL_DENOM);
+                        Some((contract_addr.clone(), funds[0].amount))
+                    }
+                    _ => None,
+                },
+                CosmosMsg::Wasm(_) => None,
+                _ => panic!("unexpected message {:?}", msg),
+            })
+            .collect()
+    }
+
+    /// Payouts requested from the vault, by receiver.
+    fn vault_payouts(resp: &Response) -> Vec<(String, Uint128)> {
+        resp.messages
+            .iter()
+            .filter_map(|msg| match &msg.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => {
+                    assert_eq!(contract_addr, "vault");
+                    match from_json(msg).unwrap() {
+                        VaultExecuteMsg::SendAssets {
+                            denom,
+                            amount,
+                            receiver,
+                        } => {
+                            assert_eq!(denom, COLLATERAL_DENOM);
+                            Some((receiver, amount))
+                        }
+                        VaultExecuteMsg::ReceiveAssets {} => None,
+                        msg => panic!("unexpected vault message {:?}", msg),
+                    }
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_partial_close_keeps_remaining_position() {
+        let mut deps = mock_deps(Decimal::from_ratio(110_u64, 1_u64));
+        let block = mock_block(10);
+        let mut trade = default_trade(0);
+        trade.tp = Decimal::from_ratio(150_u64, 1_u64);
+        trade.sl = Decimal::from_ratio(95_u64, 1_u64);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &trade);
+
+        let info = message_info(&Addr::unchecked(TRADER), &[]);
+        let resp = close_trade_market(
+            &mut deps.as_mut(),
+            &block,
+            info,
+            0,
+            Some(Uint128::new(400_000)),
+        )
+        .unwrap();
+
+        // closed position is 4_000_000: 0.1% close fee split with the vault
+        // and the 0.02% trigger fee to staking for market closes
+        assert_eq!(
+            bank_sends(&resp),
+            vec![
+                ("vault".to_string(), Uint128::new(2_000)),
+                ("staking".to_string(), Uint128::new(800)),
+                (TRADER.to_string(), Uint128::new(397_200)),
+            ]
+        );
+        // the +100% profit not held by the contract is paid by the vault
+        assert_eq!(
+            vault_payouts(&resp),
+            vec![(TRADER.to_string(), Uint128::new(400_000))]
+        );
+
+        let remaining = TRADES
+            .load(deps.as_ref().storage, (Addr::unchecked(TRADER), 0))
+            .unwrap();
+        assert!(remaining.is_open);
+        assert_eq!(remaining.collateral_amount, Uint128::new(600_000));
+        assert_eq!(remaining.open_price, trade.open_price);
+        assert_eq!(remaining.tp, trade.tp);
+        assert_eq!(remaining.sl, trade.sl);
+
+        let pair_oi = PAIR_OIS.load(deps.as_ref().storage, (0, 0)).unwrap();
+        assert_eq!(pair_oi.long, Uint128::new(6_000_000));
+    }
+
+    #[test]
+    fn test_close_whole_trade_without_delta() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+        let info = message_info(&Addr::unchecked(TRADER), &[]);
+        close_trade_market(&mut deps.as_mut(), &block, info, 0, None).unwrap();
+
+        let trade = TRADES
+            .load(deps.as_ref().storage, (Addr::unchecked(TRADER), 0))
+            .unwrap();
+        assert!(!trade.is_open);
+        let pair_oi = PAIR_OIS.load(deps.as_ref().storage, (0, 0)).unwrap();
+        assert_eq!(pair_oi.long, Uint128::zero());
+    }
+
+    #[test]
+    fn test_partial_close_invalid_delta() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+        for delta in [Uint128::zero(), Uint128::new(1_000_001)] {
+            let info = message_info(&Addr::unchecked(TRADER), &[]);
+            let err = close_trade_market(
+                &mut deps.as_mut(),
+                &block,
+                info,
+                0,
+                Some(delta),
+            )
+            .unwrap_err();
+            assert_eq!(err, ContractError::InvalidCollateralDelta);
+        }
+    }
+
+    #[test]
+    fn test_increase_position_averages_open_price() {
+        let mut deps = mock_deps(Decimal::from_ratio(110_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        OI_WINDOWS_SETTINGS
+            .save(
+                deps.as_mut().storage,
+                &OiWindowsSettings {
+                    start_ts: 0,
+                    windows_duration: 3600,
+                    windows_count: 3,
+                },
+            )
+            .unwrap();
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+        let info = message_info(
+            &Addr::unchecked(TRADER),
+            &[Coin::new(1_000_000_u128, COLLATERAL_DENOM)],
+        );
+        let mut env = mock_env();
+        env.block = block.clone();
+        let resp = increase_position(
+            &mut deps.as_mut(),
+            &env,
+            info,
+            0,
+            Uint128::new(1_000_000),
+            Uint128::new(10),
+        )
+        .unwrap();
+
+        // 0.1% open fee is paid twice (staking and pending gov fees) and the
+        // 0.02% trigger fee goes to staking on the added 10_000_000 position
+        assert_eq!(
+            bank_sends(&resp),
+            vec![("staking".to_string(), Uint128::new(12_000))]
+        );
+        assert_eq!(
+            PENDING_GOV_FEES.load(deps.as_ref().storage, 0).unwrap(),
+            Uint128::new(10_000)
+        );
+
+        let trade = TRADES
+            .load(deps.as_ref().storage, (Addr::unchecked(TRADER), 0))
+            .unwrap();
+        // the 20_000_000 requested size is stored as 1_978_000 at 10x, the
+        // open price is averaged over the 9_780_000 actually added at 110
+        assert_eq!(
+            trade.open_price,
+            Decimal::from_ratio(2_075_800_000_u64, 19_780_000_u64)
+        );
+        assert_eq!(trade.collateral_amount, Uint128::new(1_978_000));
+        assert_eq!(trade.leverage, Uint128::new(10));
+
+        let pair_oi = PAIR_OIS.load(deps.as_ref().storage, (0, 0)).unwrap();
+        assert_eq!(pair_oi.long, Uint128::new(19_780_000));
+
+        let window_id = block.time.seconds() / 3600;
+        let window = WINDOWS
+            .load(deps.as_ref().storage, (0, window_id, 3))
+            .unwrap();
+        assert_eq!(window.oi_long_usd, Uint128::new(9_780_000));
+    }
+
+    #[test]
+    fn test_increase_position_checks() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+        let funds = [Coin::new(1_000_000_u128, COLLATERAL_DENOM)];
+        let test_cases = vec![
+            (
+                "funds do not match the collateral",
+                Uint128::new(2_000_000),
+                Uint128::new(10),
+                ContractError::InvalidFunds,
+            ),
+            (
+                "leverage above the group max",
+                Uint128::new(1_000_000),
+                Uint128::new(101),
+                ContractError::InvalidLeverage,
+            ),
+            (
+                "added size above the pair max oi",
+                Uint128::new(1_000_000),
+                Uint128::new(100),
+                ContractError::ExposureLimitReached,
+            ),
+        ];
+
+        let mut pair_oi = PAIR_OIS.load(deps.as_ref().storage, (0, 0)).unwrap();
+        pair_oi.max = Uint128::new(50_000_000);
+        PAIR_OIS
+            .save(deps.as_mut().storage, (0, 0), &pair_oi)
+            .unwrap();
+
+        let mut env = mock_env();
+        env.block = block.clone();
+        for (description, collateral, leverage, expected) in test_cases {
+            let info = message_info(&Addr::unchecked(TRADER), &funds);
+            let err = increase_position(
+                &mut deps.as_mut(),
+                &env,
+                info,
+                0,
+                collateral,
+                leverage,
+            )
+            .unwrap_err();
+            assert_eq!(err, expected, "Failed test: {}", description);
+        }
+
+        // a 10x long opened at 112 is past its liquidation price at 100, the
+        // increase brings it to 18x around 106, still past it
+        let mut losing_trade = default_trade(1);
+        losing_trade.open_price = Decimal::from_ratio(112_u64, 1_u64);
+        store_open_trade(&mut deps.as_mut(), &block, &losing_trade);
+        let info = message_info(
+            &Addr::unchecked(TRADER),
+            &[Coin::new(100_000_u128, COLLATERAL_DENOM)],
+        );
+        let err = increase_position(
+            &mut deps.as_mut(),
+            &env,
+            info,
+            1,
+            Uint128::new(100_000),
+            Uint128::new(100),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LiquidationPriceReached);
+    }
+
+    #[test]
+    fn test_deposit_margin_lowers_leverage() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+        let info = message_info(
+            &Addr::unchecked(TRADER),
+            &[Coin::new(500_000_u128, COLLATERAL_DENOM)],
+        );
+        let err = update_trade_margin(
+            &mut deps.as_mut(),
+            &block,
+            info,
+            0,
+            Uint128::new(5),
+            true,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidFunds);
+
+        let info = message_info(
+            &Addr::unchecked(TRADER),
+            &[Coin::new(1_000_000_u128, COLLATERAL_DENOM)],
+        );
+        let resp = update_trade_margin(
+            &mut deps.as_mut(),
+            &block,
+            info,
+            0,
+            Uint128::new(5),
+            true,
+        )
+        .unwrap();
+        assert!(resp.messages.is_empty());
+
+        let trade = TRADES
+            .load(deps.as_ref().storage, (Addr::unchecked(TRADER), 0))
+            .unwrap();
+        assert_eq!(trade.collateral_amount, Uint128::new(2_000_000));
+        assert_eq!(trade.leverage, Uint128::new(5));
+        assert_eq!(
+            trade.get_position_size_collateral(),
+            Uint128::new(10_000_000)
+        );
+
+        let pair_oi = PAIR_OIS.load(deps.as_ref().storage, (0, 0)).unwrap();
+        assert_eq!(pair_oi.long, Uint128::new(10_000_000));
+    }
+
+    #[test]
+    fn test_withdraw_margin_keeps_position_size() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+        let info = message_info(&Addr::unchecked(TRADER), &[]);
+        let resp = update_trade_margin(
+            &mut deps.as_mut(),
+            &block,
+            info,
+            0,
+            Uint128::new(30),
+            false,
+        )
+        .unwrap();
+
+        // 10_000_000 / 30 leaves 333_333 collateral
+        assert_eq!(
+            bank_sends(&resp),
+            vec![(TRADER.to_string(), Uint128::new(666_667))]
+        );
+        let trade = TRADES
+            .load(deps.as_ref().storage, (Addr::unchecked(TRADER), 0))
+            .unwrap();
+        assert_eq!(trade.collateral_amount, Uint128::new(333_333));
+        assert_eq!(trade.leverage, Uint128::new(30));
+
+        let pair_oi = PAIR_OIS.load(deps.as_ref().storage, (0, 0)).unwrap();
+        assert_eq!(pair_oi.long, Uint128::new(9_999_990));
+    }
+
+    #[test]
+    fn test_withdraw_margin_checks() {
+        let test_cases = vec![
+            (
+                "leverage not above the current one",
+                Decimal::from_ratio(100_u64, 1_u64),
+                Uint128::new(10),
+                ContractError::InvalidLeverage,
+            ),
+            (
+                "leverage above the group max",
+                Decimal::from_ratio(100_u64, 1_u64),
+                Uint128::new(101),
+                ContractError::InvalidLeverage,
+            ),
+            (
+                "leverage above the pair custom max",
+                Decimal::from_ratio(100_u64, 1_u64),
+                Uint128::new(60),
+                ContractError::InvalidLeverage,
+            ),
+            (
+                // liquidation price at 20x is 95.62
+                "price past the new liquidation price",
+                Decimal::from_ratio(95_u64, 1_u64),
+                Uint128::new(20),
+                ContractError::LiquidationPriceReached,
+            ),
+        ];
+
+        for (description, price, leverage, expected) in test_cases {
+            let mut deps = mock_deps(price);
+            let block = mock_block(10);
+            setup_market(&mut deps.as_mut());
+            PAIR_CUSTOM_MAX_LEVERAGE
+                .save(deps.as_mut().storage, 0, &Uint128::new(50))
+                .unwrap();
+            store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+
+            let info = message_info(&Addr::unchecked(TRADER), &[]);
+            let err = update_trade_margin(
+                &mut deps.as_mut(),
+                &block,
+                info,
+                0,
+                leverage,
+                false,
+            )
+            .unwrap_err();
+            assert_eq!(err, expected, "Failed test: {}", description);
+        }
+    }
+
+    #[test]
+    fn test_trade_indices_are_never_reused() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+
+        for _ in 0..2 {
+            open_trade(
+                &mut deps.as_mut(),
+                &block,
+                default_trade(0),
+                OpenOrderType::MARKET,
+                Decimal::percent(1),
+                None,
+            )
+            .unwrap();
+        }
+
+        let info = message_info(&Addr::unchecked(TRADER), &[]);
+        close_trade_market(&mut deps.as_mut(), &block, info, 0, None).unwrap();
+
+        open_trade(
+            &mut deps.as_mut(),
+            &block,
+            default_trade(0),
+            OpenOrderType::MARKET,
+            Decimal::percent(1),
+            None,
+        )
+        .unwrap();
+
+        let trader = Addr::unchecked(TRADER);
+        let is_open = |index: u64| {
+            TRADES
+                .load(deps.as_ref().storage, (trader.clone(), index))
+                .unwrap()
+                .is_open
+        };
+        assert!(!is_open(0));
+        assert!(is_open(1));
+        assert!(is_open(2));
+        assert_eq!(
+            TRADES
+                .load(deps.as_ref().storage, (trader.clone(), 2))
+                .unwrap()
+                .index,
+            2
+        );
+        assert!(TRADE_INFOS.has(deps.as_ref().storage, (trader.clone(), 2)));
+        assert_eq!(
+            TRADER_PAIR_COUNTS
+                .load(deps.as_ref().storage, (trader.clone(), 0))
+                .unwrap()
+                .trades,
+            2
+        );
+        assert_eq!(
+            USER_COUNTERS.load(deps.as_ref().storage, trader).unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_updates_are_keyed_by_trade_index() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        let mut limit_order = default_trade(0);
+        limit_order.trade_type = TradeType::Limit;
+        limit_order.open_price = Decimal::from_ratio(90_u64, 1_u64);
+
+        // trade 0 and trade 1 on pair 0, limit order 2 on pair 0
+        for trade in [default_trade(0), default_trade(0), limit_order] {
+            open_trade(
+                &mut deps.as_mut(),
+                &block,
+                trade,
+                OpenOrderType::MARKET,
+                Decimal::percent(1),
+                None,
+            )
+            .unwrap();
+        }
+
+        let trader = Addr::unchecked(TRADER);
+        let first_trade = TRADES
+            .load(deps.as_ref().storage, (trader.clone(), 0))
+            .unwrap();
+        let info = message_info(&trader, &[]);
+        let tp = Decimal::from_ratio(120_u64, 1_u64);
+        let sl = Decimal::from_ratio(95_u64, 1_u64);
+        update_tp(&mut deps.as_mut(), &mock_block(11), info.clone(), 1, tp)
+            .unwrap();
+        update_sl(&mut deps.as_mut(), &mock_block(12), info.clone(), 1, sl)
+            .unwrap();
+        update_open_order(
+            &mut deps.as_mut(),
+            &mock_block(13),
+            info,
+            2,
+            Decimal::from_ratio(85_u64, 1_u64),
+            tp,
+            Decimal::zero(),
+            Decimal::percent(2),
+        )
+        .unwrap();
+
+        let load = |index: u64| {
+            let key = (trader.clone(), index);
+            (
+                TRADES.load(deps.as_ref().storage, key.clone()).unwrap(),
+                TRADE_INFOS.load(deps.as_ref().storage, key).unwrap(),
+            )
+        };
+        let (trade, trade_info) = load(1);
+        assert_eq!(trade.index, 1);
+        assert_eq!((trade.tp, trade.sl), (tp, sl));
+        assert_eq!(trade_info.tp_last_updated_block, 11);
+        assert_eq!(trade_info.sl_last_updated_block, 12);
+
+        let (order, order_info) = load(2);
+        assert_eq!(order.index, 2);
+        assert_eq!(order.open_price, Decimal::from_ratio(85_u64, 1_u64));
+        assert_eq!(order.tp, tp);
+        assert_eq!(order_info.max_slippage_p, Decimal::percent(2));
+
+        // the trade stored under the pair index is left untouched
+        assert_eq!(load(0).0, first_trade);
+    }
+
+    #[test]
+    fn test_max_trades_and_pending_orders_per_pair() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        execute_admin(
+            &mut deps.as_mut(),
+            &block,
+            AdminExecuteMsg::UpdateMaxTradesPerPair {
+                max_trades_per_pair: 2,
+            },
+        )
+        .unwrap();
+        execute_admin(
+            &mut deps.as_mut(),
+            &block,
+            AdminExecuteMsg::UpdateMaxPendingOrders {
+                max_pending_orders: 1,
+            },
+        )
+        .unwrap();
+
+        let mut limit_order = default_trade(0);
+        limit_order.trade_type = TradeType::Limit;
+        limit_order.open_price = Decimal::from_ratio(90_u64, 1_u64);
+
+        let test_cases = vec![
+            ("first trade", default_trade(0), None),
+            ("second trade", default_trade(0), None),
+            (
+                "third trade",
+                default_trade(0),
+                Some(ContractError::MaxTradesPerPair),
+            ),
+            ("first limit order", limit_order.clone(), None),
+            (
+                "second limit order",
+                limit_order,
+                Some(ContractError::MaxPendingOrders),
+            ),
+        ];
+
+        for (description, trade, expected) in test_cases {
+            let result = open_trade(
+                &mut deps.as_mut(),
+                &block,
+                trade,
+                OpenOrderType::MARKET,
+                Decimal::percent(1),
+                None,
+            );
+            assert_eq!(result.err(), expected, "Failed test: {}", description);
+        }
+    }
+
+    #[test]
+    fn test_funding_fees_paid_by_heavier_side() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        execute_admin(
+            &mut deps.as_mut(),
+            &block,
+            AdminExecuteMsg::UpdatePairFundingFees {
+                pair_funding_fees: vec![(
+                    (0, 0),
+                    Decimal::from_ratio(1_u64, 1_000_000_u64),
+                )],
+            },
+        )
+        .unwrap();
+
+        let long_trade = default_trade(0);
+        let mut short_trade = default_trade(0);
+        short_trade.user = Addr::unchecked("other");
+        short_trade.long = false;
+        short_trade.collateral_amount = Uint128::new(500_000);
+        store_open_trade(&mut deps.as_mut(), &block, &long_trade);
+        store_open_trade(&mut deps.as_mut(), &block, &short_trade);
+
+        // 10_000_000 long vs 5_000_000 short, longs pay 1e-6 of the
+        // 5_000_000 skew per block
+        let later = mock_block(110);
+        let test_cases = vec![
+            ("long pays", long_trade, 500, 999_500),
+            ("short receives", short_trade, -500, 500_500),
+        ];
+        for (description, trade, expected_funding, expected_value) in test_cases
+        {
+            let funding = trade
+                .get_trade_funding_fees_collateral(&deps.as_ref(), &later)
+                .unwrap();
+            assert_eq!(
+                funding,
+                Int128::new(expected_funding),
+                "Failed test: {}",
+                description
+            );
+
+            let (value, _) = trade
+                .get_trade_value_collateral(
+                    &deps.as_ref(),
+                    &later,
+                    SignedDecimal::zero(),
+                    Uint128::zero(),
+                    PendingOrderType::Market,
+                )
+                .unwrap();
+            assert_eq!(
+                value,
+                Int128::new(expected_value),
+                "Failed test: {}",
+                description
+            );
+        }
+    }
+
+    #[test]
+    fn test_funding_fee_update_keeps_accrued_funding() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        let update_fee = |deps: &mut DepsMut, block: &BlockInfo, fee| {
+            execute_admin(
+                deps,
+                block,
+                AdminExecuteMsg::UpdatePairFundingFees {
+                    pair_funding_fees: vec![((0, 0), fee)],
+                },
+            )
+            .unwrap()
+        };
+        update_fee(
+            &mut deps.as_mut(),
+            &block,
+            Decimal::from_ratio(1_u64, 1_000_000_u64),
+        );
+        let long_trade = default_trade(0);
+        store_open_trade(&mut deps.as_mut(), &block, &long_trade);
+
+        // 50 blocks at 1e-6 of the 10_000_000 skew, then no funding
+        let resp =
+            update_fee(&mut deps.as_mut(), &mock_block(60), Decimal::zero());
+        assert_eq!(resp.events[0].ty, "funding_fee_per_block_p_updated");
+        let funding = long_trade
+            .get_trade_funding_fees_collateral(&deps.as_ref(), &mock_block(110))
+            .unwrap();
+        assert_eq!(funding, Int128::new(500));
+    }
+
+    #[test]
+    fn test_bad_debt_covered_by_insurance_fund() {
+        let mut deps = mock_deps(Decimal::from_ratio(100_u64, 1_u64));
+        let block = mock_block(10);
+        setup_market(&mut deps.as_mut());
+        execute_admin(
+            &mut deps.as_mut(),
+            &block,
+            AdminExecuteMsg::UpdateInsuranceFundFeeP {
+                insurance_fund_fee_p: Decimal::percent(50),
+            },
+        )
+        .unwrap();
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(0));
+        store_open_trade(&mut deps.as_mut(), &block, &default_trade(1));
+
+        // -110% is capped at -100%, the 5_000 vault and 2_000 gov closing
+        // fees are bad debt, half of them funds the insurance fund and the
+        // rest of the collateral is lost to the vault
+        set_oracle_price(&mut deps, Decimal::from_ratio(89_u64, 1_u64));
+        let info = message_info(&Addr::unchecked(TRADER), &[]);
+        let resp = close_trade_market(&mut deps.as_mut(), &block, info, 1, None)
+            .unwrap();
+        assert_eq!(
+            bank_sends(&resp),
+            vec![
+                ("vault".to_string(), Uint128::new(2_500)),
+                ("staking".to_string(), Uint128::new(1_000)),
+                ("vault".to_string(), Uint128::new(3_500)),
+                ("vault".to_string(), Uint128::new(993_000)),
+            ]
+        );
+        assert_eq!(
+            BAD_DEBTS.load(deps.as_ref().storage, 0).unwrap(),
+            BadDebt {
+                cumulative: Uint128::new(7_000),
+                covered: Uint128::new(3_500),
+            }
+        );
+        assert_eq!(
+            INSURANCE_FUNDS.load(deps.as_ref().storage, 0).unwrap(),
+            Uint128::zero()
+        );
+
+        // the next fees cover the rest
+        set_oracle_price(&mut deps, Decimal::from_ratio(100_u64, 1_u64));
+        let info = message_info(&Addr::unchecked(TRADER), &[]);
+        close_trade_market(&mut deps.as_mut(), &block, info, 0, None).unwrap();
+        assert_eq!(
+            BAD_DEBTS.load(deps.as_ref().storage, 0).unwrap().covered,
+            Uint128::new(7_000)
+        );
+        assert_eq!(
+            INSURANCE_FUNDS.load(deps.as_ref().storage, 0).unwrap(),
+            Uint128::zero()
+        );
+    }
 }
@@ -1,11 +1,16 @@
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{
-    Addr, BlockInfo, Decimal, Deps, SignedDecimal, Timestamp, Uint128,
+    Addr, BlockInfo, Decimal, Deps, Empty, Int128, Order, SignedDecimal,
+    StdResult, Storage, Timestamp, Uint128,
 };
 use cw_storage_plus::{Item, Map};
+use std::collections::BTreeMap;
 
 use crate::{
-    borrowing::{get_trade_borrowing_fees, state::BorrowingFeeInput},
+    borrowing::{
+        get_trade_borrowing_fees, get_trade_funding_fees,
+        state::{BorrowingFeeInput, INITIAL_ACC_FEES},
+    },
     constants::{LIQ_THRESHOLD_P, MAX_OPEN_NEGATIVE_PNL_P},
     error::ContractError,
     pairs::state::PAIRS,
@@ -22,7 +27,221 @@ pub const COLLATERALS: Map<u64, String> = Map::new("collaterals");
 pub const TRADES: Map<(Addr, u64), Trade> = Map::new("trades");
 pub const TRADE_INFOS: Map<(Addr, u64), TradeInfo> = Map::new("trade_infos");
 pub const TRADER_STORED: Map<Addr, bool> = Map::new("trader_stored");
+/// Indices of the open trades of each trader, so that listing them does not
+/// read the closed ones.
+pub const OPEN_TRADES: Map<(Addr, u64), Empty> = Map::new("open_trades");
+/// Indices of the pending limit and stop orders of each trader.
+pub const PENDING_ORDERS: Map<(Addr, u64), Empty> = Map::new("pending_orders");
+/// Next trade index of each trader, indices are never reused.
 pub const USER_COUNTERS: Map<Addr, u64> = Map::new("user_counters");
+/// Open trades and pending orders of a trader on a pair.
+pub const TRADER_PAIR_COUNTS: Map<(Addr, u64), TraderPairCounts> =
+    Map::new("trader_pair_counts");
+pub const MAX_TRADES_PER_PAIR: Item<u64> = Item::new("max_trades_per_pair");
+pub const MAX_PENDING_ORDERS: Item<u64> = Item::new("max_pending_orders");
+
+/// Position sizes of the open trades of a pair, in collateral and divided by
+/// their open price, from which their aggregate PnL at a price is derived.
+#[cw_serde]
+#[derive(Default)]
+pub struct OpenPositions {
+    pub long_collateral: Uint128,
+    pub long_per_price: Decimal,
+    pub short_collateral: Uint128,
+    pub short_per_price: Decimal,
+}
+
+impl OpenPositions {
+    /// Unrealized PnL of the positions at `price`, positive when traders are
+    /// in profit. Trade PnLs are not capped at their max gain or collateral.
+    pub fn pnl_collateral(
+        &self,
+        price: Decimal,
+    ) -> Result<Int128, ContractError> {
+        let long_value = price.checked_mul(self.long_per_price)?.to_uint_floor();
+        let short_value =
+            price.checked_mul(self.short_per_price)?.to_uint_ceil();
+        Ok(u128_to_i128(long_value)?
+            .checked_sub(u128_to_i128(self.long_collateral)?)?
+            .checked_add(u128_to_i128(self.short_collateral)?)?
+            .checked_sub(u128_to_i128(short_value)?)?)
+    }
+}
+
+/// Open positions per collateral and pair.
+pub const PAIR_OPEN_POSITIONS: Map<(u64, u64), OpenPositions> =
+    Map::new("pair_open_positions");
+
+/// Saves the trade, keeps it in the open trades or pending orders of its
+/// trader while it is open and in the open positions of its pair while it is
+/// an open market trade.
+pub(crate) fn save_trade(
+    storage: &mut dyn Storage,
+    trade: &Trade,
+) -> Result<(), ContractError> {
+    save_trade_at(storage, (trade.user.clone(), trade.index), trade)
+}
+
+pub(crate) fn save_trade_at(
+    storage: &mut dyn Storage,
+    key: (Addr, u64),
+    trade: &Trade,
+) -> Result<(), ContractError> {
+    if let Some(previous) = TRADES.may_load(storage, key.clone())? {
+        update_open_positions(storage, &previous, false)?;
+    }
+    update_open_positions(storage, trade, true)?;
+    TRADES.save(storage, key, trade)?;
+    Ok(index_trade(storage, trade)?)
+}
+
+/// Adds an open market trade to the open positions of its pair, or removes
+/// it, other trades are ignored.
+fn update_open_positions(
+    storage: &mut dyn Storage,
+    trade: &Trade,
+    add: bool,
+) -> Result<(), ContractError> {
+    if !trade.is_open || trade.trade_type != TradeType::Trade {
+        return Ok(());
+    }
+    let size = trade.get_position_size_collateral();
+    let size_per_price = u128_to_dec(size)?.checked_div(trade.open_price)?;
+
+    let key = (trade.collateral_index, trade.pair_index);
+    let mut positions = PAIR_OPEN_POSITIONS
+        .may_load(storage, key)?
+        .unwrap_or_default();
+    let (collateral, per_price) = if trade.long {
+        (
+            &mut positions.long_collateral,
+            &mut positions.long_per_price,
+        )
+    } else {
+        (
+            &mut positions.short_collateral,
+            &mut positions.short_per_price,
+        )
+    };
+    if add {
+        *collateral = collateral.checked_add(size)?;
+        *per_price = per_price.checked_add(size_per_price)?;
+    } else {
+        *collateral = collateral.checked_sub(size)?;
+        *per_price = per_price.checked_sub(size_per_price)?;
+    }
+    PAIR_OPEN_POSITIONS.save(storage, key, &positions)?;
+    Ok(())
+}
+
+pub(crate) fn index_trade(
+    storage: &mut dyn Storage,
+    trade: &Trade,
+) -> StdResult<()> {
+    let key = (trade.user.clone(), trade.index);
+    OPEN_TRADES.remove(storage, key.clone());
+    PENDING_ORDERS.remove(storage, key.clone());
+    if !trade.is_open {
+        return Ok(());
+    }
+    if trade.trade_type == TradeType::Trade {
+        OPEN_TRADES.save(storage, key, &Empty {})
+    } else {
+        PENDING_ORDERS.save(storage, key, &Empty {})
+    }
+}
+
+/// Moves the trades stored before they were keyed by index, under
+/// `(user, pair_index)`, to `(user, index)` with their trade info. Their
+/// borrowing and funding snapshots are already keyed by index. A legacy
+/// trade whose index is taken, as closes used to give indices back, gets the
+/// next free one. The counters and indices of every trader and the open
+/// positions of every pair are then rebuilt from the stored trades.
+pub(crate) fn migrate_trades(
+    storage: &mut dyn Storage,
+) -> Result<(), ContractError> {
+    let trades = TRADES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut next_indices: BTreeMap<Addr, u64> = BTreeMap::new();
+    let mut legacy_trades = vec![];
+    for ((user, key), trade) in trades {
+        let next_index = next_indices.entry(user.clone()).or_insert(
+            USER_COUNTERS.may_load(storage, user.clone())?.unwrap_or(0),
+        );
+        *next_index = (*next_index).max(key + 1).max(trade.index + 1);
+        if key != trade.index {
+            let trade_info =
+                TRADE_INFOS.may_load(storage, (user.clone(), key))?;
+            TRADES.remove(storage, (user.clone(), key));
+            TRADE_INFOS.remove(storage, (user, key));
+            legacy_trades.push((trade, trade_info));
+        }
+    }
+
+    // open trades keep their index over the closed ones
+    legacy_trades.sort_by_key(|(trade, _)| !trade.is_open);
+    for (mut trade, trade_info) in legacy_trades {
+        if TRADES.has(storage, (trade.user.clone(), trade.index)) {
+            let next_index = next_indices.entry(trade.user.clone()).or_default();
+            let acc_fees_key =
+                |index| (trade.collateral_index, trade.user.clone(), index);
+            if let Some(acc_fees) =
+                INITIAL_ACC_FEES.may_load(storage, acc_fees_key(trade.index))?
+            {
+                INITIAL_ACC_FEES.save(
+                    storage,
+                    acc_fees_key(*next_index),
+                    &acc_fees,
+                )?;
+            }
+            trade.index = *next_index;
+            *next_index += 1;
+        }
+        let key = (trade.user.clone(), trade.index);
+        TRADES.save(storage, key.clone(), &trade)?;
+        if let Some(trade_info) = trade_info {
+            TRADE_INFOS.save(storage, key, &trade_info)?;
+        }
+    }
+
+    for (user, next_index) in next_indices {
+        USER_COUNTERS.save(storage, user, &next_index)?;
+    }
+
+    let count_keys = TRADER_PAIR_COUNTS
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for key in count_keys {
+        TRADER_PAIR_COUNTS.remove(storage, key);
+    }
+    PAIR_OPEN_POSITIONS.clear(storage);
+    let trades = TRADES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, trade) in trades {
+        index_trade(storage, &trade)?;
+        update_open_positions(storage, &trade, true)?;
+        if !trade.is_open {
+            continue;
+        }
+        TRADER_PAIR_COUNTS.update(
+            storage,
+            (trade.user.clone(), trade.pair_index),
+            |counts| -> StdResult<_> {
+                let mut counts = counts.unwrap_or_default();
+                if trade.trade_type == TradeType::Trade {
+                    counts.trades += 1;
+                } else {
+                    counts.pending_orders += 1;
+                }
+                Ok(counts)
+            },
+        )?;
+    }
+    Ok(())
+}
 
 // todo: make message for this
 pub const TRADING_ACTIVATED: Item<TradingActivated> =
@@ -35,11 +254,17 @@ pub enum TradingActivated {
     Paused,
 }
 
+#[cw_serde]
+#[derive(Default)]
+pub struct TraderPairCounts {
+    pub trades: u64,
+    pub pending_orders: u64,
+}
+
 #[cw_serde]
 pub struct Trader {
     pub leverage_unlocked: u64,
     pub referral: Addr,
-    pub referral_rewards_total: u128,
 }
 
 #[cw_serde]
@@ -73,29 +298,33 @@ impl Trade {
         percent_profit: SignedDecimal,
         closing_fee_collateral: Uint128,
         order_type: PendingOrderType,
-    ) -> Result<(Uint128, Uint128), ContractError> {
+    ) -> Result<(Int128, Uint128), ContractError> {
         let borrowing_fees_collateral =
             self.get_trade_borrowing_fees_collateral(deps, block)?;
-
-        let value_collateral = if order_type == PendingOrderType::LiqClose {
-            Uint128::zero()
+        let funding_fees_collateral =
+            self.get_trade_funding_fees_collateral(deps, block)?;
+
+        let value = u128_to_i128(self.collateral_amount)?
+            + (u128_to_sdec(self.collateral_amount)?
+                .checked_mul(percent_profit)?
+                .to_int_floor())
+            .checked_sub(u128_to_i128(borrowing_fees_collateral)?)?
+            .checked_sub(funding_fees_collateral)?
+            .checked_sub(u128_to_i128(closing_fee_collateral)?)?;
+
+        let collateral_liq_threshold = u128_to_dec(self.collateral_amount)?
+            .checked_mul(Decimal::one().checked_sub(LIQ_THRESHOLD_P)?)?
+            .to_uint_floor();
+
+        // a negative value is the loss the collateral could not cover, a
+        // liquidated trade or one below the liq threshold gets nothing back
+        let value_collateral = if !value.is_negative()
+            && (order_type == PendingOrderType::LiqClose
+                || value <= u128_to_i128(collateral_liq_threshold)?)
+        {
+            Int128::zero()
         } else {
-            let value = u128_to_i128(self.collateral_amount)?
-                + (u128_to_sdec(self.collateral_amount)?
-                    .checked_mul(percent_profit)?
-                    .to_int_floor())
-                .checked_sub(u128_to_i128(borrowing_fees_collateral)?)?
-                .checked_sub(u128_to_i128(closing_fee_collateral)?)?;
-
-            let collateral_liq_threshold = u128_to_dec(self.collateral_amount)?
-                .checked_mul(Decimal::one().checked_sub(LIQ_THRESHOLD_P)?)?
-                .to_uint_floor();
-
-            if value.i128() > collateral_liq_threshold.u128() as i128 {
-                Uint128::try_from(value).unwrap()
-            } else {
-                Uint128::zero()
-            }
+            value
         };
 
         Ok((value_collateral, borrowing_fees_collateral))
@@ -171,12 +400,25 @@ impl Trade {
         Ok((price_impact_p, price_after_impact))
     }
 
-    fn get_trade_borrowing_fees_collateral(
+    pub(crate) fn get_trade_borrowing_fees_collateral(
         &self,
         deps: &Deps,
         block: &BlockInfo,
     ) -> Result<Uint128, ContractError> {
-        let input = BorrowingFeeInput {
+        get_trade_borrowing_fees(deps, block, self.borrowing_fee_input())
+    }
+
+    /// Positive when the trade pays funding, negative when it receives it.
+    pub(crate) fn get_trade_funding_fees_collateral(
+        &self,
+        deps: &Deps,
+        block: &BlockInfo,
+    ) -> Result<Int128, ContractError> {
+        get_trade_funding_fees(deps, block, self.borrowing_fee_input())
+    }
+
+    fn borrowing_fee_input(&self) -> BorrowingFeeInput {
+        BorrowingFeeInput {
             collateral_index: self.collateral_index,
             trader: self.user.clone(),
             pair_index: self.pair_index,
@@ -184,9 +426,7 @@ impl Trade {
             long: self.long,
             collateral: self.collateral_amount,
             leverage: self.leverage,
-        };
-
-        get_trade_borrowing_fees(deps, block, input)
+        }
     }
 }
 
@@ -1,11 +1,11 @@
-use cosmwasm_std::{Decimal, Deps, SignedDecimal, Storage, Uint128};
-use oracle::contract::OracleQueryMsg;
+use cosmwasm_std::{BlockInfo, Decimal, Deps, SignedDecimal, Storage, Uint128};
+use oracle::contract::{OracleQueryMsg, Price};
 
 use crate::{
     borrowing::state::{GROUP_OIS, PAIR_OIS},
     constants::{MAX_PNL_P, MAX_SL_P},
     error::ContractError,
-    pairs::state::{FEES, ORACLE_ADDRESS, PAIRS},
+    pairs::state::{FEES, ORACLE_ADDRESS, PAIRS, PAIR_PRICE_GUARDS},
     utils::{dec_to_sdec, u128_to_dec},
 };
 
@@ -96,6 +96,64 @@ pub fn get_collateral_price(
     )?)
 }
 
+/// Collateral price for trading on `pair_index`, checked against the pair
+/// price guard.
+pub fn get_guarded_collateral_price(
+    deps: &Deps,
+    block: &BlockInfo,
+    collateral_index: u64,
+    pair_index: u64,
+) -> Result<Decimal, ContractError> {
+    let price = deps.querier.query_wasm_smart::<Price>(
+        ORACLE_ADDRESS.load(deps.storage)?.to_string(),
+        &OracleQueryMsg::GetCollateralPriceData {
+            index: collateral_index,
+        },
+    )?;
+    check_price_guard(deps.storage, block, pair_index, &price, true)?;
+    Ok(price.price)
+}
+
+/// Rejects an oracle price that is older, or if `check_deviation` that moved
+/// more on its last update, than the price guard of the pair allows. A price
+/// without a previous one, like the first pushed price or a native
+/// `x/oracle` rate, has no deviation to check.
+pub(crate) fn check_price_guard(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    pair_index: u64,
+    price: &Price,
+    check_deviation: bool,
+) -> Result<(), ContractError> {
+    let Some(guard) = PAIR_PRICE_GUARDS.may_load(storage, pair_index)? else {
+        return Ok(());
+    };
+
+    if guard.max_age_blocks != 0
+        && block.height.saturating_sub(price.last_update_block)
+            > guard.max_age_blocks
+    {
+        return Err(ContractError::StalePrice);
+    }
+
+    if !check_deviation || guard.max_deviation_p.is_zero() {
+        return Ok(());
+    }
+    match price.previous_price {
+        Some(previous_price)
+            if !previous_price.is_zero()
+                && price
+                    .price
+                    .abs_diff(previous_price)
+                    .checked_div(previous_price)?
+                    > guard.max_deviation_p =>
+        {
+            Err(ContractError::PriceDeviationTooHigh)
+        }
+        _ => Ok(()),
+    }
+}
+
 pub(crate) fn limit_tp_distance(
     open_price: Decimal,
     leverage: Uint128,
@@ -106,7 +164,6 @@ pub(crate) fn limit_tp_distance(
         || get_pnl_percent(open_price, tp, long, leverage)?
             == dec_to_sdec(MAX_PNL_P)?
     {
-        let open_price = open_price;
         let tp_diff =
             (open_price * MAX_PNL_P).checked_div(u128_to_dec(leverage)?)?;
         let new_tp = if long {
@@ -3,7 +3,8 @@ use cosmwasm_std::{Decimal, Int128, SignedDecimal, Uint128};
 use crate::error::ContractError;
 
 pub fn u128_to_i128(u: Uint128) -> Result<Int128, ContractError> {
-    Int128::try_from(u.u128() as i128)
+    i128::try_from(u.u128())
+        .map(Int128::new)
         .map_err(|_| ContractError::ConversionOverflow)
 }
 
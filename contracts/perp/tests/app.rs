@@ -1,15 +1,29 @@
+#![allow(dead_code)]
+
 use cosmwasm_schema::serde::de::DeserializeOwned;
-use cosmwasm_std::{from_json, Addr, Coin, Decimal, Empty, StdError};
+use cosmwasm_std::{
+    from_json, Addr, Coin, Decimal, Empty, StdError, StdResult, Uint128,
+};
 use cw_multi_test::{
-    error::AnyResult, BankSudo, Contract, ContractWrapper, Executor,
+    error::AnyResult, AppResponse, BankSudo, Contract, ContractWrapper, Executor,
+};
+use perp::{
+    borrowing::state::{BorrowingData, BorrowingPairGroup, OpenInterest},
+    msgs::AdminExecuteMsg,
+    price_impact::state::{OiWindowsSettings, PairDepth},
+    trading::state::TradingActivated,
 };
-use perp::{msgs::AdminExecuteMsg, trading::state::TradingActivated};
+use referrals::{contract::ReferralsExecuteMsg, query::ReferralsQueryMsg};
+use staking::{contract::StakingExecuteMsg, query::StakingQueryMsg};
 use test_app::Simapp;
+use vault::{contract::VaultExecuteMsg, query::VaultQueryMsg};
 
 struct Contracts {
     oracle: Box<dyn Contract<Empty>>,
     perp: Box<dyn Contract<Empty>>,
     referrals: Box<dyn Contract<Empty>>,
+    vault: Box<dyn Contract<Empty>>,
+    staking: Box<dyn Contract<Empty>>,
 }
 
 impl Contracts {
@@ -30,19 +44,36 @@ impl Contracts {
                 referrals::contract::instantiate,
                 referrals::query::query,
             )),
+            vault: Box::new(ContractWrapper::new(
+                vault::contract::execute,
+                vault::contract::instantiate,
+                vault::query::query,
+            )),
+            staking: Box::new(ContractWrapper::new(
+                staking::contract::execute,
+                staking::contract::instantiate,
+                staking::query::query,
+            )),
         }
     }
 }
 
+pub const GOV_DENOM: &str = "ugov";
+
 pub struct App {
     pub simapp: Simapp,
     pub oracle_addr: Addr,
     pub perp_addr: Addr,
     pub referrals_addr: Addr,
+    pub vault_addr: Addr,
+    pub staking_addr: Addr,
 
     pub oracle_owner: Addr,
     pub referrals_owner: Addr,
     pub perp_owner: Addr,
+    pub vault_owner: Addr,
+    pub treasury: Addr,
+    pub staking_owner: Addr,
 }
 
 impl Default for App {
@@ -52,6 +83,8 @@ impl Default for App {
         let oracle_code_id = app.store_code(contracts.oracle);
         let perp_code_id = app.store_code(contracts.perp);
         let referrals_code_id = app.store_code(contracts.referrals);
+        let vault_code_id = app.store_code(contracts.vault);
+        let staking_code_id = app.store_code(contracts.staking);
 
         let oracle_owner = Addr::unchecked("oracle");
         let oracle = app
@@ -72,14 +105,51 @@ impl Default for App {
             .instantiate_contract(
                 referrals_code_id,
                 referrals_owner.clone(),
-                &referrals::contract::ReferralInstantiateMsg {},
+                &referrals::contract::ReferralInstantiateMsg {
+                    perp_address: None,
+                },
                 &[],
                 "referrals",
                 None,
             )
             .unwrap();
 
+        let vault_owner = Addr::unchecked("vault");
+        let vault = app
+            .instantiate_contract(
+                vault_code_id,
+                vault_owner.clone(),
+                &vault::contract::VaultInstantiateMsg {
+                    owner: Some(vault_owner.clone().into_string()),
+                    perp_address: None,
+                    epoch_duration: None,
+                    min_collateralization_p: None,
+                },
+                &[],
+                "vault",
+                None,
+            )
+            .unwrap();
+
+        let staking_owner = Addr::unchecked("staking");
+        let staking = app
+            .instantiate_contract(
+                staking_code_id,
+                staking_owner.clone(),
+                &staking::contract::StakingInstantiateMsg {
+                    owner: Some(staking_owner.clone().into_string()),
+                    gov_denom: GOV_DENOM.to_string(),
+                    reward_denoms: vec!["usd".to_string()],
+                    unstake_cooldown: None,
+                },
+                &[],
+                "staking",
+                None,
+            )
+            .unwrap();
+
         let perp_owner = Addr::unchecked("perp");
+        let treasury = app.api().addr_make("treasury");
         let perp = app
             .instantiate_contract(
                 perp_code_id,
@@ -87,7 +157,10 @@ impl Default for App {
                 &perp::msgs::InstantiateMsg {
                     owner: Some(perp_owner.clone().into_string()),
                     oracle_address: Some(oracle.to_string()),
-                    staking_address: None,
+                    staking_address: Some(staking.to_string()),
+                    vault_address: Some(vault.to_string()),
+                    treasury_address: Some(treasury.to_string()),
+                    referrals_address: Some(referrals.to_string()),
                 },
                 &[],
                 "perp",
@@ -95,14 +168,48 @@ impl Default for App {
             )
             .unwrap();
 
+        app.execute_contract(
+            vault_owner.clone(),
+            vault.clone(),
+            &vault::contract::VaultExecuteMsg::UpdatePerpAddress {
+                perp_address: perp.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        // perp binds traders to the codes they trade with
+        app.execute_contract(
+            referrals_owner.clone(),
+            referrals.clone(),
+            &referrals::contract::ReferralsExecuteMsg::AddAdmin {
+                account: perp.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            referrals_owner.clone(),
+            referrals.clone(),
+            &referrals::contract::ReferralsExecuteMsg::SetPerpAddress {
+                perp_address: perp.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+
         App {
             simapp: app,
             oracle_addr: oracle,
             perp_addr: perp,
             referrals_addr: referrals,
+            vault_addr: vault,
+            staking_addr: staking,
             oracle_owner,
             referrals_owner,
             perp_owner,
+            vault_owner,
+            treasury,
+            staking_owner,
         }
     }
 }
@@ -124,12 +231,12 @@ impl App {
         Ok(from_json::<T>(data)?)
     }
 
-    pub fn fund(&mut self, addr: &Addr, coins: &Vec<Coin>) {
+    pub fn fund(&mut self, addr: &Addr, coins: &[Coin]) {
         self.simapp
             .sudo(
                 BankSudo::Mint {
                     to_address: addr.into(),
-                    amount: coins.clone(),
+                    amount: coins.to_vec(),
                 }
                 .into(),
             )
@@ -168,22 +275,96 @@ impl App {
     }
 
     pub fn create_default_pairs(&mut self) {
-        let mut messages: Vec<AdminExecuteMsg> = vec![];
-
-        // pairs
-        messages.push(AdminExecuteMsg::default_set_pairs());
-        messages.push(AdminExecuteMsg::default_set_groups());
-        // fees
-        messages.push(AdminExecuteMsg::default_set_fees());
-        messages.push(AdminExecuteMsg::default_set_fee_tiers());
-        // trading
-        messages.push(AdminExecuteMsg::default_collaterals());
-
-        // turn on trading
-        messages.push(AdminExecuteMsg::set_trading_activated(
-            TradingActivated::Activated,
-        ));
+        self.execute_admin_msgs(vec![
+            // pairs
+            AdminExecuteMsg::default_set_pairs(),
+            AdminExecuteMsg::default_set_groups(),
+            // fees
+            AdminExecuteMsg::default_set_fees(),
+            AdminExecuteMsg::default_set_fee_tiers(),
+            // trading
+            AdminExecuteMsg::default_collaterals(),
+            // turn on trading
+            AdminExecuteMsg::set_trading_activated(TradingActivated::Activated),
+        ]);
+    }
+
+    /// Sets up the borrowing, open interest and price impact state of pair 0
+    /// with no borrowing fees and no price impact, and gives the vault half
+    /// of the closing fees.
+    pub fn set_up_trading(&mut self) {
+        let open_interest = OpenInterest {
+            long: Uint128::zero(),
+            short: Uint128::zero(),
+            max: Uint128::new(1_000_000_000_000),
+        };
+        let borrowing_data = BorrowingData {
+            fee_per_block: Decimal::zero(),
+            acc_fee_long: Decimal::zero(),
+            acc_fee_short: Decimal::zero(),
+            acc_last_updated_block: 0,
+            fee_exponent: 1,
+        };
 
+        self.execute_admin_msgs(vec![
+            AdminExecuteMsg::UpdateBorrowingPairs {
+                borrowing_pairs: vec![((0, 0), borrowing_data.clone())]
+                    .into_iter()
+                    .collect(),
+            },
+            AdminExecuteMsg::UpdateBorrowingPairGroups {
+                pair_groups: vec![(
+                    (0, 0),
+                    vec![BorrowingPairGroup {
+                        group_index: 0,
+                        block: 0,
+                        initial_acc_fee_long: Decimal::zero(),
+                        initial_acc_fee_short: Decimal::zero(),
+                        prev_group_acc_fee_long: Decimal::zero(),
+                        prev_group_acc_fee_short: Decimal::zero(),
+                        pair_acc_fee_long: Decimal::zero(),
+                        pair_acc_fee_short: Decimal::zero(),
+                    }],
+                )]
+                .into_iter()
+                .collect(),
+            },
+            AdminExecuteMsg::UpdateBorrowingGroups {
+                groups: vec![((0, 0), borrowing_data)].into_iter().collect(),
+            },
+            AdminExecuteMsg::UpdateBorrowingPairOis {
+                pair_ois: vec![((0, 0), open_interest.clone())]
+                    .into_iter()
+                    .collect(),
+            },
+            AdminExecuteMsg::UpdateBorrowingGroupOis {
+                group_ois: vec![((0, 0), open_interest)].into_iter().collect(),
+            },
+            AdminExecuteMsg::UpdateOiWindowsSettings {
+                oi_windows_settings: OiWindowsSettings {
+                    start_ts: 0,
+                    windows_duration: 3600,
+                    windows_count: 0,
+                },
+            },
+            AdminExecuteMsg::UpdatePairDepths {
+                pair_depths: vec![(
+                    0,
+                    PairDepth {
+                        one_percent_depth_above_usd: 0,
+                        one_percent_depth_below_usd: 0,
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            },
+            AdminExecuteMsg::UpdateVaultClosingFeeP {
+                vault_closing_fee_p: Decimal::percent(50),
+            },
+        ]);
+    }
+
+    pub fn execute_admin_msgs(&mut self, messages: Vec<AdminExecuteMsg>) {
         for msg in messages {
             self.simapp
                 .execute_contract(
@@ -195,4 +376,101 @@ impl App {
                 .unwrap();
         }
     }
+
+    pub fn execute_perp(
+        &mut self,
+        from: &Addr,
+        msg: perp::msgs::ExecuteMsg,
+        funds: &[Coin],
+    ) -> AnyResult<AppResponse> {
+        self.simapp.execute_contract(
+            from.clone(),
+            self.perp_addr.clone(),
+            &msg,
+            funds,
+        )
+    }
+
+    pub fn execute_vault(
+        &mut self,
+        from: &Addr,
+        msg: VaultExecuteMsg,
+        funds: &[Coin],
+    ) -> AnyResult<AppResponse> {
+        self.simapp.execute_contract(
+            from.clone(),
+            self.vault_addr.clone(),
+            &msg,
+            funds,
+        )
+    }
+
+    pub fn query_vault<T: DeserializeOwned>(
+        &self,
+        msg: VaultQueryMsg,
+    ) -> StdResult<T> {
+        self.simapp
+            .wrap()
+            .query_wasm_smart(self.vault_addr.clone(), &msg)
+    }
+
+    pub fn execute_referrals(
+        &mut self,
+        from: &Addr,
+        msg: ReferralsExecuteMsg,
+    ) -> AnyResult<AppResponse> {
+        self.simapp.execute_contract(
+            from.clone(),
+            self.referrals_addr.clone(),
+            &msg,
+            &[],
+        )
+    }
+
+    pub fn query_referrals<T: DeserializeOwned>(
+        &self,
+        msg: ReferralsQueryMsg,
+    ) -> StdResult<T> {
+        self.simapp
+            .wrap()
+            .query_wasm_smart(self.referrals_addr.clone(), &msg)
+    }
+
+    pub fn execute_staking(
+        &mut self,
+        from: &Addr,
+        msg: StakingExecuteMsg,
+        funds: &[Coin],
+    ) -> AnyResult<AppResponse> {
+        self.simapp.execute_contract(
+            from.clone(),
+            self.staking_addr.clone(),
+            &msg,
+            funds,
+        )
+    }
+
+    pub fn query_staking<T: DeserializeOwned>(
+        &self,
+        msg: StakingQueryMsg,
+    ) -> StdResult<T> {
+        self.simapp
+            .wrap()
+            .query_wasm_smart(self.staking_addr.clone(), &msg)
+    }
+
+    pub fn advance_time(&mut self, seconds: u64) {
+        self.simapp.update_block(|block| {
+            block.time = block.time.plus_seconds(seconds);
+            block.height += seconds / 5;
+        });
+    }
+
+    pub fn balance(&self, addr: &Addr, denom: &str) -> Uint128 {
+        self.simapp
+            .wrap()
+            .query_balance(addr, denom)
+            .unwrap()
+            .amount
+    }
 }
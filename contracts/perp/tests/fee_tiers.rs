@@ -0,0 +1,180 @@
+use cosmwasm_std::{coin, Addr, Decimal, Uint128};
+use perp::{
+    fees::state::FeeTier,
+    msgs::{AdminExecuteMsg, ExecuteMsg, QueryMsg, TraderFeeTierResponse},
+    pairs::state::Fee,
+    trading::state::{OpenOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+
+use crate::app::App;
+
+mod app;
+
+const DENOM: &str = "usd";
+const DAY: u64 = 86_400;
+
+/// App with pair 0 (btc-usd) at 100, a 0.1% opening fee, one point per usd
+/// traded on group 0 and a 10% discount from 15_000_000 trailing points.
+fn set_up() -> App {
+    let mut app = App::default();
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    app.set_up_oracle_collateral(0, Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+
+    let mut fee_tiers = [0; 8].map(|_| FeeTier {
+        fee_multiplier: Decimal::zero(),
+        points_treshold: Uint128::zero(),
+    });
+    fee_tiers[0] = FeeTier {
+        fee_multiplier: Decimal::percent(90),
+        points_treshold: Uint128::new(15_000_000),
+    };
+    app.execute_admin_msgs(vec![
+        AdminExecuteMsg::SetFees {
+            fees: vec![(
+                0,
+                Fee {
+                    name: "default".to_string(),
+                    open_fee_p: Decimal::permille(1),
+                    close_fee_p: Decimal::zero(),
+                    oracle_fee_p: Decimal::zero(),
+                    trigger_order_fee_p: Decimal::zero(),
+                    min_position_size_usd: Uint128::zero(),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        },
+        AdminExecuteMsg::UpdateGroupVolumeMultipliers {
+            group_volume_multipliers: vec![(0, Decimal::one())]
+                .into_iter()
+                .collect(),
+        },
+        AdminExecuteMsg::UpdateFeeTiers { fee_tiers },
+    ]);
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(100_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(100_000_000, DENOM)],
+    )
+    .unwrap();
+    app
+}
+
+fn open_long(app: &mut App, trader: &Addr, collateral: u128) {
+    app.fund(trader, &[coin(collateral, DENOM)]);
+    app.execute_perp(
+        trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(collateral),
+                open_price: u128_to_dec(100_u64.into()).unwrap(),
+                tp: Decimal::zero(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.01".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(collateral, DENOM)],
+    )
+    .unwrap();
+}
+
+fn fee_tier(app: &App, trader: &Addr) -> TraderFeeTierResponse {
+    app.simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::TraderFeeTier {
+                address: trader.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+fn pending_gov_fees(app: &App) -> Uint128 {
+    let pending: Vec<(u64, Uint128)> = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::PendingGovFees {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    pending[0].1
+}
+
+#[test]
+fn trailing_points_unlock_fee_discount() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+
+    // the points of the day only count from the next day
+    open_long(&mut app, &trader, 1_000_000);
+    open_long(&mut app, &trader, 1_000_000);
+    assert_eq!(
+        fee_tier(&app, &trader),
+        TraderFeeTierResponse {
+            trailing_points: Uint128::zero(),
+            points_today: Uint128::new(20_000_000),
+            fee_multiplier: Decimal::one(),
+        }
+    );
+    assert_eq!(pending_gov_fees(&app), Uint128::new(20_000));
+
+    app.advance_time(DAY);
+    assert_eq!(
+        fee_tier(&app, &trader),
+        TraderFeeTierResponse {
+            trailing_points: Uint128::new(20_000_000),
+            points_today: Uint128::zero(),
+            fee_multiplier: Decimal::percent(90),
+        }
+    );
+    open_long(&mut app, &trader, 1_000_000);
+    assert_eq!(pending_gov_fees(&app), Uint128::new(20_000 + 9_000));
+
+    // the points of the first day leave the trailing window
+    app.advance_time(30 * DAY);
+    assert_eq!(
+        fee_tier(&app, &trader),
+        TraderFeeTierResponse {
+            trailing_points: Uint128::new(10_000_000),
+            points_today: Uint128::zero(),
+            fee_multiplier: Decimal::one(),
+        }
+    );
+}
+
+#[test]
+fn volume_without_multiplier_earns_no_points() {
+    let mut app = set_up();
+    app.execute_admin_msgs(vec![
+        AdminExecuteMsg::UpdateGroupVolumeMultipliers {
+            group_volume_multipliers: vec![(0, Decimal::zero())]
+                .into_iter()
+                .collect(),
+        },
+    ]);
+    let trader = app.simapp.api().addr_make("trader");
+
+    open_long(&mut app, &trader, 1_000_000);
+    assert_eq!(fee_tier(&app, &trader).points_today, Uint128::zero());
+}
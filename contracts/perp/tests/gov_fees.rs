@@ -0,0 +1,185 @@
+use cosmwasm_std::{coin, Addr, Decimal, Uint128};
+use perp::{
+    error::ContractError,
+    msgs::{AdminExecuteMsg, ExecuteMsg, QueryMsg},
+    pairs::state::Fee,
+    trading::state::{OpenOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+
+use crate::app::App;
+
+mod app;
+
+const DENOM: &str = "usd";
+
+/// App with pair 0 (btc-usd) at 100, a 0.1% opening fee and a funded vault.
+fn set_up() -> App {
+    let mut app = App::default();
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    app.set_up_oracle_collateral(0, Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetFees {
+        fees: vec![(
+            0,
+            Fee {
+                name: "default".to_string(),
+                open_fee_p: Decimal::permille(1),
+                close_fee_p: Decimal::zero(),
+                oracle_fee_p: Decimal::zero(),
+                trigger_order_fee_p: Decimal::zero(),
+                min_position_size_usd: Uint128::zero(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }]);
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(10_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(10_000_000, DENOM)],
+    )
+    .unwrap();
+    app
+}
+
+fn open_long(app: &mut App, trader: &Addr, collateral: u128) {
+    app.fund(trader, &[coin(collateral, DENOM)]);
+    app.execute_perp(
+        trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(collateral),
+                open_price: u128_to_dec(100_u64.into()).unwrap(),
+                tp: Decimal::zero(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.01".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(collateral, DENOM)],
+    )
+    .unwrap();
+}
+
+fn pending_gov_fees(app: &App) -> Vec<(u64, Uint128)> {
+    app.simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::PendingGovFees {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn treasury_claims_pending_gov_fees() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+
+    // 0.1% of the 10_000_000 position
+    open_long(&mut app, &trader, 1_000_000);
+    assert_eq!(pending_gov_fees(&app), vec![(0, Uint128::new(10_000))]);
+
+    let err = app
+        .execute_perp(&trader, ExecuteMsg::ClaimGovFees {}, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Unauthorized
+    );
+
+    let treasury = app.treasury.clone();
+    let res = app
+        .execute_perp(&treasury, ExecuteMsg::ClaimGovFees {}, &[])
+        .unwrap();
+    assert!(res.events.iter().any(|event| {
+        event.ty == "wasm-gov_fees_claimed"
+            && event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "amount" && attr.value == "10000usd")
+    }));
+    assert_eq!(app.balance(&treasury, DENOM), Uint128::new(10_000));
+    assert_eq!(pending_gov_fees(&app), vec![(0, Uint128::zero())]);
+
+    let err = app
+        .execute_perp(&treasury, ExecuteMsg::ClaimGovFees {}, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoPendingGovFees
+    );
+}
+
+#[test]
+fn gov_fees_are_claimed_per_denom() {
+    let mut app = set_up();
+    let perp_addr = app.perp_addr.clone();
+    app.execute_admin_msgs(vec![
+        AdminExecuteMsg::UpdateCollaterals {
+            collaterals: [(1, DENOM.to_string())].into_iter().collect(),
+        },
+        AdminExecuteMsg::UpdatePendingGovFees {
+            pending_gov_fees: [
+                (0, Uint128::new(10_000)),
+                (1, Uint128::new(5_000)),
+            ]
+            .into_iter()
+            .collect(),
+        },
+    ]);
+    app.fund(&perp_addr, &[coin(15_000, DENOM)]);
+
+    // a single coin is sent for both collaterals
+    let treasury = app.treasury.clone();
+    let res = app
+        .execute_perp(&treasury, ExecuteMsg::ClaimGovFees {}, &[])
+        .unwrap();
+    assert!(res.events.iter().any(|event| {
+        event.ty == "transfer"
+            && event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "amount" && attr.value == "15000usd")
+    }));
+    assert_eq!(app.balance(&treasury, DENOM), Uint128::new(15_000));
+    assert_eq!(
+        pending_gov_fees(&app),
+        vec![(0, Uint128::zero()), (1, Uint128::zero())]
+    );
+}
+
+#[test]
+fn owner_claims_to_updated_treasury() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+    open_long(&mut app, &trader, 1_000_000);
+
+    let new_treasury = app.simapp.api().addr_make("new_treasury");
+    app.execute_admin_msgs(vec![AdminExecuteMsg::UpdateTreasuryAddress {
+        treasury_address: new_treasury.to_string(),
+    }]);
+    let owner = app.perp_owner.clone();
+    app.execute_perp(&owner, ExecuteMsg::ClaimGovFees {}, &[])
+        .unwrap();
+
+    assert_eq!(app.balance(&new_treasury, DENOM), Uint128::new(10_000));
+    assert_eq!(app.balance(&app.treasury, DENOM), Uint128::zero());
+}
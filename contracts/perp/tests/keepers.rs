@@ -0,0 +1,385 @@
+use cosmwasm_std::{coin, from_json, Addr, Decimal, Uint128};
+use perp::{
+    error::ContractError,
+    keepers::state::{Keeper, KeeperConfig},
+    msgs::{
+        AdminExecuteMsg, ExecuteMsg, KeeperResponse, QueryMsg, TradeResponse,
+        TriggerTradeResult, TriggerTradesResponse,
+    },
+    pairs::state::Fee,
+    trading::state::{OpenOrderType, PendingOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+
+use crate::app::{App, GOV_DENOM};
+
+mod app;
+
+const DENOM: &str = "usd";
+
+/// App with pair 0 (btc-usd) at 100, a 0.1% trigger order fee and a funded
+/// vault.
+fn set_up() -> App {
+    let mut app = App::default();
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    app.set_up_oracle_collateral(0, Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetFees {
+        fees: vec![(
+            0,
+            Fee {
+                name: "default".to_string(),
+                open_fee_p: Decimal::zero(),
+                close_fee_p: Decimal::zero(),
+                oracle_fee_p: Decimal::zero(),
+                trigger_order_fee_p: Decimal::permille(1),
+                min_position_size_usd: Uint128::zero(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }]);
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(10_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(10_000_000, DENOM)],
+    )
+    .unwrap();
+    app
+}
+
+/// Opens a 10x long of 1_000_000 at 100 with a TP at 110. The market order
+/// pays 10_000 of trigger fee, leaving 990_000 of collateral.
+fn open_long(app: &mut App, trader: &Addr) {
+    app.fund(trader, &[coin(1_000_000, DENOM)]);
+    app.execute_perp(
+        trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(1_000_000),
+                open_price: u128_to_dec(100_u64.into()).unwrap(),
+                tp: u128_to_dec(110_u64.into()).unwrap(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.01".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(1_000_000, DENOM)],
+    )
+    .unwrap();
+}
+
+fn trigger(
+    app: &mut App,
+    keeper: &Addr,
+    trader: &Addr,
+    order_type: PendingOrderType,
+) -> Result<(), ContractError> {
+    app.execute_perp(
+        keeper,
+        ExecuteMsg::TriggerTrade {
+            trader: trader.clone(),
+            index: 0,
+            order_type,
+        },
+        &[],
+    )
+    .map(|_| ())
+    .map_err(|err| err.downcast().unwrap())
+}
+
+fn keeper_info(app: &App, keeper: &Addr) -> KeeperResponse {
+    app.simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::Keeper {
+                address: keeper.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn keeper_earns_share_of_trigger_fee() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    open_long(&mut app, &trader);
+    let staking_balance = app.balance(&app.staking_addr, DENOM);
+
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    trigger(&mut app, &keeper, &trader, PendingOrderType::TpClose).unwrap();
+
+    // 0.1% of the 9_900_000 position, 20% of it to the keeper by default
+    assert_eq!(app.balance(&keeper, DENOM), Uint128::new(1_980));
+    assert_eq!(
+        app.balance(&app.staking_addr, DENOM) - staking_balance,
+        Uint128::new(7_920)
+    );
+    assert_eq!(
+        keeper_info(&app, &keeper),
+        KeeperResponse {
+            keeper: Keeper {
+                registered: false,
+                bond: Uint128::zero(),
+                bond_denom: String::new(),
+                triggered_orders: 1,
+                liquidations: 0,
+                last_trigger_block: app.simapp.block_info().height,
+            },
+            rewards: vec![(0, Uint128::new(1_980))],
+        }
+    );
+}
+
+#[test]
+fn trader_pays_all_closing_fees_on_tp_close() {
+    let mut app = set_up();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetFees {
+        fees: vec![(
+            0,
+            Fee {
+                name: "default".to_string(),
+                open_fee_p: Decimal::zero(),
+                close_fee_p: Decimal::percent(1),
+                oracle_fee_p: Decimal::zero(),
+                trigger_order_fee_p: Decimal::permille(1),
+                min_position_size_usd: Uint128::zero(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }]);
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    open_long(&mut app, &trader);
+
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    trigger(&mut app, &keeper, &trader, PendingOrderType::TpClose).unwrap();
+
+    // +100% on the 990_000 collateral, minus the 99_000 closing fee shared
+    // by the vault and the stakers and the 9_900 trigger fee
+    assert_eq!(app.balance(&trader, DENOM), Uint128::new(1_871_100));
+}
+
+#[test]
+fn liquidations_pay_keeper() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    open_long(&mut app, &trader);
+
+    app.set_up_oracle_asset(0, u128_to_dec(90_u64.into()).unwrap());
+    trigger(&mut app, &keeper, &trader, PendingOrderType::LiqClose).unwrap();
+
+    // the liquidation fee is 5% of the 990_000 collateral
+    assert_eq!(app.balance(&keeper, DENOM), Uint128::new(9_900));
+    let info = keeper_info(&app, &keeper);
+    assert_eq!(info.keeper.triggered_orders, 1);
+    assert_eq!(info.keeper.liquidations, 1);
+}
+
+#[test]
+fn registration_requires_bond() {
+    let mut app = set_up();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::UpdateKeeperConfig {
+        keeper_config: KeeperConfig {
+            reward_p: Decimal::percent(50),
+            registration_required: true,
+            bond_denom: GOV_DENOM.to_string(),
+            min_bond: Uint128::new(1_000),
+        },
+    }]);
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    open_long(&mut app, &trader);
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+
+    assert_eq!(
+        trigger(&mut app, &keeper, &trader, PendingOrderType::TpClose),
+        Err(ContractError::KeeperNotRegistered)
+    );
+
+    app.fund(&keeper, &[coin(1_000, GOV_DENOM)]);
+    let err = app
+        .execute_perp(
+            &keeper,
+            ExecuteMsg::RegisterKeeper {},
+            &[coin(500, GOV_DENOM)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientKeeperBond
+    );
+    app.execute_perp(
+        &keeper,
+        ExecuteMsg::RegisterKeeper {},
+        &[coin(1_000, GOV_DENOM)],
+    )
+    .unwrap();
+
+    trigger(&mut app, &keeper, &trader, PendingOrderType::TpClose).unwrap();
+    assert_eq!(app.balance(&keeper, DENOM), Uint128::new(4_950));
+
+    // a bond in the previous denom no longer counts and cannot be topped up
+    // in the new one
+    app.execute_admin_msgs(vec![AdminExecuteMsg::UpdateKeeperConfig {
+        keeper_config: KeeperConfig {
+            reward_p: Decimal::percent(50),
+            registration_required: true,
+            bond_denom: DENOM.to_string(),
+            min_bond: Uint128::new(1_000),
+        },
+    }]);
+    let other_trader = app.simapp.api().addr_make("other_trader");
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    open_long(&mut app, &other_trader);
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    assert_eq!(
+        trigger(&mut app, &keeper, &other_trader, PendingOrderType::TpClose),
+        Err(ContractError::KeeperNotRegistered)
+    );
+    let err = app
+        .execute_perp(
+            &keeper,
+            ExecuteMsg::RegisterKeeper {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidFunds
+    );
+
+    // the bond is sent back in its denom and the stats are kept
+    app.execute_perp(&keeper, ExecuteMsg::UnregisterKeeper {}, &[])
+        .unwrap();
+    assert_eq!(app.balance(&keeper, GOV_DENOM), Uint128::new(1_000));
+    let info = keeper_info(&app, &keeper);
+    assert!(!info.keeper.registered);
+    assert_eq!(info.keeper.bond, Uint128::zero());
+    assert_eq!(info.keeper.triggered_orders, 1);
+}
+
+#[test]
+fn triggered_limit_order_pays_keeper() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    app.fund(&trader, &[coin(1_000_000, DENOM)]);
+    app.execute_perp(
+        &trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Limit,
+                collateral_amount: Uint128::new(1_000_000),
+                open_price: u128_to_dec(95_u64.into()).unwrap(),
+                tp: Decimal::zero(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::REVERSAL,
+            slippage_p: "0.01".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(1_000_000, DENOM)],
+    )
+    .unwrap();
+
+    app.set_up_oracle_asset(0, u128_to_dec(95_u64.into()).unwrap());
+    trigger(&mut app, &keeper, &trader, PendingOrderType::LimitOpen).unwrap();
+
+    // 20% of the 10_000 trigger fee of the 10_000_000 position
+    assert_eq!(app.balance(&keeper, DENOM), Uint128::new(2_000));
+    assert_eq!(keeper_info(&app, &keeper).keeper.triggered_orders, 1);
+}
+
+#[test]
+fn batch_reports_failed_orders() {
+    let mut app = set_up();
+    let keeper = app.simapp.api().addr_make("keeper");
+    let traders = ["trader1", "trader2", "trader3"]
+        .map(|trader| app.simapp.api().addr_make(trader));
+    for trader in &traders {
+        open_long(&mut app, trader);
+    }
+    app.execute_perp(
+        &traders[2],
+        ExecuteMsg::CloseTradeMarket {
+            index: 0,
+            collateral_delta: None,
+        },
+        &[],
+    )
+    .unwrap();
+
+    // the second trigger of the first trade sees it closed by the first one
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    let res = app
+        .execute_perp(
+            &keeper,
+            ExecuteMsg::TriggerTrades {
+                orders: vec![
+                    (traders[0].clone(), 0, PendingOrderType::TpClose),
+                    (traders[1].clone(), 0, PendingOrderType::SlClose),
+                    (traders[2].clone(), 0, PendingOrderType::TpClose),
+                    (traders[0].clone(), 0, PendingOrderType::TpClose),
+                ],
+            },
+            &[],
+        )
+        .unwrap();
+    let result =
+        |trader: &Addr, error: Option<ContractError>| TriggerTradeResult {
+            trader: trader.clone(),
+            index: 0,
+            error: error.map(|err| err.to_string()),
+        };
+    assert_eq!(
+        from_json::<TriggerTradesResponse>(res.data.unwrap()).unwrap(),
+        TriggerTradesResponse {
+            results: vec![
+                result(&traders[0], None),
+                result(&traders[1], Some(ContractError::InvalidTriggerPrice)),
+                result(&traders[2], Some(ContractError::TradeClosed)),
+                result(&traders[0], Some(ContractError::TradeClosed)),
+            ],
+        }
+    );
+
+    assert_eq!(app.balance(&keeper, DENOM), Uint128::new(1_980));
+    assert_eq!(keeper_info(&app, &keeper).keeper.triggered_orders, 1);
+    let trade: TradeResponse = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::Trade {
+                address: traders[1].to_string(),
+                index: 0,
+            },
+        )
+        .unwrap();
+    assert!(trade.trade.is_open);
+}
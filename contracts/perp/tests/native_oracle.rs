@@ -0,0 +1,266 @@
+use cosmwasm_std::{coin, Decimal, Empty, Uint128};
+use cw_multi_test::Executor;
+use oracle::{
+    contract::{OracleQueryMsg, OraclesExecuteMsg, Price},
+    error::ContractError as OracleError,
+    feeders::FeedKind,
+    native::OracleMode,
+};
+use perp::{
+    error::ContractError,
+    msgs::{AdminExecuteMsg, ExecuteMsg, QueryMsg, TradeResponse},
+    pairs::state::PriceGuard,
+    trading::state::{OpenOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+
+use crate::app::App;
+
+mod app;
+
+const DENOM: &str = "usd";
+
+/// App whose oracle reads pair 0 from the "ubtc:uusd" exchange rate and
+/// collateral 0 from the "uusd:uusd" one.
+fn set_up() -> App {
+    let mut app = App::default();
+    for msg in [
+        OraclesExecuteMsg::SetOracleMode {
+            mode: OracleMode::Native,
+        },
+        OraclesExecuteMsg::SetExchangeRateKeys {
+            keys: vec![(0, "ubtc:uusd".to_string())],
+            collateral_keys: vec![(0, "uusd:uusd".to_string())],
+        },
+    ] {
+        app.simapp
+            .execute_contract(
+                app.oracle_owner.clone(),
+                app.oracle_addr.clone(),
+                &msg,
+                &[],
+            )
+            .unwrap();
+    }
+    test_app::set_exchange_rate(
+        &mut app.simapp,
+        "ubtc:uusd",
+        u128_to_dec(100_u64.into()).unwrap(),
+    );
+    test_app::set_exchange_rate(&mut app.simapp, "uusd:uusd", Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(10_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(10_000_000, DENOM)],
+    )
+    .unwrap();
+    app
+}
+
+#[test]
+fn prices_are_read_from_exchange_rates() {
+    let mut app = set_up();
+    let price: Price = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.oracle_addr.clone(),
+            &OracleQueryMsg::GetPriceData { index: 0 },
+        )
+        .unwrap();
+    assert_eq!(
+        price,
+        Price {
+            last_update_block: app.simapp.block_info().height,
+            price: u128_to_dec(100_u64.into()).unwrap(),
+            previous_price: None,
+        }
+    );
+
+    test_app::set_exchange_rate(
+        &mut app.simapp,
+        "ubtc:uusd",
+        u128_to_dec(120_u64.into()).unwrap(),
+    );
+    let trader = app.simapp.api().addr_make("trader");
+    app.fund(&trader, &[coin(1_000_000, DENOM)]);
+    app.execute_perp(
+        &trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(1_000_000),
+                open_price: u128_to_dec(120_u64.into()).unwrap(),
+                tp: Decimal::zero(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.01".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(1_000_000, DENOM)],
+    )
+    .unwrap();
+
+    let trade: TradeResponse = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::Trade {
+                address: trader.to_string(),
+                index: 0,
+            },
+        )
+        .unwrap();
+    assert_eq!(trade.trade.open_price, u128_to_dec(120_u64.into()).unwrap());
+
+    // the price keeps the block the rate was voted at
+    let voted_block = app.simapp.block_info().height;
+    app.advance_time(50);
+    let price: Price = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.oracle_addr.clone(),
+            &OracleQueryMsg::GetPriceData { index: 0 },
+        )
+        .unwrap();
+    assert_eq!(price.last_update_block, voted_block);
+}
+
+#[test]
+fn price_guards_apply_to_exchange_rates() {
+    let mut app = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+    let open_long = |app: &mut App| {
+        app.fund(&trader, &[coin(1_000_000, DENOM)]);
+        app.execute_perp(
+            &trader,
+            ExecuteMsg::OpenTrade {
+                trade: Trade {
+                    user: trader.clone(),
+                    pair_index: 0,
+                    index: 0,
+                    leverage: Uint128::new(10),
+                    long: true,
+                    is_open: true,
+                    collateral_index: 0,
+                    trade_type: TradeType::Trade,
+                    collateral_amount: Uint128::new(1_000_000),
+                    open_price: u128_to_dec(100_u64.into()).unwrap(),
+                    tp: Decimal::zero(),
+                    sl: Decimal::zero(),
+                },
+                order_type: OpenOrderType::MARKET,
+                slippage_p: "0.01".to_string(),
+                referral: "".to_string(),
+            },
+            &[coin(1_000_000, DENOM)],
+        )
+        .map(|_| ())
+        .map_err(|err| err.downcast::<ContractError>().unwrap())
+    };
+    let set_price_guard = |app: &mut App, price_guard| {
+        app.execute_admin_msgs(vec![AdminExecuteMsg::SetPairPriceGuards {
+            pair_price_guards: [(0, price_guard)].into_iter().collect(),
+        }]);
+    };
+
+    // rates voted 20 blocks ago are stale
+    set_price_guard(
+        &mut app,
+        PriceGuard {
+            max_age_blocks: 10,
+            max_deviation_p: Decimal::zero(),
+        },
+    );
+    app.advance_time(100);
+    assert_eq!(open_long(&mut app), Err(ContractError::StalePrice));
+
+    // native rates have no previous price to check a deviation against
+    set_price_guard(
+        &mut app,
+        PriceGuard {
+            max_age_blocks: 0,
+            max_deviation_p: Decimal::percent(5),
+        },
+    );
+    open_long(&mut app).unwrap();
+}
+
+#[test]
+fn history_is_not_kept() {
+    let app = set_up();
+    for msg in [
+        OracleQueryMsg::Twap {
+            kind: FeedKind::Price,
+            index: 0,
+            window_blocks: 10,
+        },
+        OracleQueryMsg::LastPrices {
+            kind: FeedKind::Price,
+            index: 0,
+            count: 1,
+        },
+        OracleQueryMsg::PriceRange {
+            kind: FeedKind::Price,
+            index: 0,
+            since_block: 0,
+        },
+    ] {
+        let err = app
+            .simapp
+            .wrap()
+            .query_wasm_smart::<Empty>(app.oracle_addr.clone(), &msg)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&OracleError::NativeMode.to_string()),
+            "{err}"
+        );
+    }
+}
+
+#[test]
+fn pushed_prices_are_rejected() {
+    let mut app = set_up();
+    let err = app
+        .simapp
+        .execute_contract(
+            app.oracle_owner.clone(),
+            app.oracle_addr.clone(),
+            &OraclesExecuteMsg::SetPrice {
+                index: 0,
+                price: Decimal::one(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<OracleError>().unwrap(),
+        OracleError::NativeMode
+    );
+
+    // an index without an exchange rate key has no price
+    assert!(app
+        .simapp
+        .wrap()
+        .query_wasm_smart::<Decimal>(
+            app.oracle_addr.clone(),
+            &OracleQueryMsg::GetPrice { index: 1 },
+        )
+        .is_err());
+}
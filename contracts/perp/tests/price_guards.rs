@@ -0,0 +1,229 @@
+use cosmwasm_std::{coin, Addr, Decimal, Uint128};
+use perp::{
+    error::ContractError,
+    msgs::{AdminExecuteMsg, ExecuteMsg, QueryMsg, TradesResponse},
+    pairs::state::PriceGuard,
+    trading::state::{OpenOrderType, PendingOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+
+use crate::app::App;
+
+mod app;
+
+const DENOM: &str = "usd";
+
+/// App with pair 0 (btc-usd) at 100, the given price guard on it and a
+/// funded vault. Prices are set twice so that they have a previous price.
+fn set_up(price_guard: PriceGuard) -> App {
+    let mut app = App::default();
+    for _ in 0..2 {
+        app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+        app.set_up_oracle_collateral(0, Decimal::one());
+    }
+    app.create_default_pairs();
+    app.set_up_trading();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetPairPriceGuards {
+        pair_price_guards: [(0, price_guard)].into_iter().collect(),
+    }]);
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(10_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(10_000_000, DENOM)],
+    )
+    .unwrap();
+    app
+}
+
+/// Opens a 10x long of 1_000_000 at 100 with a TP at 110.
+fn open_long(app: &mut App, trader: &Addr) -> Result<(), ContractError> {
+    app.fund(trader, &[coin(1_000_000, DENOM)]);
+    app.execute_perp(
+        trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(1_000_000),
+                open_price: u128_to_dec(100_u64.into()).unwrap(),
+                tp: u128_to_dec(110_u64.into()).unwrap(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.2".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(1_000_000, DENOM)],
+    )
+    .map(|_| ())
+    .map_err(|err| err.downcast().unwrap())
+}
+
+#[test]
+fn stale_prices_are_rejected() {
+    let mut app = set_up(PriceGuard {
+        max_age_blocks: 10,
+        max_deviation_p: Decimal::zero(),
+    });
+    let trader = app.simapp.api().addr_make("trader");
+
+    // 20 blocks without an oracle update
+    app.advance_time(100);
+    assert_eq!(open_long(&mut app, &trader), Err(ContractError::StalePrice));
+
+    // the collateral price is checked as well
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    assert_eq!(open_long(&mut app, &trader), Err(ContractError::StalePrice));
+
+    app.set_up_oracle_collateral(0, Decimal::one());
+    open_long(&mut app, &trader).unwrap();
+
+    // so are the collateral prices of partial closes and margin withdrawals
+    app.advance_time(100);
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    for msg in [
+        ExecuteMsg::CloseTradeMarket {
+            index: 0,
+            collateral_delta: Some(Uint128::new(100_000)),
+        },
+        ExecuteMsg::WithdrawMargin {
+            index: 0,
+            leverage: Uint128::new(20),
+        },
+    ] {
+        let err = app.execute_perp(&trader, msg, &[]).unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::StalePrice
+        );
+    }
+}
+
+#[test]
+fn price_jumps_are_rejected() {
+    let mut app = set_up(PriceGuard {
+        max_age_blocks: 0,
+        max_deviation_p: Decimal::percent(5),
+    });
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    open_long(&mut app, &trader).unwrap();
+
+    // a 10% move in a single update
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    let trigger = |app: &mut App| {
+        app.execute_perp(
+            &keeper,
+            ExecuteMsg::TriggerTrade {
+                trader: trader.clone(),
+                index: 0,
+                order_type: PendingOrderType::TpClose,
+            },
+            &[],
+        )
+        .map(|_| ())
+        .map_err(|err| err.downcast::<ContractError>().unwrap())
+    };
+    assert_eq!(trigger(&mut app), Err(ContractError::PriceDeviationTooHigh));
+    let other_trader = app.simapp.api().addr_make("other_trader");
+    assert_eq!(
+        open_long(&mut app, &other_trader),
+        Err(ContractError::PriceDeviationTooHigh)
+    );
+
+    // a confirming update brings the move back within the guard
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    trigger(&mut app).unwrap();
+}
+
+#[test]
+fn liquidations_go_through_price_jumps() {
+    let mut app = set_up(PriceGuard {
+        max_age_blocks: 0,
+        max_deviation_p: Decimal::percent(5),
+    });
+    let trader = app.simapp.api().addr_make("trader");
+    let keeper = app.simapp.api().addr_make("keeper");
+    open_long(&mut app, &trader).unwrap();
+
+    // a 15% drop in a single update, past the liquidation price
+    app.set_up_oracle_asset(0, u128_to_dec(85_u64.into()).unwrap());
+    app.execute_perp(
+        &keeper,
+        ExecuteMsg::TriggerTrade {
+            trader: trader.clone(),
+            index: 0,
+            order_type: PendingOrderType::LiqClose,
+        },
+        &[],
+    )
+    .unwrap();
+    let trades: TradesResponse = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::OpenTrades {
+                address: trader.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(trades.trades.is_empty());
+}
+
+#[test]
+fn price_guards_are_validated_and_listed() {
+    let price_guard = PriceGuard {
+        max_age_blocks: 10,
+        max_deviation_p: Decimal::percent(5),
+    };
+    let mut app = set_up(price_guard.clone());
+
+    let err = app
+        .execute_perp(
+            &app.perp_owner.clone(),
+            ExecuteMsg::AdminMsg {
+                msg: AdminExecuteMsg::SetPairPriceGuards {
+                    pair_price_guards: [(
+                        1,
+                        PriceGuard {
+                            max_age_blocks: 10,
+                            max_deviation_p: Decimal::percent(101),
+                        },
+                    )]
+                    .into_iter()
+                    .collect(),
+                },
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidPercentage
+    );
+
+    let price_guards: Vec<(u64, PriceGuard)> = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::PairPriceGuards {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(price_guards, vec![(0, price_guard)]);
+}
@@ -0,0 +1,597 @@
+use cosmwasm_std::{coin, Addr, Coin, Decimal, Uint128};
+use perp::{
+    error::ContractError,
+    msgs::{AdminExecuteMsg, ExecuteMsg, QueryMsg, TradeResponse},
+    pairs::state::Fee,
+    trading::state::{OpenOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+use referrals::{contract::ReferralsExecuteMsg, query::ReferralsQueryMsg};
+
+use crate::app::App;
+
+mod app;
+
+const DENOM: &str = "usd";
+const CODE: &str = "ALICE";
+
+/// App with pair 0 (btc-usd) at 100, a 0.1% opening fee and a referral tier
+/// giving back half of the open fee, 40% of it as a trader discount. The
+/// referrer owns CODE.
+fn set_up() -> (App, Addr) {
+    let mut app = App::default();
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    app.set_up_oracle_collateral(0, Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetFees {
+        fees: vec![(
+            0,
+            Fee {
+                name: "default".to_string(),
+                open_fee_p: Decimal::permille(1),
+                close_fee_p: Decimal::zero(),
+                oracle_fee_p: Decimal::zero(),
+                trigger_order_fee_p: Decimal::zero(),
+                min_position_size_usd: Uint128::zero(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }]);
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(10_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(10_000_000, DENOM)],
+    )
+    .unwrap();
+
+    let referrals_owner = app.referrals_owner.clone();
+    app.execute_referrals(
+        &referrals_owner,
+        ReferralsExecuteMsg::SetTier {
+            tier_id: Uint128::one(),
+            total_rebate: Uint128::new(5_000),
+            discount_share: Uint128::new(4_000),
+        },
+    )
+    .unwrap();
+    let referrer = app.simapp.api().addr_make("referrer");
+    app.execute_referrals(
+        &referrer,
+        ReferralsExecuteMsg::RegisterCode {
+            code: CODE.to_string(),
+        },
+    )
+    .unwrap();
+    (app, referrer)
+}
+
+fn open_long(
+    app: &mut App,
+    trader: &Addr,
+    referral: &str,
+) -> Result<(), ContractError> {
+    app.fund(trader, &[coin(1_000_000, DENOM)]);
+    app.execute_perp(
+        trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(1_000_000),
+                open_price: u128_to_dec(100_u64.into()).unwrap(),
+                tp: Decimal::zero(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.01".to_string(),
+            referral: referral.to_string(),
+        },
+        &[coin(1_000_000, DENOM)],
+    )
+    .map(|_| ())
+    .map_err(|err| err.downcast().unwrap())
+}
+
+fn trade_collateral(app: &App, trader: &Addr, index: u64) -> Uint128 {
+    let response: TradeResponse = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::Trade {
+                address: trader.to_string(),
+                index,
+            },
+        )
+        .unwrap();
+    response.trade.collateral_amount
+}
+
+fn claimable(referrer: &Addr) -> ReferralsQueryMsg {
+    ReferralsQueryMsg::ClaimableRewards {
+        referrer: referrer.to_string(),
+    }
+}
+
+fn referrer_amounts(app: &App, msg: ReferralsQueryMsg) -> Vec<Coin> {
+    app.query_referrals(msg).unwrap()
+}
+
+fn pending_gov_fees(app: &App) -> Uint128 {
+    let pending: Vec<(u64, Uint128)> = app
+        .simapp
+        .wrap()
+        .query_wasm_smart(
+            app.perp_addr.clone(),
+            &QueryMsg::PendingGovFees {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    pending[0].1
+}
+
+#[test]
+fn referred_trader_gets_discount_and_referrer_rebate() {
+    let (mut app, referrer) = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+
+    // 20_000 of open fee, half of it to the referral program: 4_000 of
+    // discount and 6_000 of rebate, the rest split between gov and staking
+    open_long(&mut app, &trader, CODE).unwrap();
+    assert_eq!(
+        app.query_referrals::<String>(
+            ReferralsQueryMsg::GetTraderReferralInfo {
+                account: trader.to_string(),
+            }
+        )
+        .unwrap(),
+        referrer.to_string()
+    );
+    assert_eq!(
+        referrer_amounts(&app, claimable(&referrer)),
+        vec![coin(6_000, DENOM)]
+    );
+    assert_eq!(pending_gov_fees(&app), Uint128::new(5_000));
+    assert_eq!(app.balance(&app.staking_addr, DENOM), Uint128::new(5_000));
+    assert_eq!(trade_collateral(&app, &trader, 0), Uint128::new(984_000));
+
+    // the next trades keep the first referrer, even without a code
+    open_long(&mut app, &trader, "").unwrap();
+    assert_eq!(trade_collateral(&app, &trader, 1), Uint128::new(984_000));
+    assert_eq!(
+        referrer_amounts(&app, claimable(&referrer)),
+        vec![coin(12_000, DENOM)]
+    );
+    assert_eq!(
+        referrer_amounts(
+            &app,
+            ReferralsQueryMsg::ReferredVolume {
+                referrer: referrer.to_string(),
+            }
+        ),
+        vec![coin(20_000_000, DENOM)]
+    );
+}
+
+#[test]
+fn referrer_claims_rebates() {
+    let (mut app, referrer) = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+    open_long(&mut app, &trader, CODE).unwrap();
+
+    app.execute_referrals(&referrer, ReferralsExecuteMsg::ClaimRewards {})
+        .unwrap();
+    assert_eq!(app.balance(&referrer, DENOM), Uint128::new(6_000));
+    assert_eq!(referrer_amounts(&app, claimable(&referrer)), vec![]);
+
+    let err = app
+        .execute_referrals(&referrer, ReferralsExecuteMsg::ClaimRewards {})
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<referrals::error::ContractError>().unwrap(),
+        referrals::error::ContractError::NothingToClaim
+    );
+
+    // the lifetime rebates are kept once claimed
+    open_long(&mut app, &trader, "").unwrap();
+    assert_eq!(
+        referrer_amounts(
+            &app,
+            ReferralsQueryMsg::LifetimeRebates {
+                referrer: referrer.to_string(),
+            }
+        ),
+        vec![coin(12_000, DENOM)]
+    );
+}
+
+#[test]
+fn only_perp_credits_rebates() {
+    let (mut app, referrer) = set_up();
+
+    let err = app
+        .execute_referrals(
+            &referrer,
+            ReferralsExecuteMsg::CreditRebate {
+                referrer: referrer.to_string(),
+                denom: DENOM.to_string(),
+                volume: Uint128::new(1_000),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<referrals::error::ContractError>().unwrap(),
+        referrals::error::ContractError::Unauthorized
+    );
+}
+
+#[test]
+fn trade_without_referral_pays_full_fee() {
+    let (mut app, referrer) = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+
+    open_long(&mut app, &trader, "").unwrap();
+    assert_eq!(app.balance(&referrer, DENOM), Uint128::zero());
+    assert_eq!(pending_gov_fees(&app), Uint128::new(10_000));
+    assert_eq!(trade_collateral(&app, &trader, 0), Uint128::new(980_000));
+}
+
+#[test]
+fn referrals_query_errors_are_not_swallowed() {
+    let (mut app, _) = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+
+    // the vault does not know the referrals queries
+    let vault_addr = app.vault_addr.to_string();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::UpdateReferralsAddress {
+        referrals_address: vault_addr,
+    }]);
+    assert!(matches!(
+        open_long(&mut app, &trader, ""),
+        Err(ContractError::Std(_))
+    ));
+}
+
+#[test]
+fn invalid_referral_codes_are_refused() {
+    let (mut app, referrer) = set_up();
+    let trader = app.simapp.api().addr_make("trader");
+
+    assert_eq!(
+        open_long(&mut app, &trader, "UNKNOWN"),
+        Err(ContractError::InvalidReferral)
+    );
+    assert_eq!(
+        open_long(&mut app, &referrer, CODE),
+        Err(ContractError::InvalidReferral)
+    );
+
+    // traders can only bind themselves to registered codes
+    for (code, expected) in [
+        ("A.B", referrals::error::ContractError::InvalidCode),
+        ("UNKNOWN", referrals::error::ContractError::CodeNotFound),
+    ] {
+        let err = app
+            .execute_referrals(
+                &trader,
+                ReferralsExecuteMsg::SetTraderReferralCodeByUser {
+                    code: code.to_string(),
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<referrals::error::ContractError>().unwrap(),
+            expected
+        );
+    }
+}
+
+fn register_code(
+    app: &mut App,
+    owner: &Addr,
+    code: &str,
+) -> Result<(), referrals::error::ContractError> {
+    app.execute_referrals(
+        owner,
+        ReferralsExecuteMsg::RegisterCode {
+            code: code.to_string(),
+        },
+    )
+    .map(|_| ())
+    .map_err(|err| err.downcast().unwrap())
+}
+
+fn code_owner(app: &App, code: &str) -> String {
+    app.query_referrals(ReferralsQueryMsg::GetCodeOwner {
+        code: code.to_string(),
+    })
+    .unwrap()
+}
+
+#[test]
+fn codes_are_validated_and_case_insensitive() {
+    let (mut app, referrer) = set_up();
+    let squatter = app.simapp.api().addr_make("squatter");
+
+    for code in ["", "AB", "A".repeat(21).as_str(), "AL ICE", "ÀLICE", "A.B"] {
+        assert_eq!(
+            register_code(&mut app, &squatter, code),
+            Err(referrals::error::ContractError::InvalidCode)
+        );
+    }
+    assert_eq!(
+        register_code(&mut app, &squatter, "alice"),
+        Err(referrals::error::ContractError::CodeAlreadyClaimed)
+    );
+    register_code(&mut app, &squatter, "bob_2-x").unwrap();
+    assert_eq!(code_owner(&app, "BOB_2-X"), squatter.to_string());
+
+    // traders can use the code in any case
+    let trader = app.simapp.api().addr_make("trader");
+    open_long(&mut app, &trader, "Alice").unwrap();
+    assert_eq!(
+        app.query_referrals::<String>(
+            ReferralsQueryMsg::GetTraderReferralInfo {
+                account: trader.to_string(),
+            }
+        )
+        .unwrap(),
+        referrer.to_string()
+    );
+}
+
+#[test]
+fn reserved_codes_are_assigned_by_admins() {
+    let (mut app, _) = set_up();
+    let referrals_owner = app.referrals_owner.clone();
+    let partner = app.simapp.api().addr_make("partner");
+
+    let err = app
+        .execute_referrals(
+            &partner,
+            ReferralsExecuteMsg::ReserveCodes {
+                codes: vec!["NIBIRU".to_string()],
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<referrals::error::ContractError>().unwrap(),
+        referrals::error::ContractError::Unauthorized
+    );
+
+    app.execute_referrals(
+        &referrals_owner,
+        ReferralsExecuteMsg::ReserveCodes {
+            codes: vec!["nibiru".to_string()],
+        },
+    )
+    .unwrap();
+    assert!(app
+        .query_referrals::<bool>(ReferralsQueryMsg::IsCodeReserved {
+            code: "NIBIRU".to_string(),
+        })
+        .unwrap());
+    assert_eq!(
+        register_code(&mut app, &partner, "Nibiru"),
+        Err(referrals::error::ContractError::CodeReserved)
+    );
+
+    app.execute_referrals(
+        &referrals_owner,
+        ReferralsExecuteMsg::SetCodeOwner {
+            code: "nibiru".to_string(),
+            owner: partner.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(code_owner(&app, "NIBIRU"), partner.to_string());
+}
+
+#[test]
+fn owner_transfers_and_renounces_code() {
+    let (mut app, referrer) = set_up();
+    let new_owner = app.simapp.api().addr_make("new_owner");
+
+    let err = app
+        .execute_referrals(
+            &new_owner,
+            ReferralsExecuteMsg::TransferCode {
+                code: CODE.to_string(),
+                new_owner: new_owner.to_string(),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<referrals::error::ContractError>().unwrap(),
+        referrals::error::ContractError::Unauthorized
+    );
+
+    app.execute_referrals(
+        &referrer,
+        ReferralsExecuteMsg::TransferCode {
+            code: "alice".to_string(),
+            new_owner: new_owner.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(code_owner(&app, CODE), new_owner.to_string());
+    bind_code(&mut app, "trader", CODE);
+
+    app.execute_referrals(
+        &new_owner,
+        ReferralsExecuteMsg::RenounceCode {
+            code: CODE.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(code_owner(&app, CODE), "");
+    let err = app
+        .execute_referrals(
+            &new_owner,
+            ReferralsExecuteMsg::RenounceCode {
+                code: CODE.to_string(),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<referrals::error::ContractError>().unwrap(),
+        referrals::error::ContractError::CodeNotFound
+    );
+
+    // a renounced code is free again, without its traders
+    let squatter = app.simapp.api().addr_make("squatter");
+    register_code(&mut app, &squatter, CODE).unwrap();
+    let traders: Vec<String> = app
+        .query_referrals(ReferralsQueryMsg::TradersByCode {
+            code: CODE.to_string(),
+            start_after: None,
+            limit: None,
+        })
+        .unwrap();
+    assert!(traders.is_empty());
+}
+
+fn bind_code(app: &mut App, trader: &str, code: &str) -> String {
+    let trader = app.simapp.api().addr_make(trader);
+    app.execute_referrals(
+        &trader,
+        ReferralsExecuteMsg::SetTraderReferralCodeByUser {
+            code: code.to_string(),
+        },
+    )
+    .unwrap();
+    trader.to_string()
+}
+
+#[test]
+fn referrer_network_is_paginated() {
+    let (mut app, referrer) = set_up();
+    register_code(&mut app, &referrer, "BOB").unwrap();
+    let mut alice_traders = [
+        bind_code(&mut app, "trader1", CODE),
+        bind_code(&mut app, "trader2", "alice"),
+    ];
+    alice_traders.sort();
+    let bob_traders = [bind_code(&mut app, "trader3", "BOB")];
+
+    let codes: Vec<String> = app
+        .query_referrals(ReferralsQueryMsg::CodesByOwner {
+            owner: referrer.to_string(),
+            start_after: None,
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(codes, vec![CODE.to_string(), "BOB".to_string()]);
+
+    let traders: Vec<String> = app
+        .query_referrals(ReferralsQueryMsg::TradersByCode {
+            code: "Alice".to_string(),
+            start_after: Some(alice_traders[0].clone()),
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(traders, alice_traders[1..]);
+
+    // the pages of the referrer network continue over its codes
+    let page: Vec<(String, String)> = app
+        .query_referrals(ReferralsQueryMsg::TradersByReferrer {
+            referrer: referrer.to_string(),
+            start_after: None,
+            limit: Some(1),
+        })
+        .unwrap();
+    assert_eq!(page, vec![(CODE.to_string(), alice_traders[0].clone())]);
+    let page: Vec<(String, String)> = app
+        .query_referrals(ReferralsQueryMsg::TradersByReferrer {
+            referrer: referrer.to_string(),
+            start_after: page.last().cloned(),
+            limit: Some(2),
+        })
+        .unwrap();
+    assert_eq!(
+        page,
+        vec![
+            (CODE.to_string(), alice_traders[1].clone()),
+            ("BOB".to_string(), bob_traders[0].clone()),
+        ]
+    );
+
+    // the indexes follow the transfers and new bindings
+    let new_owner = app.simapp.api().addr_make("new_owner");
+    app.execute_referrals(
+        &referrer,
+        ReferralsExecuteMsg::TransferCode {
+            code: "BOB".to_string(),
+            new_owner: new_owner.to_string(),
+        },
+    )
+    .unwrap();
+    bind_code(&mut app, "trader1", "BOB");
+    let page: Vec<(String, String)> = app
+        .query_referrals(ReferralsQueryMsg::TradersByReferrer {
+            referrer: referrer.to_string(),
+            start_after: None,
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(
+        page,
+        vec![(
+            CODE.to_string(),
+            app.simapp.api().addr_make("trader2").to_string()
+        )]
+    );
+}
+
+#[test]
+fn tiers_and_admins_are_listed() {
+    let (app, _) = set_up();
+
+    let tiers: Vec<(Uint128, referrals::state::Tier)> = app
+        .query_referrals(ReferralsQueryMsg::Tiers {
+            start_after: None,
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(
+        tiers,
+        vec![(
+            Uint128::one(),
+            referrals::state::Tier {
+                total_rebate: Uint128::new(5_000),
+                discount_share: Uint128::new(4_000),
+            }
+        )]
+    );
+
+    let mut expected =
+        vec![app.referrals_owner.to_string(), app.perp_addr.to_string()];
+    expected.sort();
+    let admins: Vec<String> = app
+        .query_referrals(ReferralsQueryMsg::Admins {
+            start_after: None,
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(admins, expected);
+    let admins: Vec<String> = app
+        .query_referrals(ReferralsQueryMsg::Admins {
+            start_after: Some(expected[0].clone()),
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(admins, expected[1..]);
+}
@@ -0,0 +1,265 @@
+use cosmwasm_std::{coin, Addr, Coin, Decimal, Uint128};
+use perp::{
+    msgs::{AdminExecuteMsg, ExecuteMsg},
+    pairs::state::Fee,
+    trading::state::{OpenOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+use staking::{
+    contract::StakingExecuteMsg, error::ContractError, query::StakingQueryMsg,
+    state::Unstaking,
+};
+
+use crate::app::{App, GOV_DENOM};
+
+mod app;
+
+const DENOM: &str = "usd";
+const COOLDOWN: u64 = 7 * 86_400;
+
+/// App with pair 0 (btc-usd) at 100, a 0.1% trigger order fee, which goes to
+/// the stakers on market orders, and a funded vault.
+fn set_up() -> App {
+    let mut app = App::default();
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    app.set_up_oracle_collateral(0, Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetFees {
+        fees: vec![(
+            0,
+            Fee {
+                name: "default".to_string(),
+                open_fee_p: Decimal::zero(),
+                close_fee_p: Decimal::zero(),
+                oracle_fee_p: Decimal::zero(),
+                trigger_order_fee_p: Decimal::permille(1),
+                min_position_size_usd: Uint128::zero(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }]);
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(10_000_000, DENOM)]);
+    app.execute_vault(
+        &lp,
+        vault::contract::VaultExecuteMsg::Deposit {},
+        &[coin(10_000_000, DENOM)],
+    )
+    .unwrap();
+    app
+}
+
+fn stake(app: &mut App, staker: &Addr, amount: u128) {
+    app.fund(staker, &[coin(amount, GOV_DENOM)]);
+    app.execute_staking(
+        staker,
+        StakingExecuteMsg::Stake {},
+        &[coin(amount, GOV_DENOM)],
+    )
+    .unwrap();
+}
+
+/// Opens and closes a 10x long of `collateral` with market orders.
+fn trade(app: &mut App, trader: &str, collateral: u128) {
+    let trader = app.simapp.api().addr_make(trader);
+    app.fund(&trader, &[coin(collateral, DENOM)]);
+    app.execute_perp(
+        &trader,
+        ExecuteMsg::OpenTrade {
+            trade: Trade {
+                user: trader.clone(),
+                pair_index: 0,
+                index: 0,
+                leverage: Uint128::new(10),
+                long: true,
+                is_open: true,
+                collateral_index: 0,
+                trade_type: TradeType::Trade,
+                collateral_amount: Uint128::new(collateral),
+                open_price: u128_to_dec(100_u64.into()).unwrap(),
+                tp: Decimal::zero(),
+                sl: Decimal::zero(),
+            },
+            order_type: OpenOrderType::MARKET,
+            slippage_p: "0.01".to_string(),
+            referral: "".to_string(),
+        },
+        &[coin(collateral, DENOM)],
+    )
+    .unwrap();
+    app.execute_perp(
+        &trader,
+        ExecuteMsg::CloseTradeMarket {
+            index: 0,
+            collateral_delta: None,
+        },
+        &[],
+    )
+    .unwrap();
+}
+
+fn pending_rewards(app: &App, staker: &Addr) -> Vec<Coin> {
+    app.query_staking(StakingQueryMsg::PendingRewards {
+        address: staker.to_string(),
+    })
+    .unwrap()
+}
+
+fn staked(app: &App, staker: &Addr) -> Uint128 {
+    app.query_staking(StakingQueryMsg::Stake {
+        address: staker.to_string(),
+    })
+    .unwrap()
+}
+
+#[test]
+fn stakers_share_closing_fees() {
+    let mut app = set_up();
+    let alice = app.simapp.api().addr_make("alice");
+    let bob = app.simapp.api().addr_make("bob");
+    stake(&mut app, &alice, 300);
+    stake(&mut app, &bob, 100);
+
+    // 0.1% of the 10_000_000 position when opening, then of the 9_900_000
+    // left once the opening fees are paid when closing
+    trade(&mut app, "trader", 1_000_000);
+    assert_eq!(app.balance(&app.staking_addr, DENOM), Uint128::new(19_900));
+    assert_eq!(pending_rewards(&app, &alice), vec![coin(14_925, DENOM)]);
+    assert_eq!(pending_rewards(&app, &bob), vec![coin(4_975, DENOM)]);
+
+    app.execute_staking(&alice, StakingExecuteMsg::ClaimRewards {}, &[])
+        .unwrap();
+    assert_eq!(app.balance(&alice, DENOM), Uint128::new(14_925));
+    assert_eq!(pending_rewards(&app, &alice), vec![coin(0, DENOM)]);
+
+    let err = app
+        .execute_staking(&alice, StakingExecuteMsg::ClaimRewards {}, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NothingToWithdraw
+    );
+
+    // a new staker only earns the fees received after its stake
+    let carol = app.simapp.api().addr_make("carol");
+    stake(&mut app, &carol, 400);
+    trade(&mut app, "other_trader", 1_000_000);
+    assert_eq!(pending_rewards(&app, &alice), vec![coin(7_462, DENOM)]);
+    assert_eq!(
+        pending_rewards(&app, &bob),
+        vec![coin(4_975 + 2_487, DENOM)]
+    );
+    assert_eq!(pending_rewards(&app, &carol), vec![coin(9_950, DENOM)]);
+}
+
+#[test]
+fn removed_reward_denoms_stay_claimable() {
+    let mut app = set_up();
+    let alice = app.simapp.api().addr_make("alice");
+    let bob = app.simapp.api().addr_make("bob");
+    stake(&mut app, &alice, 300);
+    stake(&mut app, &bob, 100);
+    trade(&mut app, "trader", 1_000_000);
+
+    let staking_owner = app.staking_owner.clone();
+    app.execute_staking(
+        &staking_owner,
+        StakingExecuteMsg::UpdateConfig {
+            reward_denoms: Some(vec![]),
+            unstake_cooldown: None,
+        },
+        &[],
+    )
+    .unwrap();
+
+    // the fees received after the removal are not distributed
+    trade(&mut app, "other_trader", 1_000_000);
+    assert_eq!(pending_rewards(&app, &alice), vec![coin(14_925, DENOM)]);
+
+    app.execute_staking(&alice, StakingExecuteMsg::ClaimRewards {}, &[])
+        .unwrap();
+    app.execute_staking(&bob, StakingExecuteMsg::ClaimRewards {}, &[])
+        .unwrap();
+    assert_eq!(app.balance(&alice, DENOM), Uint128::new(14_925));
+    assert_eq!(app.balance(&bob, DENOM), Uint128::new(4_975));
+}
+
+#[test]
+fn unstake_waits_for_cooldown() {
+    let mut app = set_up();
+    let alice = app.simapp.api().addr_make("alice");
+    let bob = app.simapp.api().addr_make("bob");
+    stake(&mut app, &alice, 100);
+    stake(&mut app, &bob, 100);
+
+    let err = app
+        .execute_staking(
+            &alice,
+            StakingExecuteMsg::Unstake {
+                amount: Uint128::new(101),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientStake
+    );
+
+    app.execute_staking(
+        &alice,
+        StakingExecuteMsg::Unstake {
+            amount: Uint128::new(100),
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(staked(&app, &alice), Uint128::zero());
+    let unstakings: Vec<Unstaking> = app
+        .query_staking(StakingQueryMsg::Unstakings {
+            address: alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(unstakings.len(), 1);
+    assert_eq!(unstakings[0].amount, Uint128::new(100));
+
+    // unstaked tokens no longer earn rewards
+    trade(&mut app, "trader", 1_000_000);
+    assert_eq!(pending_rewards(&app, &alice), vec![coin(0, DENOM)]);
+    assert_eq!(pending_rewards(&app, &bob), vec![coin(19_900, DENOM)]);
+
+    let err = app
+        .execute_staking(&alice, StakingExecuteMsg::WithdrawUnstaked {}, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NothingToWithdraw
+    );
+
+    app.advance_time(COOLDOWN);
+    app.execute_staking(&alice, StakingExecuteMsg::WithdrawUnstaked {}, &[])
+        .unwrap();
+    assert_eq!(app.balance(&alice, GOV_DENOM), Uint128::new(100));
+}
+
+#[test]
+fn stake_rejects_other_denoms() {
+    let mut app = set_up();
+    let alice = app.simapp.api().addr_make("alice");
+    app.fund(&alice, &[coin(100, DENOM)]);
+
+    let err = app
+        .execute_staking(
+            &alice,
+            StakingExecuteMsg::Stake {},
+            &[coin(100, DENOM)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidFunds(GOV_DENOM.to_string())
+    );
+}
@@ -0,0 +1,556 @@
+use cosmwasm_std::{coin, Addr, Decimal, Uint128};
+use cw_multi_test::Executor;
+use perp::{
+    error::ContractError as PerpError,
+    msgs::{AdminExecuteMsg, ExecuteMsg},
+    pairs::state::Fee,
+    trading::state::{OpenOrderType, Trade, TradeType},
+    utils::u128_to_dec,
+};
+use vault::{
+    contract::VaultExecuteMsg,
+    error::ContractError,
+    query::{CollateralizationResponse, VaultQueryMsg, WithdrawRequest},
+};
+
+use crate::app::App;
+
+mod app;
+
+const DENOM: &str = "usd";
+const EPOCH: u64 = 86_400;
+
+/// App with pair 0 (btc-usd) at 100, no fees and `liquidity` deposited in
+/// the vault by an LP.
+fn set_up(liquidity: u128) -> (App, Addr) {
+    let mut app = App::default();
+    app.set_up_oracle_asset(0, u128_to_dec(100_u64.into()).unwrap());
+    app.set_up_oracle_collateral(0, Decimal::one());
+    app.create_default_pairs();
+    app.set_up_trading();
+
+    let lp = app.simapp.api().addr_make("lp");
+    app.fund(&lp, &[coin(liquidity, DENOM)]);
+    app.execute_vault(
+        &lp,
+        VaultExecuteMsg::Deposit {},
+        &[coin(liquidity, DENOM)],
+    )
+    .unwrap();
+    (app, lp)
+}
+
+fn open_long_msg(trader: &Addr, collateral: u128) -> ExecuteMsg {
+    open_long_msg_at(trader, collateral, 100)
+}
+
+/// Market long of `collateral` at 10x, expected to open at `price`.
+fn open_long_msg_at(trader: &Addr, collateral: u128, price: u64) -> ExecuteMsg {
+    ExecuteMsg::OpenTrade {
+        trade: Trade {
+            user: trader.clone(),
+            pair_index: 0,
+            index: 0,
+            leverage: Uint128::new(10),
+            long: true,
+            is_open: true,
+            collateral_index: 0,
+            trade_type: TradeType::Trade,
+            collateral_amount: Uint128::new(collateral),
+            open_price: u128_to_dec(price.into()).unwrap(),
+            tp: Decimal::zero(),
+            sl: Decimal::zero(),
+        },
+        order_type: OpenOrderType::MARKET,
+        slippage_p: "0.01".to_string(),
+        referral: "".to_string(),
+    }
+}
+
+fn open_long(app: &mut App, trader: &Addr, collateral: u128) {
+    app.fund(trader, &[coin(collateral, DENOM)]);
+    app.execute_perp(
+        trader,
+        open_long_msg(trader, collateral),
+        &[coin(collateral, DENOM)],
+    )
+    .unwrap();
+}
+
+fn withdraw(shares: u128) -> VaultExecuteMsg {
+    VaultExecuteMsg::Withdraw {
+        denom: DENOM.to_string(),
+        shares: Uint128::new(shares),
+    }
+}
+
+fn close(app: &mut App, trader: &Addr) {
+    app.execute_perp(
+        trader,
+        ExecuteMsg::CloseTradeMarket {
+            index: 0,
+            collateral_delta: None,
+        },
+        &[],
+    )
+    .unwrap();
+}
+
+fn share_price(app: &App) -> Decimal {
+    app.query_vault(VaultQueryMsg::SharePrice {
+        denom: DENOM.to_string(),
+    })
+    .unwrap()
+}
+
+/// Sends `amount` to the vault assets the way perp pays fees and losses.
+fn receive_assets(app: &mut App, amount: u128) {
+    let perp_addr = app.perp_addr.clone();
+    app.fund(&perp_addr, &[coin(amount, DENOM)]);
+    app.execute_vault(
+        &perp_addr,
+        VaultExecuteMsg::ReceiveAssets {},
+        &[coin(amount, DENOM)],
+    )
+    .unwrap();
+}
+
+fn tvl(app: &App) -> Uint128 {
+    app.query_vault(VaultQueryMsg::Tvl {
+        denom: DENOM.to_string(),
+    })
+    .unwrap()
+}
+
+#[test]
+fn lp_deposit_and_withdraw() {
+    let (mut app, lp) = set_up(1_000_000);
+    let bob = app.simapp.api().addr_make("bob");
+
+    let shares: Uint128 = app
+        .query_vault(VaultQueryMsg::Shares {
+            address: lp.to_string(),
+            denom: DENOM.to_string(),
+        })
+        .unwrap();
+    assert_eq!(shares, Uint128::new(1_000_000));
+    assert_eq!(share_price(&app), Decimal::one());
+
+    // coins sent to the vault directly are not part of its assets
+    let vault_addr = app.vault_addr.clone();
+    app.fund(&vault_addr, &[coin(1_000_000, DENOM)]);
+    assert_eq!(tvl(&app), Uint128::new(1_000_000));
+    assert_eq!(share_price(&app), Decimal::one());
+
+    // fees sent by perp raise the value of the existing shares
+    receive_assets(&mut app, 250_000);
+    assert_eq!(tvl(&app), Uint128::new(1_250_000));
+    assert_eq!(share_price(&app), Decimal::percent(125));
+
+    app.fund(&bob, &[coin(500_000, DENOM)]);
+    app.execute_vault(
+        &bob,
+        VaultExecuteMsg::Deposit {},
+        &[coin(500_000, DENOM)],
+    )
+    .unwrap();
+    let bob_shares: Uint128 = app
+        .query_vault(VaultQueryMsg::Shares {
+            address: bob.to_string(),
+            denom: DENOM.to_string(),
+        })
+        .unwrap();
+    assert_eq!(bob_shares, Uint128::new(400_000));
+    assert_eq!(share_price(&app), Decimal::percent(125));
+
+    let err = app
+        .execute_vault(
+            &bob,
+            VaultExecuteMsg::RequestWithdraw {
+                denom: DENOM.to_string(),
+                shares: Uint128::new(400_001),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientShares
+    );
+
+    // no open PnL, the request waits for 2 epochs
+    app.execute_vault(
+        &lp,
+        VaultExecuteMsg::RequestWithdraw {
+            denom: DENOM.to_string(),
+            shares: Uint128::new(1_000_000),
+        },
+        &[],
+    )
+    .unwrap();
+    let requests: Vec<WithdrawRequest> = app
+        .query_vault(VaultQueryMsg::WithdrawRequests {
+            address: lp.to_string(),
+            denom: DENOM.to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        requests,
+        vec![WithdrawRequest {
+            unlock_epoch: 2,
+            shares: Uint128::new(1_000_000),
+        }]
+    );
+
+    app.advance_time(EPOCH);
+    let err = app
+        .execute_vault(&lp, withdraw(1_000_000), &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientUnlockedShares
+    );
+
+    app.advance_time(EPOCH);
+    app.execute_vault(&lp, withdraw(1_000_000), &[]).unwrap();
+    assert_eq!(app.balance(&lp, DENOM), Uint128::new(1_250_000));
+    assert_eq!(tvl(&app), Uint128::new(500_000));
+    assert_eq!(share_price(&app), Decimal::percent(125));
+}
+
+#[test]
+fn withdraw_timelock_grows_with_open_pnl() {
+    let (mut app, lp) = set_up(1_000_000);
+    let trader = app.simapp.api().addr_make("trader");
+    // a 4_000_000 long position
+    open_long(&mut app, &trader, 400_000);
+
+    let test_cases = vec![
+        ("traders at a loss", Decimal::percent(9_500), -200_000, 1),
+        ("no open pnl", Decimal::percent(10_000), 0, 2),
+        ("traders in profit", Decimal::permille(102_500), 100_000, 3),
+    ];
+    for (description, price, open_pnl, expected_timelock) in test_cases {
+        app.set_up_oracle_asset(0, price);
+        let collateralization: CollateralizationResponse = app
+            .query_vault(VaultQueryMsg::Collateralization {
+                denom: DENOM.to_string(),
+            })
+            .unwrap();
+        assert_eq!(
+            collateralization.open_pnl.i128(),
+            open_pnl,
+            "Failed test: {}",
+            description
+        );
+        assert_eq!(
+            collateralization.withdraw_epochs_timelock, expected_timelock,
+            "Failed test: {}",
+            description
+        );
+
+        app.execute_vault(
+            &lp,
+            VaultExecuteMsg::RequestWithdraw {
+                denom: DENOM.to_string(),
+                shares: Uint128::new(1_000),
+            },
+            &[],
+        )
+        .unwrap();
+        let requests: Vec<WithdrawRequest> = app
+            .query_vault(VaultQueryMsg::WithdrawRequests {
+                address: lp.to_string(),
+                denom: DENOM.to_string(),
+            })
+            .unwrap();
+        assert!(
+            requests
+                .iter()
+                .any(|request| request.unlock_epoch == expected_timelock),
+            "Failed test: {}",
+            description
+        );
+    }
+
+    app.execute_vault(
+        &lp,
+        VaultExecuteMsg::CancelWithdrawRequest {
+            denom: DENOM.to_string(),
+            unlock_epoch: 3,
+        },
+        &[],
+    )
+    .unwrap();
+    app.advance_time(3 * EPOCH);
+    let err = app.execute_vault(&lp, withdraw(3_000), &[]).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientUnlockedShares
+    );
+    app.execute_vault(&lp, withdraw(2_000), &[]).unwrap();
+}
+
+#[test]
+fn undercollateralized_vault_refuses_new_trades() {
+    let (mut app, _) = set_up(10_000_000);
+    let trader = app.simapp.api().addr_make("trader");
+    // a 10_000_000 long position
+    open_long(&mut app, &trader, 1_000_000);
+
+    // traders hold 30% of the vault assets in unrealized profits
+    app.set_up_oracle_asset(0, Decimal::percent(13_000));
+    let collateralization: CollateralizationResponse = app
+        .query_vault(VaultQueryMsg::Collateralization {
+            denom: DENOM.to_string(),
+        })
+        .unwrap();
+    assert_eq!(collateralization.collateralization_p, Decimal::percent(70));
+    assert!(collateralization.undercollateralized);
+
+    app.fund(&trader, &[coin(1_000_000, DENOM)]);
+    let err = app
+        .execute_perp(
+            &trader,
+            open_long_msg_at(&trader, 1_000_000, 130),
+            &[coin(1_000_000, DENOM)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<PerpError>().unwrap(),
+        PerpError::VaultUndercollateralized
+    );
+
+    // no one has to report the open pnl for trading to resume
+    app.set_up_oracle_asset(0, Decimal::percent(11_000));
+    app.execute_perp(
+        &trader,
+        open_long_msg_at(&trader, 1_000_000, 110),
+        &[coin(1_000_000, DENOM)],
+    )
+    .unwrap();
+}
+
+#[test]
+fn donations_do_not_move_the_share_price() {
+    let mut app = App::default();
+    let attacker = app.simapp.api().addr_make("attacker");
+    let lp = app.simapp.api().addr_make("lp");
+    let vault_addr = app.vault_addr.clone();
+    app.fund(&attacker, &[coin(1_000_001, DENOM)]);
+    app.fund(&lp, &[coin(1_000_000, DENOM)]);
+
+    // a 1 share first deposit followed by a large donation
+    app.execute_vault(&attacker, VaultExecuteMsg::Deposit {}, &[coin(1, DENOM)])
+        .unwrap();
+    app.simapp
+        .send_tokens(attacker, vault_addr, &[coin(1_000_000, DENOM)])
+        .unwrap();
+    assert_eq!(share_price(&app), Decimal::one());
+
+    app.execute_vault(
+        &lp,
+        VaultExecuteMsg::Deposit {},
+        &[coin(1_000_000, DENOM)],
+    )
+    .unwrap();
+    let shares: Uint128 = app
+        .query_vault(VaultQueryMsg::Shares {
+            address: lp.to_string(),
+            denom: DENOM.to_string(),
+        })
+        .unwrap();
+    assert_eq!(shares, Uint128::new(1_000_000));
+}
+
+#[test]
+fn only_perp_can_send_assets_to_the_vault() {
+    let (mut app, lp) = set_up(1_000_000);
+    app.fund(&lp, &[coin(1_000, DENOM)]);
+
+    let err = app
+        .execute_vault(
+            &lp,
+            VaultExecuteMsg::ReceiveAssets {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Unauthorized
+    );
+}
+
+#[test]
+fn only_perp_can_request_payouts() {
+    let (mut app, lp) = set_up(1_000_000);
+
+    let err = app
+        .execute_vault(
+            &lp,
+            VaultExecuteMsg::SendAssets {
+                denom: DENOM.to_string(),
+                amount: Uint128::new(1_000_000),
+                receiver: lp.to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Unauthorized
+    );
+}
+
+#[test]
+fn trader_profit_paid_by_vault() {
+    let (mut app, _) = set_up(10_000_000);
+    let trader = app.simapp.api().addr_make("trader");
+
+    open_long(&mut app, &trader, 1_000_000);
+    assert_eq!(app.balance(&app.perp_addr, DENOM), Uint128::new(1_000_000));
+
+    // +10% at 10x leverage doubles the collateral
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+    close(&mut app, &trader);
+
+    assert_eq!(app.balance(&trader, DENOM), Uint128::new(2_000_000));
+    assert_eq!(app.balance(&app.perp_addr, DENOM), Uint128::zero());
+    assert_eq!(tvl(&app), Uint128::new(9_000_000));
+    assert_eq!(share_price(&app), Decimal::percent(90));
+}
+
+#[test]
+fn increase_position_settles_funding_with_the_vault() {
+    let (mut app, _) = set_up(10_000_000);
+    app.execute_admin_msgs(vec![AdminExecuteMsg::UpdatePairFundingFees {
+        pair_funding_fees: vec![(
+            (0, 0),
+            Decimal::from_ratio(1_u64, 1_000_000_u64),
+        )],
+    }]);
+    let long_trader = app.simapp.api().addr_make("long_trader");
+    let short_trader = app.simapp.api().addr_make("short_trader");
+    open_long(&mut app, &long_trader, 1_000_000);
+    let ExecuteMsg::OpenTrade {
+        mut trade,
+        order_type,
+        slippage_p,
+        referral,
+    } = open_long_msg(&short_trader, 500_000)
+    else {
+        unreachable!()
+    };
+    trade.long = false;
+    app.fund(&short_trader, &[coin(500_000, DENOM)]);
+    app.execute_perp(
+        &short_trader,
+        ExecuteMsg::OpenTrade {
+            trade,
+            order_type,
+            slippage_p,
+            referral,
+        },
+        &[coin(500_000, DENOM)],
+    )
+    .unwrap();
+
+    // 10_000_000 long vs 5_000_000 short, over 100 blocks the long pays and
+    // the short receives 1e-6 of the skew per block
+    app.advance_time(500);
+    let increase = |app: &mut App, trader: &Addr, collateral: u128| {
+        app.fund(trader, &[coin(collateral, DENOM)]);
+        app.execute_perp(
+            trader,
+            ExecuteMsg::IncreasePosition {
+                index: 0,
+                collateral: Uint128::new(collateral),
+                leverage: Uint128::new(10),
+            },
+            &[coin(collateral, DENOM)],
+        )
+        .unwrap();
+    };
+
+    increase(&mut app, &long_trader, 1_000_000);
+    assert_eq!(app.balance(&app.perp_addr, DENOM), Uint128::new(2_499_500));
+    assert_eq!(
+        app.balance(&app.vault_addr, DENOM),
+        Uint128::new(10_000_500)
+    );
+    assert_eq!(tvl(&app), Uint128::new(10_000_500));
+
+    increase(&mut app, &short_trader, 500_000);
+    assert_eq!(app.balance(&app.perp_addr, DENOM), Uint128::new(3_000_000));
+    assert_eq!(
+        app.balance(&app.vault_addr, DENOM),
+        Uint128::new(10_000_000)
+    );
+    assert_eq!(tvl(&app), Uint128::new(10_000_000));
+}
+
+#[test]
+fn trader_profit_above_vault_liquidity_fails() {
+    let (mut app, _) = set_up(500_000);
+    let trader = app.simapp.api().addr_make("trader");
+
+    open_long(&mut app, &trader, 1_000_000);
+    app.set_up_oracle_asset(0, u128_to_dec(110_u64.into()).unwrap());
+
+    let err = app
+        .execute_perp(
+            &trader,
+            ExecuteMsg::CloseTradeMarket {
+                index: 0,
+                collateral_delta: None,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientAssets
+    );
+}
+
+#[test]
+fn trader_loss_and_closing_fees_sent_to_vault() {
+    let (mut app, _) = set_up(10_000_000);
+    let trader = app.simapp.api().addr_make("trader");
+
+    // -5% at 10x leverage loses half of the collateral
+    open_long(&mut app, &trader, 1_000_000);
+    app.set_up_oracle_asset(0, u128_to_dec(95_u64.into()).unwrap());
+    close(&mut app, &trader);
+
+    assert_eq!(app.balance(&trader, DENOM), Uint128::new(500_000));
+    assert_eq!(tvl(&app), Uint128::new(10_500_000));
+    assert_eq!(share_price(&app), Decimal::percent(105));
+
+    // 0.1% closing fee on the position, half of which goes to the vault
+    app.execute_admin_msgs(vec![AdminExecuteMsg::SetFees {
+        fees: vec![(
+            0,
+            Fee {
+                name: "default".to_string(),
+                open_fee_p: Decimal::zero(),
+                close_fee_p: Decimal::permille(1),
+                oracle_fee_p: Decimal::zero(),
+                trigger_order_fee_p: Decimal::zero(),
+                min_position_size_usd: Uint128::zero(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }]);
+    let other_trader = app.simapp.api().addr_make("other_trader");
+    open_long(&mut app, &other_trader, 1_000_000);
+    close(&mut app, &other_trader);
+
+    let vault_closing_fee = Uint128::new(10_000_000 / 1_000 / 2);
+    assert_eq!(
+        app.balance(&other_trader, DENOM),
+        Uint128::new(1_000_000) - vault_closing_fee
+    );
+    assert_eq!(tvl(&app), Uint128::new(10_500_000) + vault_closing_fee);
+}
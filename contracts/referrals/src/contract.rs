@@ -1,35 +1,145 @@
 use cosmwasm_schema::cw_serde;
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{DepsMut, Env, MessageInfo, Response, Uint128};
+use cosmwasm_std::{
+    Addr, BankMsg, Coin, DepsMut, Empty, Env, MessageInfo, Order, Response,
+    StdResult, Storage, Uint128,
+};
+use cw_storage_plus::Map;
+use cw_utils::may_pay;
 
 use crate::{
     error::ContractError,
     state::{
-        add_admin, remove_admin, Tier, BASIS_POINTS, CODE_OWNERS,
-        REFERRER_DISCOUNT_SHARES, REFERRER_TIERS, TIERS, TRADER_REFERRAL_CODES,
+        add_admin, remove_admin, Tier, BASIS_POINTS, CLAIMABLE_REBATES,
+        CODE_OWNERS, LIFETIME_REBATES, PERP_ADDRESS, REFERRED_VOLUMES,
+        REFERRER_DISCOUNT_SHARES, REFERRER_TIERS, RESERVED_CODES, TIERS,
+        TRADER_REFERRAL_CODES,
     },
-    utils::check_admin,
+    utils::{check_admin, normalize_code, validate_code},
 };
 
 pub const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
 pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[cw_serde]
-pub struct ReferralInstantiateMsg {}
+pub struct ReferralInstantiateMsg {
+    /// Perp contract allowed to credit rebates, which can also be set later
+    /// with `SetPerpAddress`.
+    pub perp_address: Option<String>,
+}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     mut deps: DepsMut,
     _env: Env,
     info: MessageInfo,
-    _msg: ReferralInstantiateMsg,
+    msg: ReferralInstantiateMsg,
 ) -> Result<Response, ContractError> {
     add_admin(deps.branch(), info.sender)?;
+    if let Some(perp_address) = msg.perp_address {
+        PERP_ADDRESS
+            .save(deps.storage, &deps.api.addr_validate(&perp_address)?)?;
+    }
 
     Ok(Response::default())
 }
 
+#[cw_serde]
+pub struct ReferralMigrateMsg {
+    /// Perp contract allowed to credit rebates, required unless already set.
+    pub perp_address: Option<String>,
+}
+
+/// Rebates are credited by the perp contract, so a migrated contract without
+/// a perp address is refused rather than failing every referred trade.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(
+    deps: DepsMut,
+    _env: Env,
+    msg: ReferralMigrateMsg,
+) -> Result<Response, ContractError> {
+    match msg.perp_address {
+        Some(perp_address) => PERP_ADDRESS
+            .save(deps.storage, &deps.api.addr_validate(&perp_address)?)?,
+        None if !PERP_ADDRESS.exists(deps.storage) => {
+            return Err(ContractError::PerpAddressNotSet)
+        }
+        None => {}
+    }
+    let dropped_codes = normalize_stored_codes(deps.storage)?;
+    backfill_indexes(deps.storage)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("dropped_codes", dropped_codes.join(",")))
+}
+
+/// Adds the index entries of the code owners and trader codes stored before
+/// the maps were indexed. Entries already indexed are written unchanged.
+fn backfill_indexes(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let code_owners = CODE_OWNERS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (code, owner) in code_owners {
+        CODE_OWNERS.replace(storage, code, Some(&owner), None)?;
+    }
+
+    let trader_codes = TRADER_REFERRAL_CODES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (trader, code) in trader_codes {
+        TRADER_REFERRAL_CODES.replace(storage, trader, Some(&code), None)?;
+    }
+    Ok(())
+}
+
+/// Rewrites the codes stored before codes were normalized to upper case.
+/// When several codes only differ by case, the first one in byte order, so
+/// the upper-case one if any, keeps the code and the others are dropped,
+/// their traders left without a referrer rather than moved to the owner of
+/// the kept code. Returns the dropped codes.
+fn normalize_stored_codes(
+    storage: &mut dyn Storage,
+) -> Result<Vec<String>, ContractError> {
+    let code_owners = CODE_OWNERS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut dropped_codes = vec![];
+    for (code, owner) in code_owners {
+        let normalized = normalize_code(&String::from_utf8_lossy(&code));
+        if normalized.as_bytes() == code.as_slice() {
+            continue;
+        }
+        CODE_OWNERS.remove(storage, code.clone())?;
+        if CODE_OWNERS.has(storage, normalized.as_bytes().to_vec()) {
+            dropped_codes.push(code);
+        } else {
+            CODE_OWNERS.save(storage, normalized.into_bytes(), &owner)?;
+        }
+    }
+
+    let trader_codes = TRADER_REFERRAL_CODES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (trader, code) in trader_codes {
+        let normalized = normalize_code(&String::from_utf8_lossy(&code));
+        if dropped_codes.contains(&code) {
+            TRADER_REFERRAL_CODES.remove(storage, trader)?;
+        } else if normalized.as_bytes() != code.as_slice() {
+            TRADER_REFERRAL_CODES.save(
+                storage,
+                trader,
+                &normalized.into_bytes(),
+            )?;
+        }
+    }
+    Ok(dropped_codes
+        .iter()
+        .map(|code| String::from_utf8_lossy(code).into_owned())
+        .collect())
+}
+
 #[cw_serde]
 pub enum ReferralsExecuteMsg {
     SetTier {
@@ -65,6 +175,35 @@ pub enum ReferralsExecuteMsg {
     RegisterCode {
         code: String,
     },
+    SetPerpAddress {
+        perp_address: String,
+    },
+    /// Credits the funds sent to the referrer as a rebate, and the position
+    /// size opened by its referred trader. Perp only.
+    CreditRebate {
+        referrer: String,
+        denom: String,
+        volume: Uint128,
+    },
+    /// Sends the rebates of the sender for every denom.
+    ClaimRewards {},
+    /// Gives a code owned by the sender to another account.
+    TransferCode {
+        code: String,
+        new_owner: String,
+    },
+    /// Releases a code owned by the sender. Its traders no longer have a
+    /// referrer, and are not handed to whoever registers it next.
+    RenounceCode {
+        code: String,
+    },
+    /// Blocks codes from being registered by users. Admin only.
+    ReserveCodes {
+        codes: Vec<String>,
+    },
+    UnreserveCodes {
+        codes: Vec<String>,
+    },
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -118,6 +257,29 @@ pub fn execute(
         ReferralsExecuteMsg::RegisterCode { code } => {
             execute_register_code(deps, env, info, code)
         }
+        ReferralsExecuteMsg::SetPerpAddress { perp_address } => {
+            execute_set_perp_address(deps, env, info, perp_address)
+        }
+        ReferralsExecuteMsg::CreditRebate {
+            referrer,
+            denom,
+            volume,
+        } => execute_credit_rebate(deps, info, referrer, denom, volume),
+        ReferralsExecuteMsg::ClaimRewards {} => {
+            execute_claim_rewards(deps, info)
+        }
+        ReferralsExecuteMsg::TransferCode { code, new_owner } => {
+            execute_transfer_code(deps, info, code, new_owner)
+        }
+        ReferralsExecuteMsg::RenounceCode { code } => {
+            execute_renounce_code(deps, info, code)
+        }
+        ReferralsExecuteMsg::ReserveCodes { codes } => {
+            execute_reserve_codes(deps, env, info, codes, true)
+        }
+        ReferralsExecuteMsg::UnreserveCodes { codes } => {
+            execute_reserve_codes(deps, env, info, codes, false)
+        }
     }
 }
 
@@ -128,9 +290,10 @@ pub fn execute_register_code(
     code: String,
 ) -> Result<Response, ContractError> {
     let account = info.sender;
+    let code = validate_code(&code)?;
 
-    if code.as_bytes() == [0u8; 32] {
-        return Err(ContractError::InvalidCode {});
+    if RESERVED_CODES.has(deps.storage, code.as_bytes().to_vec()) {
+        return Err(ContractError::CodeReserved {});
     }
 
     if CODE_OWNERS.has(deps.storage, code.as_bytes().to_vec()) {
@@ -238,7 +401,6 @@ pub fn execute_set_referrer_discount_share(
     check_admin(deps.as_ref(), _env, info)?;
 
     let referrer = deps.api.addr_validate(&referrer)?;
-    let discount_share = discount_share;
 
     REFERRER_DISCOUNT_SHARES.save(
         deps.storage,
@@ -262,6 +424,7 @@ pub fn execute_set_trader_referral_code(
     check_admin(deps.as_ref(), _env, info)?;
 
     let account = deps.api.addr_validate(&account)?;
+    let code = normalize_code(&code);
 
     TRADER_REFERRAL_CODES.save(
         deps.storage,
@@ -282,6 +445,11 @@ pub fn execute_set_trader_referral_code_by_user(
     code: String,
 ) -> Result<Response, ContractError> {
     let account = info.sender;
+    let code = validate_code(&code)?;
+
+    if !CODE_OWNERS.has(deps.storage, code.as_bytes().to_vec()) {
+        return Err(ContractError::CodeNotFound {});
+    }
 
     TRADER_REFERRAL_CODES.save(
         deps.storage,
@@ -305,6 +473,7 @@ pub fn execute_set_code_owner(
     check_admin(deps.as_ref(), _env, info)?;
 
     let owner = deps.api.addr_validate(&owner)?;
+    let code = validate_code(&code)?;
 
     CODE_OWNERS.save(deps.storage, code.as_bytes().to_vec(), &owner)?;
 
@@ -313,3 +482,327 @@ pub fn execute_set_code_owner(
         .add_attribute("code", code)
         .add_attribute("owner", owner.to_string()))
 }
+
+pub fn execute_set_perp_address(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    perp_address: String,
+) -> Result<Response, ContractError> {
+    check_admin(deps.as_ref(), env, info)?;
+
+    let perp_address = deps.api.addr_validate(&perp_address)?;
+    PERP_ADDRESS.save(deps.storage, &perp_address)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_perp_address")
+        .add_attribute("perp_address", perp_address.to_string()))
+}
+
+pub fn execute_credit_rebate(
+    deps: DepsMut,
+    info: MessageInfo,
+    referrer: String,
+    denom: String,
+    volume: Uint128,
+) -> Result<Response, ContractError> {
+    if PERP_ADDRESS.may_load(deps.storage)? != Some(info.sender.clone()) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let referrer = deps.api.addr_validate(&referrer)?;
+    let rebate = may_pay(&info, &denom)?;
+    for (map, amount) in [
+        (CLAIMABLE_REBATES, rebate),
+        (LIFETIME_REBATES, rebate),
+        (REFERRED_VOLUMES, volume),
+    ] {
+        add_amount(deps.storage, map, &referrer, &denom, amount)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "credit_rebate")
+        .add_attribute("referrer", referrer.to_string())
+        .add_attribute("rebate", Coin::new(rebate, denom).to_string())
+        .add_attribute("volume", volume.to_string()))
+}
+
+pub fn execute_claim_rewards(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let rewards = CLAIMABLE_REBATES
+        .prefix(&info.sender)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| !matches!(item, Ok((_, amount)) if amount.is_zero()))
+        .map(|item| item.map(|(denom, amount)| Coin::new(amount, denom)))
+        .collect::<StdResult<Vec<_>>>()?;
+    if rewards.is_empty() {
+        return Err(ContractError::NothingToClaim);
+    }
+    for reward in &rewards {
+        CLAIMABLE_REBATES.remove(deps.storage, (&info.sender, &reward.denom));
+    }
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: rewards.clone(),
+        })
+        .add_attribute("action", "claim_rewards")
+        .add_attribute("referrer", info.sender.to_string())
+        .add_attribute(
+            "rewards",
+            rewards
+                .iter()
+                .map(Coin::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ))
+}
+
+pub fn execute_transfer_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code: String,
+    new_owner: String,
+) -> Result<Response, ContractError> {
+    let code = load_owned_code(deps.as_ref().storage, &info, &code)?;
+    let new_owner = deps.api.addr_validate(&new_owner)?;
+
+    CODE_OWNERS.save(deps.storage, code.as_bytes().to_vec(), &new_owner)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "transfer_code")
+        .add_attribute("code", code)
+        .add_attribute("owner", info.sender.to_string())
+        .add_attribute("new_owner", new_owner.to_string()))
+}
+
+pub fn execute_renounce_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code: String,
+) -> Result<Response, ContractError> {
+    let code = load_owned_code(deps.as_ref().storage, &info, &code)?;
+
+    CODE_OWNERS.remove(deps.storage, code.as_bytes().to_vec())?;
+    let traders = TRADER_REFERRAL_CODES
+        .idx
+        .code
+        .prefix(code.as_bytes().to_vec())
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for trader in &traders {
+        TRADER_REFERRAL_CODES.remove(deps.storage, trader.clone())?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "renounce_code")
+        .add_attribute("code", code)
+        .add_attribute("owner", info.sender.to_string())
+        .add_attribute("unbound_traders", traders.len().to_string()))
+}
+
+pub fn execute_reserve_codes(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    codes: Vec<String>,
+    reserved: bool,
+) -> Result<Response, ContractError> {
+    check_admin(deps.as_ref(), env, info)?;
+
+    let codes = codes
+        .iter()
+        .map(|code| validate_code(code))
+        .collect::<Result<Vec<_>, _>>()?;
+    for code in &codes {
+        if reserved {
+            RESERVED_CODES.save(
+                deps.storage,
+                code.as_bytes().to_vec(),
+                &Empty {},
+            )?;
+        } else {
+            RESERVED_CODES.remove(deps.storage, code.as_bytes().to_vec());
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute(
+            "action",
+            if reserved {
+                "reserve_codes"
+            } else {
+                "unreserve_codes"
+            },
+        )
+        .add_attribute("codes", codes.join(",")))
+}
+
+/// Returns the normalized code if it is owned by the sender.
+fn load_owned_code(
+    storage: &dyn Storage,
+    info: &MessageInfo,
+    code: &str,
+) -> Result<String, ContractError> {
+    let code = normalize_code(code);
+    match CODE_OWNERS.may_load(storage, code.as_bytes().to_vec())? {
+        Some(owner) if owner == info.sender => Ok(code),
+        Some(_) => Err(ContractError::Unauthorized {}),
+        None => Err(ContractError::CodeNotFound {}),
+    }
+}
+
+fn add_amount(
+    storage: &mut dyn Storage,
+    map: Map<(&Addr, &str), Uint128>,
+    referrer: &Addr,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+    map.update(
+        storage,
+        (referrer, denom),
+        |total| -> Result<_, ContractError> {
+            Ok(total.unwrap_or_default().checked_add(amount)?)
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi};
+
+    fn migrate_msg() -> ReferralMigrateMsg {
+        ReferralMigrateMsg {
+            perp_address: Some(MockApi::default().addr_make("perp").to_string()),
+        }
+    }
+
+    #[test]
+    fn migrate_sets_the_perp_address() {
+        let mut deps = mock_dependencies();
+        let perp = deps.api.addr_make("perp");
+
+        assert_eq!(
+            migrate(
+                deps.as_mut(),
+                mock_env(),
+                ReferralMigrateMsg { perp_address: None },
+            ),
+            Err(ContractError::PerpAddressNotSet)
+        );
+        migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap();
+        assert_eq!(PERP_ADDRESS.load(deps.as_ref().storage).unwrap(), perp);
+
+        // later migrations keep it
+        migrate(
+            deps.as_mut(),
+            mock_env(),
+            ReferralMigrateMsg { perp_address: None },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn migrate_normalizes_stored_codes() {
+        let mut deps = mock_dependencies();
+        let owners: Vec<Addr> = ["owner1", "owner2", "owner3"]
+            .iter()
+            .map(|owner| deps.api.addr_make(owner))
+            .collect();
+        let trader = deps.api.addr_make("trader");
+        let other_trader = deps.api.addr_make("other_trader");
+
+        // codes stored as they were typed, before the normalization
+        let code_owners: Map<Vec<u8>, Addr> = Map::new("code_owners");
+        let trader_codes: Map<Addr, Vec<u8>> = Map::new("trader_referral_codes");
+        for (code, owner) in [
+            ("Alice", &owners[0]),
+            ("alice", &owners[1]),
+            ("BOB", &owners[2]),
+        ] {
+            code_owners
+                .save(deps.as_mut().storage, code.as_bytes().to_vec(), owner)
+                .unwrap();
+        }
+        for (trader, code) in [(&trader, "Alice"), (&other_trader, "alice")] {
+            trader_codes
+                .save(
+                    deps.as_mut().storage,
+                    trader.clone(),
+                    &code.as_bytes().to_vec(),
+                )
+                .unwrap();
+        }
+
+        let res = migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "dropped_codes" && attr.value == "alice"));
+
+        let codes = CODE_OWNERS
+            .range(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            codes,
+            vec![
+                (b"ALICE".to_vec(), owners[0].clone()),
+                (b"BOB".to_vec(), owners[2].clone()),
+            ]
+        );
+        assert_eq!(
+            TRADER_REFERRAL_CODES
+                .load(deps.as_ref().storage, trader)
+                .unwrap(),
+            b"ALICE".to_vec()
+        );
+        // the traders of the dropped code are not given to another referrer
+        assert!(!TRADER_REFERRAL_CODES.has(deps.as_ref().storage, other_trader));
+    }
+
+    #[test]
+    fn migrate_backfills_indexes() {
+        let mut deps = mock_dependencies();
+        let owner = deps.api.addr_make("owner");
+        let trader = deps.api.addr_make("trader");
+
+        // entries stored before the maps were indexed
+        let code_owners: Map<Vec<u8>, Addr> = Map::new("code_owners");
+        let trader_codes: Map<Addr, Vec<u8>> = Map::new("trader_referral_codes");
+        code_owners
+            .save(deps.as_mut().storage, b"ALICE".to_vec(), &owner)
+            .unwrap();
+        trader_codes
+            .save(deps.as_mut().storage, trader.clone(), &b"ALICE".to_vec())
+            .unwrap();
+
+        migrate(deps.as_mut(), mock_env(), migrate_msg()).unwrap();
+
+        let codes = CODE_OWNERS
+            .idx
+            .owner
+            .prefix(owner)
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(codes, vec![b"ALICE".to_vec()]);
+        let traders = TRADER_REFERRAL_CODES
+            .idx
+            .code
+            .prefix(b"ALICE".to_vec())
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(traders, vec![trader]);
+    }
+}
@@ -1,4 +1,5 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{OverflowError, StdError};
+use cw_utils::PaymentError;
 use nibiru_ownable::OwnershipError;
 use thiserror::Error;
 
@@ -7,6 +8,12 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
     #[error("serde_json error: {0}")]
     SerdeJson(String),
 
@@ -16,6 +23,12 @@ pub enum ContractError {
     #[error("code already claimed")]
     CodeAlreadyClaimed,
 
+    #[error("code reserved")]
+    CodeReserved,
+
+    #[error("code not found")]
+    CodeNotFound,
+
     #[error("invalid total")]
     InvalidTotalRebate,
 
@@ -24,6 +37,12 @@ pub enum ContractError {
 
     #[error("unauthorized")]
     Unauthorized,
+
+    #[error("the perp address is not set")]
+    PerpAddressNotSet,
+
+    #[error("no rewards to claim")]
+    NothingToClaim,
 }
 
 impl From<serde_json::Error> for ContractError {
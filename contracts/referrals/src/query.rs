@@ -1,11 +1,18 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_json_binary, Binary, Deps, Env, StdError, StdResult, Uint128};
+use cosmwasm_std::{
+    to_json_binary, Addr, Binary, Coin, Deps, Env, Order, StdError, StdResult,
+    Uint128,
+};
+use cw_storage_plus::{Bound, Map};
 
 use crate::state::{
-    Tier, CODE_OWNERS, REFERRER_DISCOUNT_SHARES, REFERRER_TIERS, TIERS, TRADER_REFERRAL_CODES,
+    Tier, CLAIMABLE_REBATES, CODE_OWNERS, LIFETIME_REBATES, REFERRALS_ADMINS,
+    REFERRED_VOLUMES, REFERRER_DISCOUNT_SHARES, REFERRER_TIERS, RESERVED_CODES,
+    TIERS, TRADER_REFERRAL_CODES,
 };
+use crate::utils::normalize_code;
 
 #[cw_serde]
 #[derive(QueryResponses)]
@@ -40,12 +47,88 @@ pub enum ReferralsQueryMsg {
         account: String,
     },
 
+    // Returns the referrer of an account, none if the account has no code or
+    // its code no owner.
+    #[returns(Option<Addr>)]
+    TraderReferrer { account: String },
+
+    // Returns the tier of a referrer, none if it has no tier.
+    #[returns(Option<Uint128>)]
+    ReferrerTier { account: String },
+
+    // Returns whether a code is reserved by the admins.
+    #[returns(bool)]
+    IsCodeReserved {
+        code: String,
+    },
+
     #[returns(Tier)]
     GetTier {
         tier_id: Uint128,
     },
+
+    // Returns the rebates a referrer can claim, per denom.
+    #[returns(Vec<Coin>)]
+    ClaimableRewards {
+        referrer: String,
+    },
+
+    // Returns the rebates credited to a referrer since the start, per denom.
+    #[returns(Vec<Coin>)]
+    LifetimeRebates {
+        referrer: String,
+    },
+
+    // Returns the position size opened by the traders of a referrer, per
+    // denom.
+    #[returns(Vec<Coin>)]
+    ReferredVolume {
+        referrer: String,
+    },
+
+    // Lists the codes owned by an account.
+    #[returns(Vec<String>)]
+    CodesByOwner {
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    // Lists the traders bound to a code.
+    #[returns(Vec<String>)]
+    TradersByCode {
+        code: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    // Lists the traders bound to the codes of a referrer, as (code, trader)
+    // pairs ordered by code. `start_after` is the last pair returned.
+    #[returns(Vec<(String, String)>)]
+    TradersByReferrer {
+        referrer: String,
+        start_after: Option<(String, String)>,
+        limit: Option<u32>,
+    },
+
+    // Lists the tiers.
+    #[returns(Vec<(Uint128, Tier)>)]
+    Tiers {
+        start_after: Option<Uint128>,
+        limit: Option<u32>,
+    },
+
+    // Lists the admins.
+    #[returns(Vec<String>)]
+    Admins {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
 
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, _env: Env, msg: ReferralsQueryMsg) -> StdResult<Binary> {
     match msg {
@@ -64,12 +147,208 @@ pub fn query(deps: Deps, _env: Env, msg: ReferralsQueryMsg) -> StdResult<Binary>
         ReferralsQueryMsg::GetTraderReferralInfo {
             account,
         } => get_trader_referral_info(deps, &account),
+        ReferralsQueryMsg::TraderReferrer { account } => {
+            to_json_binary(&load_trader_referrer(deps, &account)?)
+        }
+        ReferralsQueryMsg::ReferrerTier { account } => to_json_binary(
+            &REFERRER_TIERS
+                .may_load(deps.storage, deps.api.addr_validate(&account)?)?,
+        ),
+        ReferralsQueryMsg::IsCodeReserved {
+            code,
+        } => to_json_binary(
+            &RESERVED_CODES.has(deps.storage, normalize_code(&code).as_bytes().to_vec()),
+        ),
         ReferralsQueryMsg::GetTier {
             tier_id,
         } => get_tier(deps, tier_id),
+        ReferralsQueryMsg::ClaimableRewards {
+            referrer,
+        } => get_referrer_amounts(deps, CLAIMABLE_REBATES, &referrer),
+        ReferralsQueryMsg::LifetimeRebates {
+            referrer,
+        } => get_referrer_amounts(deps, LIFETIME_REBATES, &referrer),
+        ReferralsQueryMsg::ReferredVolume {
+            referrer,
+        } => get_referrer_amounts(deps, REFERRED_VOLUMES, &referrer),
+        ReferralsQueryMsg::CodesByOwner {
+            owner,
+            start_after,
+            limit,
+        } => get_codes_by_owner(deps, &owner, start_after, limit),
+        ReferralsQueryMsg::TradersByCode {
+            code,
+            start_after,
+            limit,
+        } => get_traders_by_code(deps, &code, start_after, limit),
+        ReferralsQueryMsg::TradersByReferrer {
+            referrer,
+            start_after,
+            limit,
+        } => get_traders_by_referrer(deps, &referrer, start_after, limit),
+        ReferralsQueryMsg::Tiers { start_after, limit } => {
+            get_tiers(deps, start_after, limit)
+        }
+        ReferralsQueryMsg::Admins { start_after, limit } => {
+            get_admins(deps, start_after, limit)
+        }
     }
 }
 
+fn get_codes_by_owner(
+    deps: Deps<'_>,
+    owner: &str,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Binary, StdError> {
+    let owner = deps.api.addr_validate(owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|code| Bound::exclusive(code_key(&code)));
+
+    let codes = CODE_OWNERS
+        .idx
+        .owner
+        .prefix(owner)
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|code| code.map(|code| code_string(&code)))
+        .collect::<StdResult<Vec<_>>>()?;
+    to_json_binary(&codes)
+}
+
+fn get_traders_by_code(
+    deps: Deps<'_>,
+    code: &str,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Binary, StdError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|trader| deps.api.addr_validate(&trader).map(Bound::exclusive))
+        .transpose()?;
+
+    let traders = TRADER_REFERRAL_CODES
+        .idx
+        .code
+        .prefix(code_key(code))
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|trader| trader.map(|trader| trader.to_string()))
+        .collect::<StdResult<Vec<_>>>()?;
+    to_json_binary(&traders)
+}
+
+fn get_traders_by_referrer(
+    deps: Deps<'_>,
+    referrer: &str,
+    start_after: Option<(String, String)>,
+    limit: Option<u32>,
+) -> Result<Binary, StdError> {
+    let referrer = deps.api.addr_validate(referrer)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after
+        .map(|(code, trader)| {
+            deps.api
+                .addr_validate(&trader)
+                .map(|trader| (code_key(&code), trader))
+        })
+        .transpose()?;
+
+    // resumes from the code of the last pair, after its trader
+    let codes = CODE_OWNERS
+        .idx
+        .owner
+        .prefix(referrer)
+        .keys(
+            deps.storage,
+            start_after
+                .as_ref()
+                .map(|(code, _)| Bound::inclusive(code.clone())),
+            None,
+            Order::Ascending,
+        )
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut traders = vec![];
+    for code in codes {
+        let start = match &start_after {
+            Some((start_code, trader)) if *start_code == code => {
+                Some(Bound::exclusive(trader.clone()))
+            }
+            _ => None,
+        };
+        for trader in TRADER_REFERRAL_CODES
+            .idx
+            .code
+            .prefix(code.clone())
+            .keys(deps.storage, start, None, Order::Ascending)
+            .take(limit - traders.len())
+        {
+            traders.push((code_string(&code), trader?.to_string()));
+        }
+        if traders.len() == limit {
+            break;
+        }
+    }
+    to_json_binary(&traders)
+}
+
+fn get_tiers(
+    deps: Deps<'_>,
+    start_after: Option<Uint128>,
+    limit: Option<u32>,
+) -> Result<Binary, StdError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|tier_id| Bound::exclusive(tier_id.u128()));
+
+    let tiers = TIERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(tier_id, tier)| (Uint128::new(tier_id), tier)))
+        .collect::<StdResult<Vec<_>>>()?;
+    to_json_binary(&tiers)
+}
+
+fn get_admins(
+    deps: Deps<'_>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Binary, StdError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|admin| deps.api.addr_validate(&admin).map(Bound::exclusive))
+        .transpose()?;
+
+    let admins = REFERRALS_ADMINS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|admin| admin.map(|admin| admin.to_string()))
+        .collect::<StdResult<Vec<_>>>()?;
+    to_json_binary(&admins)
+}
+
+/// Storage key of a code, which is stored normalized.
+fn code_key(code: &str) -> Vec<u8> {
+    normalize_code(code).into_bytes()
+}
+
+fn code_string(code: &[u8]) -> String {
+    String::from_utf8_lossy(code).into_owned()
+}
+
+fn get_referrer_amounts(
+    deps: Deps<'_>,
+    map: Map<(&Addr, &str), Uint128>,
+    referrer: &str,
+) -> Result<Binary, StdError> {
+    let referrer = deps.api.addr_validate(referrer)?;
+    let amounts = map
+        .prefix(&referrer)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin::new(amount, denom)))
+        .collect::<StdResult<Vec<_>>>()?;
+    to_json_binary(&amounts)
+}
+
 fn get_tier(deps: Deps<'_>, tier_id: Uint128) -> Result<Binary, StdError> {
     let tier: Tier = TIERS.load(deps.storage, tier_id.u128())?;
     to_json_binary(&tier)
@@ -89,6 +368,18 @@ fn get_trader_referral_info(deps: Deps<'_>, account: &str) -> Result<Binary, Std
     }
 }
 
+/// Owner of the code the trader is bound to, if both exist.
+fn load_trader_referrer(
+    deps: Deps<'_>,
+    account: &str,
+) -> StdResult<Option<Addr>> {
+    let address = deps.api.addr_validate(account)?;
+    match TRADER_REFERRAL_CODES.may_load(deps.storage, address)? {
+        Some(code) => CODE_OWNERS.may_load(deps.storage, code),
+        None => Ok(None),
+    }
+}
+
 fn get_referrer_tiers(deps: Deps<'_>, account: &str) -> Result<Binary, StdError> {
     let tier = REFERRER_TIERS.may_load(deps.storage, deps.api.addr_validate(account)?)?;
     match tier {
@@ -118,7 +409,7 @@ fn get_codes_owner(deps: Deps<'_>, codes: Vec<String>) -> Result<Binary, StdErro
 }
 
 fn load_code_owner(deps: Deps<'_>, code: &str) -> Result<String, StdError> {
-    match CODE_OWNERS.may_load(deps.storage, code.as_bytes().to_vec()) {
+    match CODE_OWNERS.may_load(deps.storage, normalize_code(code).as_bytes().to_vec()) {
         Ok(Some(owner)) => Ok(owner.to_string()),
         Ok(None) => Ok("".to_string()),
         Err(e) => Err(e),
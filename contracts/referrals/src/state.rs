@@ -1,6 +1,6 @@
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{Addr, Deps, DepsMut, Empty, StdResult, Uint128};
-use cw_storage_plus::Map;
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
 
 #[derive(Default)]
 #[cw_serde]
@@ -16,11 +16,77 @@ pub const REFERRER_TIERS: Map<Addr, Uint128> = Map::new("referrer_tiers");
 
 pub const TIERS: Map<u128, Tier> = Map::new("tiers");
 
-pub const CODE_OWNERS: Map<Vec<u8>, Addr> = Map::new("code_owners");
-pub const TRADER_REFERRAL_CODES: Map<Addr, Vec<u8>> = Map::new("trader_referral_codes");
+pub const MIN_CODE_LENGTH: usize = 3;
+pub const MAX_CODE_LENGTH: usize = 20;
+
+pub struct CodeOwnerIndexes<'a> {
+    pub owner: MultiIndex<'a, Addr, Addr, Vec<u8>>,
+}
+
+impl IndexList<Addr> for CodeOwnerIndexes<'_> {
+    fn get_indexes(
+        &'_ self,
+    ) -> Box<dyn Iterator<Item = &'_ dyn Index<Addr>> + '_> {
+        let v: Vec<&dyn Index<Addr>> = vec![&self.owner];
+        Box::new(v.into_iter())
+    }
+}
+
+pub struct TraderCodeIndexes<'a> {
+    pub code: MultiIndex<'a, Vec<u8>, Vec<u8>, Addr>,
+}
+
+impl IndexList<Vec<u8>> for TraderCodeIndexes<'_> {
+    fn get_indexes(
+        &'_ self,
+    ) -> Box<dyn Iterator<Item = &'_ dyn Index<Vec<u8>>> + '_> {
+        let v: Vec<&dyn Index<Vec<u8>>> = vec![&self.code];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Owner of each code, indexed by owner.
+pub const CODE_OWNERS: IndexedMap<Vec<u8>, Addr, CodeOwnerIndexes> =
+    IndexedMap::new(
+        "code_owners",
+        CodeOwnerIndexes {
+            owner: MultiIndex::new(
+                |_, owner| owner.clone(),
+                "code_owners",
+                "code_owners__owner",
+            ),
+        },
+    );
+/// Codes blocked by the admins, which only they can assign.
+pub const RESERVED_CODES: Map<Vec<u8>, Empty> = Map::new("reserved_codes");
+/// Code used by each trader, indexed by code.
+pub const TRADER_REFERRAL_CODES: IndexedMap<Addr, Vec<u8>, TraderCodeIndexes> =
+    IndexedMap::new(
+        "trader_referral_codes",
+        TraderCodeIndexes {
+            code: MultiIndex::new(
+                |_, code| code.clone(),
+                "trader_referral_codes",
+                "trader_referral_codes__code",
+            ),
+        },
+    );
 
 pub const REFERRALS_ADMINS: Map<Addr, Empty> = Map::new("referrals_admins");
 
+/// Perp contract allowed to credit rebates to referrers.
+pub const PERP_ADDRESS: Item<Addr> = Item::new("perp_address");
+
+/// Rebates not claimed yet, per referrer and denom.
+pub const CLAIMABLE_REBATES: Map<(&Addr, &str), Uint128> =
+    Map::new("claimable_rebates");
+/// Rebates credited since the start, per referrer and denom.
+pub const LIFETIME_REBATES: Map<(&Addr, &str), Uint128> =
+    Map::new("lifetime_rebates");
+/// Position size opened by the referred traders, per referrer and denom.
+pub const REFERRED_VOLUMES: Map<(&Addr, &str), Uint128> =
+    Map::new("referred_volumes");
+
 pub fn add_admin(deps: DepsMut, addr: Addr) -> StdResult<()> {
     REFERRALS_ADMINS.save(deps.storage, addr, &Empty::default())
 }
@@ -1,6 +1,9 @@
 use cosmwasm_std::{Deps, Env, MessageInfo};
 
-use crate::{error::ContractError, state::is_admin};
+use crate::{
+    error::ContractError,
+    state::{is_admin, MAX_CODE_LENGTH, MIN_CODE_LENGTH},
+};
 
 /// Check if the sender is the admin
 pub fn check_admin(
@@ -16,3 +19,22 @@ pub fn check_admin(
         Err(ContractError::Unauthorized {})
     }
 }
+
+/// Codes are case insensitive and stored in upper case.
+pub fn normalize_code(code: &str) -> String {
+    code.to_ascii_uppercase()
+}
+
+/// Checks the length and charset of a new code and returns it normalized.
+/// Only ASCII letters, digits, `_` and `-` are allowed to avoid confusable
+/// codes.
+pub fn validate_code(code: &str) -> Result<String, ContractError> {
+    let valid_length = (MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&code.len());
+    let valid_charset = code
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_length || !valid_charset {
+        return Err(ContractError::InvalidCode {});
+    }
+    Ok(normalize_code(code))
+}
@@ -0,0 +1,335 @@
+use cosmwasm_schema::cw_serde;
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    Addr, BankMsg, Coin, DepsMut, Env, MessageInfo, Order, Response, StdResult,
+    Uint128,
+};
+use cw2::set_contract_version;
+use nibiru_ownable::ownable_execute;
+
+use crate::{
+    error::ContractError,
+    state::{
+        reward_indexes, staker_rewards, Config, RewardIndex, StakerReward,
+        Unstaking, ACC_REWARD_PER_SHARE, CONFIG, DEFAULT_UNSTAKE_COOLDOWN,
+        DISTRIBUTED_BALANCES, STAKER_REWARDS, STAKES, TOTAL_STAKED, UNSTAKINGS,
+    },
+};
+
+pub const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[cw_serde]
+pub struct StakingInstantiateMsg {
+    pub owner: Option<String>,
+    pub gov_denom: String,
+    pub reward_denoms: Vec<String>,
+    pub unstake_cooldown: Option<u64>,
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: StakingInstantiateMsg,
+) -> Result<Response, ContractError> {
+    set_contract_version(
+        deps.storage,
+        format!("crates.io:{CONTRACT_NAME}"),
+        CONTRACT_VERSION,
+    )?;
+
+    nibiru_ownable::initialize_owner(deps.storage, msg.owner.as_deref())?;
+    let config = Config {
+        gov_denom: msg.gov_denom,
+        reward_denoms: msg.reward_denoms,
+        unstake_cooldown: msg
+            .unstake_cooldown
+            .unwrap_or(DEFAULT_UNSTAKE_COOLDOWN),
+    };
+    validate_config(&config)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+#[ownable_execute]
+#[cw_serde]
+pub enum StakingExecuteMsg {
+    /// Stakes the gov tokens sent with the message.
+    Stake {},
+    /// Stops the staking of `amount`, withdrawable after the cooldown.
+    Unstake { amount: Uint128 },
+    /// Withdraws the unstaked tokens whose cooldown elapsed.
+    WithdrawUnstaked {},
+    /// Sends the pending rewards of every reward denom, including the denoms
+    /// removed from the config since they accrued.
+    ClaimRewards {},
+    UpdateConfig {
+        reward_denoms: Option<Vec<String>>,
+        unstake_cooldown: Option<u64>,
+    },
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: StakingExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        StakingExecuteMsg::Stake {} => execute_stake(deps, env, info),
+        StakingExecuteMsg::Unstake { amount } => {
+            execute_unstake(deps, env, info, amount)
+        }
+        StakingExecuteMsg::WithdrawUnstaked {} => {
+            execute_withdraw_unstaked(deps, env, info)
+        }
+        StakingExecuteMsg::ClaimRewards {} => {
+            execute_claim_rewards(deps, env, info)
+        }
+        StakingExecuteMsg::UpdateConfig {
+            reward_denoms,
+            unstake_cooldown,
+        } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            execute_update_config(deps, env, reward_denoms, unstake_cooldown)
+        }
+        StakingExecuteMsg::UpdateOwnership(action) => {
+            let ownership = nibiru_ownable::update_ownership(
+                deps,
+                &env.block,
+                info.sender.as_str(),
+                action,
+            )?;
+            Ok(Response::new().add_attributes(ownership.into_attributes()))
+        }
+    }
+}
+
+/// Distributes the rewards received so far to the current stakers.
+fn distribute_rewards(
+    deps: &mut DepsMut,
+    env: &Env,
+) -> Result<Vec<RewardIndex>, ContractError> {
+    let indexes = reward_indexes(deps.as_ref(), env)?;
+    for index in &indexes {
+        ACC_REWARD_PER_SHARE.save(
+            deps.storage,
+            &index.denom,
+            &index.acc_reward_per_share,
+        )?;
+        if let Some(balance) = index.distributed_balance {
+            DISTRIBUTED_BALANCES.save(deps.storage, &index.denom, &balance)?;
+        }
+    }
+    Ok(indexes)
+}
+
+/// Settles the rewards of `staker`, to be called before its stake changes.
+fn update_rewards(
+    deps: &mut DepsMut,
+    env: &Env,
+    staker: &Addr,
+) -> Result<(), ContractError> {
+    let indexes = distribute_rewards(deps, env)?;
+    for (denom, reward) in staker_rewards(deps.storage, staker, &indexes)? {
+        STAKER_REWARDS.save(deps.storage, (staker, &denom), &reward)?;
+    }
+    Ok(())
+}
+
+fn execute_stake(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let gov_denom = CONFIG.load(deps.storage)?.gov_denom;
+    let amount = match info.funds.as_slice() {
+        [coin] if coin.denom == gov_denom => coin.amount,
+        _ => return Err(ContractError::InvalidFunds(gov_denom)),
+    };
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount);
+    }
+
+    update_rewards(&mut deps, &env, &info.sender)?;
+    STAKES.update(
+        deps.storage,
+        &info.sender,
+        |stake| -> Result<_, ContractError> {
+            Ok(stake.unwrap_or_default().checked_add(amount)?)
+        },
+    )?;
+    let total_staked = TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default();
+    TOTAL_STAKED.save(deps.storage, &total_staked.checked_add(amount)?)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "stake")
+        .add_attribute("staker", info.sender)
+        .add_attribute("amount", amount.to_string()))
+}
+
+fn execute_unstake(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount);
+    }
+    let stake = STAKES
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    if amount > stake {
+        return Err(ContractError::InsufficientStake);
+    }
+
+    update_rewards(&mut deps, &env, &info.sender)?;
+    STAKES.save(deps.storage, &info.sender, &(stake - amount))?;
+    TOTAL_STAKED.update(deps.storage, |total| -> Result<_, ContractError> {
+        Ok(total.checked_sub(amount)?)
+    })?;
+
+    let unlock_time = env
+        .block
+        .time
+        .plus_seconds(CONFIG.load(deps.storage)?.unstake_cooldown);
+    let mut unstakings = UNSTAKINGS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    unstakings.push(Unstaking {
+        amount,
+        unlock_time,
+    });
+    UNSTAKINGS.save(deps.storage, &info.sender, &unstakings)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "unstake")
+        .add_attribute("staker", info.sender)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("unlock_time", unlock_time.seconds().to_string()))
+}
+
+fn execute_withdraw_unstaked(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let (unlocked, locked): (Vec<Unstaking>, Vec<Unstaking>) = UNSTAKINGS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|unstaking| unstaking.unlock_time <= env.block.time);
+
+    let amount: Uint128 =
+        unlocked.iter().map(|unstaking| unstaking.amount).sum();
+    if amount.is_zero() {
+        return Err(ContractError::NothingToWithdraw);
+    }
+    if locked.is_empty() {
+        UNSTAKINGS.remove(deps.storage, &info.sender);
+    } else {
+        UNSTAKINGS.save(deps.storage, &info.sender, &locked)?;
+    }
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![Coin::new(
+                amount,
+                CONFIG.load(deps.storage)?.gov_denom,
+            )],
+        })
+        .add_attribute("action", "withdraw_unstaked")
+        .add_attribute("staker", info.sender)
+        .add_attribute("amount", amount.to_string()))
+}
+
+fn execute_claim_rewards(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    update_rewards(&mut deps, &env, &info.sender)?;
+
+    // includes the denoms removed from the config since they accrued
+    let accrued: Vec<(String, StakerReward)> = STAKER_REWARDS
+        .prefix(&info.sender)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    let mut rewards = vec![];
+    for (denom, mut reward) in accrued {
+        if reward.pending.is_zero() {
+            continue;
+        }
+        let key = (&info.sender, denom.as_str());
+
+        DISTRIBUTED_BALANCES.update(
+            deps.storage,
+            &denom,
+            |balance| -> Result<_, ContractError> {
+                Ok(balance.unwrap_or_default().checked_sub(reward.pending)?)
+            },
+        )?;
+        let amount = reward.pending;
+        reward.pending = Uint128::zero();
+        STAKER_REWARDS.save(deps.storage, key, &reward)?;
+        rewards.push(Coin::new(amount, denom));
+    }
+    if rewards.is_empty() {
+        return Err(ContractError::NothingToWithdraw);
+    }
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: rewards.clone(),
+        })
+        .add_attribute("action", "claim_rewards")
+        .add_attribute("staker", info.sender)
+        .add_attribute(
+            "rewards",
+            rewards
+                .iter()
+                .map(Coin::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ))
+}
+
+fn execute_update_config(
+    mut deps: DepsMut,
+    env: Env,
+    reward_denoms: Option<Vec<String>>,
+    unstake_cooldown: Option<u64>,
+) -> Result<Response, ContractError> {
+    // distribute with the current denoms before they change
+    distribute_rewards(&mut deps, &env)?;
+
+    let mut config = CONFIG.load(deps.storage)?;
+    if let Some(reward_denoms) = reward_denoms {
+        config.reward_denoms = reward_denoms;
+    }
+    if let Some(unstake_cooldown) = unstake_cooldown {
+        config.unstake_cooldown = unstake_cooldown;
+    }
+    validate_config(&config)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_config")
+        .add_attribute("reward_denoms", config.reward_denoms.join(","))
+        .add_attribute("unstake_cooldown", config.unstake_cooldown.to_string()))
+}
+
+fn validate_config(config: &Config) -> Result<(), ContractError> {
+    if config.reward_denoms.contains(&config.gov_denom) {
+        return Err(ContractError::InvalidRewardDenom);
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use cosmwasm_std::{OverflowError, StdError};
+use nibiru_ownable::OwnershipError;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    Ownership(#[from] OwnershipError),
+
+    #[error("only {0} can be staked")]
+    InvalidFunds(String),
+
+    #[error("amount must be positive")]
+    ZeroAmount,
+
+    #[error("insufficient stake")]
+    InsufficientStake,
+
+    #[error("the gov token can't be a reward denom")]
+    InvalidRewardDenom,
+
+    #[error("nothing to withdraw")]
+    NothingToWithdraw,
+}
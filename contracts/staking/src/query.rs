@@ -0,0 +1,87 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    to_json_binary, Binary, Coin, Decimal, Deps, Env, StdResult, Uint128,
+};
+use nibiru_ownable::ownable_query;
+
+use crate::state::{
+    reward_indexes, staker_rewards, Config, Unstaking, CONFIG, STAKES,
+    TOTAL_STAKED, UNSTAKINGS,
+};
+
+#[ownable_query]
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum StakingQueryMsg {
+    #[returns(Config)]
+    Config {},
+
+    // Returns the gov tokens staked by `address`, without the ones in
+    // cooldown.
+    #[returns(Uint128)]
+    Stake { address: String },
+
+    #[returns(Uint128)]
+    TotalStaked {},
+
+    // Returns the rewards `address` can claim, per reward denom.
+    #[returns(Vec<Coin>)]
+    PendingRewards { address: String },
+
+    // Returns the accrued rewards per staked token, per reward denom.
+    #[returns(Vec<(String, Decimal)>)]
+    RewardsPerShare {},
+
+    // Returns the unstaked tokens of `address` and when they unlock.
+    #[returns(Vec<Unstaking>)]
+    Unstakings { address: String },
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: StakingQueryMsg) -> StdResult<Binary> {
+    match msg {
+        StakingQueryMsg::Config {} => {
+            to_json_binary(&CONFIG.load(deps.storage)?)
+        }
+        StakingQueryMsg::Stake { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_json_binary(
+                &STAKES.may_load(deps.storage, &address)?.unwrap_or_default(),
+            )
+        }
+        StakingQueryMsg::TotalStaked {} => to_json_binary(
+            &TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default(),
+        ),
+        StakingQueryMsg::PendingRewards { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            let indexes = reward_indexes(deps, &env)?;
+            let rewards: Vec<Coin> =
+                staker_rewards(deps.storage, &address, &indexes)?
+                    .into_iter()
+                    .map(|(denom, reward)| Coin::new(reward.pending, denom))
+                    .collect();
+            to_json_binary(&rewards)
+        }
+        StakingQueryMsg::RewardsPerShare {} => {
+            let rewards_per_share: Vec<(String, Decimal)> =
+                reward_indexes(deps, &env)?
+                    .into_iter()
+                    .map(|index| (index.denom, index.acc_reward_per_share))
+                    .collect();
+            to_json_binary(&rewards_per_share)
+        }
+        StakingQueryMsg::Unstakings { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_json_binary(
+                &UNSTAKINGS
+                    .may_load(deps.storage, &address)?
+                    .unwrap_or_default(),
+            )
+        }
+        StakingQueryMsg::Ownership {} => {
+            to_json_binary(&nibiru_ownable::get_ownership(deps.storage)?)
+        }
+    }
+}
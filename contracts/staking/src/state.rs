@@ -0,0 +1,128 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{
+    Addr, Decimal, Deps, Env, Order, StdResult, Storage, Timestamp, Uint128,
+};
+use cw_storage_plus::{Item, Map};
+
+pub const DEFAULT_UNSTAKE_COOLDOWN: u64 = 7 * 86_400;
+
+#[cw_serde]
+pub struct Config {
+    /// Denom of the gov token that is staked.
+    pub gov_denom: String,
+    /// Collateral denoms distributed to the stakers.
+    pub reward_denoms: Vec<String>,
+    /// Seconds between an unstake and the withdrawal of the tokens.
+    pub unstake_cooldown: u64,
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+pub const TOTAL_STAKED: Item<Uint128> = Item::new("total_staked");
+
+pub const STAKES: Map<&Addr, Uint128> = Map::new("stakes");
+
+/// Rewards accrued per staked token since the start, per reward denom.
+pub const ACC_REWARD_PER_SHARE: Map<&str, Decimal> =
+    Map::new("acc_reward_per_share");
+
+/// Part of the balance of each reward denom already distributed, anything
+/// above it was received since the last distribution.
+pub const DISTRIBUTED_BALANCES: Map<&str, Uint128> =
+    Map::new("distributed_balances");
+
+#[cw_serde]
+#[derive(Default)]
+pub struct StakerReward {
+    pub acc_reward_per_share_paid: Decimal,
+    pub pending: Uint128,
+}
+
+pub const STAKER_REWARDS: Map<(&Addr, &str), StakerReward> =
+    Map::new("staker_rewards");
+
+#[cw_serde]
+pub struct Unstaking {
+    pub amount: Uint128,
+    pub unlock_time: Timestamp,
+}
+
+pub const UNSTAKINGS: Map<&Addr, Vec<Unstaking>> = Map::new("unstakings");
+
+pub struct RewardIndex {
+    pub denom: String,
+    pub acc_reward_per_share: Decimal,
+    /// Balance distributed with the index, unset when nothing is staked.
+    pub distributed_balance: Option<Uint128>,
+}
+
+/// Reward indexes including the rewards received since the last
+/// distribution. Denoms removed from the config keep their last index so
+/// that what they accrued stays claimable, but receive nothing new.
+pub fn reward_indexes(deps: Deps, env: &Env) -> StdResult<Vec<RewardIndex>> {
+    let config = CONFIG.load(deps.storage)?;
+    let total_staked = TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default();
+
+    let mut indexes = vec![];
+    for item in
+        ACC_REWARD_PER_SHARE.range(deps.storage, None, None, Order::Ascending)
+    {
+        let (denom, acc_reward_per_share) = item?;
+        if !config.reward_denoms.contains(&denom) {
+            indexes.push(RewardIndex {
+                denom,
+                acc_reward_per_share,
+                distributed_balance: None,
+            });
+        }
+    }
+    for denom in config.reward_denoms {
+        let mut index = RewardIndex {
+            acc_reward_per_share: ACC_REWARD_PER_SHARE
+                .may_load(deps.storage, &denom)?
+                .unwrap_or_default(),
+            distributed_balance: None,
+            denom,
+        };
+        // rewards received while nothing is staked go to the next stakers
+        if !total_staked.is_zero() {
+            let balance = deps
+                .querier
+                .query_balance(&env.contract.address, &index.denom)?
+                .amount;
+            let received = balance.checked_sub(
+                DISTRIBUTED_BALANCES
+                    .may_load(deps.storage, &index.denom)?
+                    .unwrap_or_default(),
+            )?;
+            index.acc_reward_per_share +=
+                Decimal::from_ratio(received, total_staked);
+            index.distributed_balance = Some(balance);
+        }
+        indexes.push(index);
+    }
+    Ok(indexes)
+}
+
+/// Rewards of `staker` for each denom, including what accrued since the
+/// last settlement.
+pub fn staker_rewards(
+    storage: &dyn Storage,
+    staker: &Addr,
+    indexes: &[RewardIndex],
+) -> StdResult<Vec<(String, StakerReward)>> {
+    let stake = STAKES.may_load(storage, staker)?.unwrap_or_default();
+
+    let mut rewards = vec![];
+    for index in indexes {
+        let mut reward = STAKER_REWARDS
+            .may_load(storage, (staker, &index.denom))?
+            .unwrap_or_default();
+        reward.pending += stake.mul_floor(
+            index.acc_reward_per_share - reward.acc_reward_per_share_paid,
+        );
+        reward.acc_reward_per_share_paid = index.acc_reward_per_share;
+        rewards.push((index.denom.clone(), reward));
+    }
+    Ok(rewards)
+}
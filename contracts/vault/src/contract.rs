@@ -0,0 +1,447 @@
+use cosmwasm_schema::cw_serde;
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    BankMsg, Coin, Decimal, DepsMut, Env, MessageInfo, Order, Response,
+    StdResult, Uint128,
+};
+use cw2::set_contract_version;
+use cw_storage_plus::Bound;
+use nibiru_ownable::ownable_execute;
+
+use crate::{
+    error::ContractError,
+    state::{
+        collateralization_p, total_assets, withdraw_epochs_timelock, Config,
+        CONFIG, DEFAULT_EPOCH_DURATION, DEFAULT_MIN_COLLATERALIZATION_P,
+        PENDING_WITHDRAW_SHARES, PERP_ADDRESS, SHARES, TOTAL_ASSETS,
+        TOTAL_SHARES, WITHDRAW_REQUESTS,
+    },
+};
+
+pub const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[cw_serde]
+pub struct VaultInstantiateMsg {
+    pub owner: Option<String>,
+    pub perp_address: Option<String>,
+    pub epoch_duration: Option<u64>,
+    pub min_collateralization_p: Option<Decimal>,
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    msg: VaultInstantiateMsg,
+) -> Result<Response, ContractError> {
+    set_contract_version(
+        deps.storage,
+        format!("crates.io:{CONTRACT_NAME}"),
+        CONTRACT_VERSION,
+    )?;
+
+    nibiru_ownable::initialize_owner(deps.storage, msg.owner.as_deref())?;
+    if let Some(perp_address) = msg.perp_address {
+        PERP_ADDRESS
+            .save(deps.storage, &deps.api.addr_validate(&perp_address)?)?;
+    }
+    let config = Config {
+        epoch_duration: msg.epoch_duration.unwrap_or(DEFAULT_EPOCH_DURATION),
+        start_epoch: 0,
+        epoch_start: env.block.time,
+        min_collateralization_p: msg
+            .min_collateralization_p
+            .unwrap_or(DEFAULT_MIN_COLLATERALIZATION_P),
+    };
+    validate_config(&config)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+#[ownable_execute]
+#[cw_serde]
+pub enum VaultExecuteMsg {
+    /// Deposits the sent coin and mints shares of the vault for its denom.
+    Deposit {},
+    /// Locks `shares` of `denom` until an epoch that depends on the vault
+    /// collateralization, after which they can be withdrawn.
+    RequestWithdraw {
+        denom: String,
+        shares: Uint128,
+    },
+    CancelWithdrawRequest {
+        denom: String,
+        unlock_epoch: u64,
+    },
+    /// Burns `shares` of `denom` out of the unlocked withdraw requests and
+    /// sends back their value.
+    Withdraw {
+        denom: String,
+        shares: Uint128,
+    },
+    /// Adds the sent coin to the assets backing the shares of its denom, only
+    /// callable by the perp contract to pay fees and trader losses.
+    ReceiveAssets {},
+    /// Pays `amount` of `denom` to `receiver`, only callable by the perp
+    /// contract to pay out trader profits.
+    SendAssets {
+        denom: String,
+        amount: Uint128,
+        receiver: String,
+    },
+    UpdatePerpAddress {
+        perp_address: String,
+    },
+    UpdateConfig {
+        epoch_duration: Option<u64>,
+        min_collateralization_p: Option<Decimal>,
+    },
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: VaultExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        VaultExecuteMsg::Deposit {} => execute_deposit(deps, info),
+        VaultExecuteMsg::ReceiveAssets {} => execute_receive_assets(deps, info),
+        VaultExecuteMsg::RequestWithdraw { denom, shares } => {
+            execute_request_withdraw(deps, env, info, denom, shares)
+        }
+        VaultExecuteMsg::CancelWithdrawRequest {
+            denom,
+            unlock_epoch,
+        } => execute_cancel_withdraw_request(deps, info, denom, unlock_epoch),
+        VaultExecuteMsg::Withdraw { denom, shares } => {
+            execute_withdraw(deps, env, info, denom, shares)
+        }
+        VaultExecuteMsg::UpdateConfig {
+            epoch_duration,
+            min_collateralization_p,
+        } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            execute_update_config(
+                deps,
+                env,
+                epoch_duration,
+                min_collateralization_p,
+            )
+        }
+        VaultExecuteMsg::SendAssets {
+            denom,
+            amount,
+            receiver,
+        } => execute_send_assets(deps, info, denom, amount, receiver),
+        VaultExecuteMsg::UpdatePerpAddress { perp_address } => {
+            nibiru_ownable::assert_owner(deps.storage, info.sender.as_str())?;
+            PERP_ADDRESS
+                .save(deps.storage, &deps.api.addr_validate(&perp_address)?)?;
+            Ok(Response::new()
+                .add_attribute("action", "update_perp_address")
+                .add_attribute("perp_address", perp_address))
+        }
+        VaultExecuteMsg::UpdateOwnership(action) => {
+            let ownership = nibiru_ownable::update_ownership(
+                deps,
+                &env.block,
+                info.sender.as_str(),
+                action,
+            )?;
+            Ok(Response::new().add_attributes(ownership.into_attributes()))
+        }
+    }
+}
+
+fn execute_deposit(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let coin = sent_coin(&info)?;
+
+    let assets_before = total_assets(deps.as_ref(), &coin.denom)?;
+    let total_shares = TOTAL_SHARES
+        .may_load(deps.storage, &coin.denom)?
+        .unwrap_or_default();
+
+    let shares = if total_shares.is_zero() {
+        coin.amount
+    } else if assets_before.is_zero() {
+        return Err(ContractError::NoAssets);
+    } else {
+        coin.amount.multiply_ratio(total_shares, assets_before)
+    };
+    if shares.is_zero() {
+        return Err(ContractError::ZeroShares);
+    }
+
+    TOTAL_ASSETS.save(
+        deps.storage,
+        &coin.denom,
+        &assets_before.checked_add(coin.amount)?,
+    )?;
+    TOTAL_SHARES.save(
+        deps.storage,
+        &coin.denom,
+        &total_shares.checked_add(shares)?,
+    )?;
+    SHARES.update(
+        deps.storage,
+        (&coin.denom, &info.sender),
+        |balance| -> Result<_, ContractError> {
+            Ok(balance.unwrap_or_default().checked_add(shares)?)
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "deposit")
+        .add_attribute("denom", coin.denom.clone())
+        .add_attribute("amount", coin.amount.to_string())
+        .add_attribute("shares", shares.to_string()))
+}
+
+fn execute_receive_assets(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    if PERP_ADDRESS.may_load(deps.storage)? != Some(info.sender.clone()) {
+        return Err(ContractError::Unauthorized);
+    }
+    let coin = sent_coin(&info)?;
+    TOTAL_ASSETS.update(
+        deps.storage,
+        &coin.denom,
+        |assets| -> Result<_, ContractError> {
+            Ok(assets.unwrap_or_default().checked_add(coin.amount)?)
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "receive_assets")
+        .add_attribute("denom", coin.denom.clone())
+        .add_attribute("amount", coin.amount.to_string()))
+}
+
+/// The single non-zero coin sent with the message.
+fn sent_coin(info: &MessageInfo) -> Result<&Coin, ContractError> {
+    match info.funds.as_slice() {
+        [coin] if !coin.amount.is_zero() => Ok(coin),
+        _ => Err(ContractError::InvalidFunds),
+    }
+}
+
+fn execute_request_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    shares: Uint128,
+) -> Result<Response, ContractError> {
+    if shares.is_zero() {
+        return Err(ContractError::ZeroShares);
+    }
+    let balance = SHARES
+        .may_load(deps.storage, (&denom, &info.sender))?
+        .unwrap_or_default();
+    let pending = PENDING_WITHDRAW_SHARES
+        .may_load(deps.storage, (&denom, &info.sender))?
+        .unwrap_or_default();
+    if pending.checked_add(shares)? > balance {
+        return Err(ContractError::InsufficientShares);
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    let unlock_epoch = config.current_epoch(&env.block)
+        + withdraw_epochs_timelock(collateralization_p(deps.as_ref(), &denom)?);
+
+    WITHDRAW_REQUESTS.update(
+        deps.storage,
+        (&denom, &info.sender, unlock_epoch),
+        |requested| -> Result<_, ContractError> {
+            Ok(requested.unwrap_or_default().checked_add(shares)?)
+        },
+    )?;
+    PENDING_WITHDRAW_SHARES.save(
+        deps.storage,
+        (&denom, &info.sender),
+        &(pending + shares),
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "request_withdraw")
+        .add_attribute("denom", denom)
+        .add_attribute("shares", shares.to_string())
+        .add_attribute("unlock_epoch", unlock_epoch.to_string()))
+}
+
+fn execute_cancel_withdraw_request(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+    unlock_epoch: u64,
+) -> Result<Response, ContractError> {
+    let key = (denom.as_str(), &info.sender, unlock_epoch);
+    let shares = WITHDRAW_REQUESTS
+        .may_load(deps.storage, key)?
+        .ok_or(ContractError::WithdrawRequestNotFound)?;
+    WITHDRAW_REQUESTS.remove(deps.storage, key);
+    PENDING_WITHDRAW_SHARES.update(
+        deps.storage,
+        (&denom, &info.sender),
+        |pending| -> Result<_, ContractError> {
+            Ok(pending.unwrap_or_default().checked_sub(shares)?)
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_withdraw_request")
+        .add_attribute("denom", denom)
+        .add_attribute("shares", shares.to_string())
+        .add_attribute("unlock_epoch", unlock_epoch.to_string()))
+}
+
+fn execute_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    shares: Uint128,
+) -> Result<Response, ContractError> {
+    if shares.is_zero() {
+        return Err(ContractError::ZeroShares);
+    }
+
+    // shares are taken from the oldest unlocked requests first
+    let current_epoch = CONFIG.load(deps.storage)?.current_epoch(&env.block);
+    let unlocked: Vec<(u64, Uint128)> = WITHDRAW_REQUESTS
+        .prefix((&denom, &info.sender))
+        .range(
+            deps.storage,
+            None,
+            Some(Bound::inclusive(current_epoch)),
+            Order::Ascending,
+        )
+        .collect::<StdResult<_>>()?;
+    let mut remaining = shares;
+    for (unlock_epoch, requested) in unlocked {
+        if remaining.is_zero() {
+            break;
+        }
+        let taken = remaining.min(requested);
+        remaining -= taken;
+        if taken == requested {
+            WITHDRAW_REQUESTS
+                .remove(deps.storage, (&denom, &info.sender, unlock_epoch));
+        } else {
+            WITHDRAW_REQUESTS.save(
+                deps.storage,
+                (&denom, &info.sender, unlock_epoch),
+                &(requested - taken),
+            )?;
+        }
+    }
+    if !remaining.is_zero() {
+        return Err(ContractError::InsufficientUnlockedShares);
+    }
+
+    let balance = SHARES.load(deps.storage, (&denom, &info.sender))?;
+    let pending =
+        PENDING_WITHDRAW_SHARES.load(deps.storage, (&denom, &info.sender))?;
+    let total_shares = TOTAL_SHARES.load(deps.storage, &denom)?;
+    let assets = total_assets(deps.as_ref(), &denom)?;
+    let amount = assets.multiply_ratio(shares, total_shares);
+
+    TOTAL_ASSETS.save(deps.storage, &denom, &(assets - amount))?;
+    TOTAL_SHARES.save(deps.storage, &denom, &(total_shares - shares))?;
+    SHARES.save(deps.storage, (&denom, &info.sender), &(balance - shares))?;
+    PENDING_WITHDRAW_SHARES.save(
+        deps.storage,
+        (&denom, &info.sender),
+        &(pending - shares),
+    )?;
+
+    let mut response = Response::new()
+        .add_attribute("action", "withdraw")
+        .add_attribute("denom", denom.clone())
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("shares", shares.to_string());
+    if !amount.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![Coin::new(amount, denom)],
+        });
+    }
+    Ok(response)
+}
+
+fn execute_send_assets(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+    amount: Uint128,
+    receiver: String,
+) -> Result<Response, ContractError> {
+    if PERP_ADDRESS.may_load(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized);
+    }
+    let assets = total_assets(deps.as_ref(), &denom)?;
+    if amount > assets {
+        return Err(ContractError::InsufficientAssets);
+    }
+    TOTAL_ASSETS.save(deps.storage, &denom, &(assets - amount))?;
+
+    let receiver = deps.api.addr_validate(&receiver)?;
+    let mut response = Response::new()
+        .add_attribute("action", "send_assets")
+        .add_attribute("denom", denom.clone())
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("receiver", receiver.to_string());
+    if !amount.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: receiver.to_string(),
+            amount: vec![Coin::new(amount, denom)],
+        });
+    }
+    Ok(response)
+}
+
+fn execute_update_config(
+    deps: DepsMut,
+    env: Env,
+    epoch_duration: Option<u64>,
+    min_collateralization_p: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if let Some(epoch_duration) = epoch_duration {
+        // the epochs already started keep their numbers
+        config.start_epoch = config.current_epoch(&env.block);
+        config.epoch_start = env.block.time;
+        config.epoch_duration = epoch_duration;
+    }
+    if let Some(min_collateralization_p) = min_collateralization_p {
+        config.min_collateralization_p = min_collateralization_p;
+    }
+    validate_config(&config)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_config")
+        .add_attribute("epoch_duration", config.epoch_duration.to_string())
+        .add_attribute(
+            "min_collateralization_p",
+            config.min_collateralization_p.to_string(),
+        ))
+}
+
+fn validate_config(config: &Config) -> Result<(), ContractError> {
+    if config.epoch_duration == 0 {
+        return Err(ContractError::InvalidEpochDuration);
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use cosmwasm_std::{OverflowError, StdError};
+use nibiru_ownable::OwnershipError;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    Ownership(#[from] OwnershipError),
+
+    #[error("unauthorized")]
+    Unauthorized,
+
+    #[error("exactly one coin must be sent")]
+    InvalidFunds,
+
+    #[error("no shares to mint or burn")]
+    ZeroShares,
+
+    #[error("insufficient shares")]
+    InsufficientShares,
+
+    #[error("insufficient unlocked shares, request a withdrawal first")]
+    InsufficientUnlockedShares,
+
+    #[error("withdraw request not found")]
+    WithdrawRequestNotFound,
+
+    #[error("epoch duration must be positive")]
+    InvalidEpochDuration,
+
+    #[error("insufficient assets in the vault")]
+    InsufficientAssets,
+
+    #[error("the vault has no assets left for its shares")]
+    NoAssets,
+}
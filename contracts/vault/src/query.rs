@@ -0,0 +1,145 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    to_json_binary, Addr, Binary, Decimal, Deps, Env, Int128, Order, StdResult,
+    Uint128,
+};
+use nibiru_ownable::ownable_query;
+
+use crate::state::{
+    collateralization_p, open_pnl, total_assets, withdraw_epochs_timelock,
+    Config, CONFIG, PERP_ADDRESS, SHARES, TOTAL_SHARES, WITHDRAW_REQUESTS,
+};
+
+#[ownable_query]
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum VaultQueryMsg {
+    // Returns the perp contract allowed to request payouts and the epochs
+    // configuration.
+    #[returns(ConfigResponse)]
+    Config {},
+
+    // Returns the value of one share of `denom`, in `denom`.
+    #[returns(Decimal)]
+    SharePrice { denom: String },
+
+    // Returns the total value locked in the vault for `denom`.
+    #[returns(Uint128)]
+    Tvl { denom: String },
+
+    // Returns the shares of `denom` held by `address`.
+    #[returns(Uint128)]
+    Shares { address: String, denom: String },
+
+    // Returns the total shares issued for `denom`.
+    #[returns(Uint128)]
+    TotalShares { denom: String },
+
+    // Returns the collateralization of the vault for `denom` and the epochs a
+    // withdraw request made now waits for.
+    #[returns(CollateralizationResponse)]
+    Collateralization { denom: String },
+
+    // Returns the pending withdraw requests of `address` for `denom`.
+    #[returns(Vec<WithdrawRequest>)]
+    WithdrawRequests { address: String, denom: String },
+}
+
+#[cw_serde]
+pub struct ConfigResponse {
+    pub perp_address: Option<Addr>,
+    pub config: Config,
+    pub current_epoch: u64,
+}
+
+#[cw_serde]
+pub struct CollateralizationResponse {
+    pub open_pnl: Int128,
+    pub collateralization_p: Decimal,
+    pub undercollateralized: bool,
+    pub withdraw_epochs_timelock: u64,
+}
+
+#[cw_serde]
+pub struct WithdrawRequest {
+    pub unlock_epoch: u64,
+    pub shares: Uint128,
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: VaultQueryMsg) -> StdResult<Binary> {
+    match msg {
+        VaultQueryMsg::Config {} => {
+            let config = CONFIG.load(deps.storage)?;
+            to_json_binary(&ConfigResponse {
+                perp_address: PERP_ADDRESS.may_load(deps.storage)?,
+                current_epoch: config.current_epoch(&env.block),
+                config,
+            })
+        }
+        VaultQueryMsg::SharePrice { denom } => {
+            to_json_binary(&query_share_price(deps, denom)?)
+        }
+        VaultQueryMsg::Tvl { denom } => {
+            to_json_binary(&total_assets(deps, &denom)?)
+        }
+        VaultQueryMsg::Shares { address, denom } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_json_binary(
+                &SHARES
+                    .may_load(deps.storage, (&denom, &address))?
+                    .unwrap_or_default(),
+            )
+        }
+        VaultQueryMsg::TotalShares { denom } => to_json_binary(
+            &TOTAL_SHARES
+                .may_load(deps.storage, &denom)?
+                .unwrap_or_default(),
+        ),
+        VaultQueryMsg::Collateralization { denom } => {
+            let collateralization_p = collateralization_p(deps, &denom)?;
+            to_json_binary(&CollateralizationResponse {
+                open_pnl: open_pnl(deps, &denom)?,
+                collateralization_p,
+                undercollateralized: collateralization_p
+                    < CONFIG.load(deps.storage)?.min_collateralization_p,
+                withdraw_epochs_timelock: withdraw_epochs_timelock(
+                    collateralization_p,
+                ),
+            })
+        }
+        VaultQueryMsg::WithdrawRequests { address, denom } => {
+            let address = deps.api.addr_validate(&address)?;
+            let requests = WITHDRAW_REQUESTS
+                .prefix((&denom, &address))
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|(unlock_epoch, shares)| WithdrawRequest {
+                        unlock_epoch,
+                        shares,
+                    })
+                })
+                .collect::<StdResult<Vec<_>>>()?;
+            to_json_binary(&requests)
+        }
+        VaultQueryMsg::Ownership {} => {
+            to_json_binary(&nibiru_ownable::get_ownership(deps.storage)?)
+        }
+    }
+}
+
+/// Shares are minted one for one until the first deposit.
+fn query_share_price(deps: Deps, denom: String) -> StdResult<Decimal> {
+    let total_shares = TOTAL_SHARES
+        .may_load(deps.storage, &denom)?
+        .unwrap_or_default();
+    if total_shares.is_zero() {
+        return Ok(Decimal::one());
+    }
+    Ok(Decimal::from_ratio(
+        total_assets(deps, &denom)?,
+        total_shares,
+    ))
+}
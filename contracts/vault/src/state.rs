@@ -0,0 +1,110 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{
+    Addr, BlockInfo, Decimal, Deps, Int128, StdResult, Timestamp, Uint128,
+};
+use cw_storage_plus::{Item, Map};
+
+pub const DEFAULT_EPOCH_DURATION: u64 = 86_400;
+pub const DEFAULT_MIN_COLLATERALIZATION_P: Decimal = Decimal::percent(80);
+
+/// Epochs a withdraw request waits for, by collateralization: the lower the
+/// collateralization, the longer the wait.
+pub const WITHDRAW_EPOCHS_TIMELOCKS: [(Decimal, u64); 2] =
+    [(Decimal::percent(100), 3), (Decimal::percent(120), 2)];
+pub const MIN_WITHDRAW_EPOCHS_TIMELOCK: u64 = 1;
+
+#[cw_serde]
+pub struct Config {
+    /// Length of an epoch, in seconds.
+    pub epoch_duration: u64,
+    /// Epoch that started at `epoch_start`, kept when the duration changes.
+    pub start_epoch: u64,
+    pub epoch_start: Timestamp,
+    /// Collateralization below which the vault is undercollateralized and
+    /// new trades are refused.
+    pub min_collateralization_p: Decimal,
+}
+
+impl Config {
+    pub fn current_epoch(&self, block: &BlockInfo) -> u64 {
+        let elapsed = block.time.seconds() - self.epoch_start.seconds();
+        self.start_epoch + elapsed / self.epoch_duration
+    }
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+/// Perp contract allowed to request payouts from the vault.
+pub const PERP_ADDRESS: Item<Addr> = Item::new("perp_address");
+
+/// Total shares issued per collateral denom.
+pub const TOTAL_SHARES: Map<&str, Uint128> = Map::new("total_shares");
+
+/// Assets backing the shares per collateral denom: the deposits, fees and
+/// trader losses sent by the perp contract, net of withdrawals and payouts.
+/// Coins sent to the vault by other means are not counted.
+pub const TOTAL_ASSETS: Map<&str, Uint128> = Map::new("total_assets");
+
+/// Shares held per collateral denom and liquidity provider.
+pub const SHARES: Map<(&str, &Addr), Uint128> = Map::new("shares");
+
+/// Shares requested for withdrawal per collateral denom, liquidity provider
+/// and unlock epoch.
+pub const WITHDRAW_REQUESTS: Map<(&str, &Addr, u64), Uint128> =
+    Map::new("withdraw_requests");
+
+/// Shares locked in withdraw requests per collateral denom and liquidity
+/// provider.
+pub const PENDING_WITHDRAW_SHARES: Map<(&str, &Addr), Uint128> =
+    Map::new("pending_withdraw_shares");
+
+pub fn total_assets(deps: Deps, denom: &str) -> StdResult<Uint128> {
+    Ok(TOTAL_ASSETS
+        .may_load(deps.storage, denom)?
+        .unwrap_or_default())
+}
+
+/// Query of the perp contract the vault makes, declared here as perp depends
+/// on the vault.
+#[cw_serde]
+enum PerpQueryMsg {
+    OpenPnl { denom: String },
+}
+
+/// Aggregate unrealized PnL of the open trades of `denom` at the current
+/// prices, positive when traders are in profit, as computed by the perp
+/// contract. Zero until the perp contract is set.
+pub fn open_pnl(deps: Deps, denom: &str) -> StdResult<Int128> {
+    match PERP_ADDRESS.may_load(deps.storage)? {
+        Some(perp_address) => deps.querier.query_wasm_smart(
+            perp_address,
+            &PerpQueryMsg::OpenPnl {
+                denom: denom.to_string(),
+            },
+        ),
+        None => Ok(Int128::zero()),
+    }
+}
+
+/// Share of the assets left once the open trades are settled at their
+/// unrealized PnL, above 100% when traders are at a loss.
+pub fn collateralization_p(deps: Deps, denom: &str) -> StdResult<Decimal> {
+    let assets = total_assets(deps, denom)?;
+    let open_pnl = open_pnl(deps, denom)?;
+
+    let equity = Int128::try_from(assets)?.checked_sub(open_pnl)?;
+    if equity <= Int128::zero() {
+        return Ok(Decimal::zero());
+    }
+    if assets.is_zero() {
+        return Ok(Decimal::one());
+    }
+    Ok(Decimal::from_ratio(equity.unsigned_abs(), assets))
+}
+
+pub fn withdraw_epochs_timelock(collateralization_p: Decimal) -> u64 {
+    WITHDRAW_EPOCHS_TIMELOCKS
+        .iter()
+        .find(|(threshold, _)| collateralization_p < *threshold)
+        .map_or(MIN_WITHDRAW_EPOCHS_TIMELOCK, |(_, epochs)| *epochs)
+}
@@ -1,15 +1,19 @@
+use anyhow::bail;
 use cosmwasm_std::{
     from_json,
     testing::{MockApi, MockStorage},
-    to_json_binary, Addr, Coin, Empty, WasmMsg,
+    to_json_binary, Addr, Api, Binary, BlockInfo, Coin, Decimal, Empty, Querier,
+    Storage, WasmMsg,
 };
 use cw_multi_test::WasmKeeper;
 use cw_multi_test::{
     error::AnyResult, no_init, App, BankKeeper, BankSudo, BasicAppBuilder,
     DistributionKeeper, Executor, FailingModule, GovFailingModule,
-    IbcFailingModule, StakeKeeper,
+    IbcFailingModule, StakeKeeper, Stargate,
 };
 use cw_utils::parse_instantiate_response_data;
+use nibiru_std::proto::nibiru::oracle::QueryExchangeRateRequest;
+use prost::Message;
 use serde::{de::DeserializeOwned, Serialize};
 
 pub type Simapp<ExecC = Empty, QueryC = Empty> = App<
@@ -22,8 +26,68 @@ pub type Simapp<ExecC = Empty, QueryC = Empty> = App<
     DistributionKeeper,
     IbcFailingModule,
     GovFailingModule,
+    NibiruOracleStargate,
 >;
 
+pub const EXCHANGE_RATE_PATH: &str = "/nibiru.oracle.v1.Query/ExchangeRate";
+const EXCHANGE_RATES_PREFIX: &[u8] = b"nibiru_oracle_exchange_rates/";
+
+/// Stand-in for the `x/oracle` module of Nibiru, serving the exchange rates
+/// set with [`set_exchange_rate`] to stargate queries.
+pub struct NibiruOracleStargate;
+
+/// `QueryExchangeRateResponse` of the versions of `x/oracle` that return the
+/// block the rate was voted at.
+#[derive(Clone, PartialEq, Message)]
+struct ExchangeRateResponse {
+    #[prost(string, tag = "1")]
+    exchange_rate: String,
+    #[prost(int64, tag = "2")]
+    block_timestamp_ms: i64,
+    #[prost(uint64, tag = "3")]
+    block_height: u64,
+}
+
+impl Stargate for NibiruOracleStargate {
+    fn query_stargate(
+        &self,
+        _api: &dyn Api,
+        storage: &dyn Storage,
+        _querier: &dyn Querier,
+        _block: &BlockInfo,
+        path: String,
+        data: Binary,
+    ) -> AnyResult<Binary> {
+        if path != EXCHANGE_RATE_PATH {
+            bail!("Unexpected stargate query: path={}", path);
+        }
+        let request = QueryExchangeRateRequest::decode(data.as_slice())?;
+        let Some(response) = storage.get(&exchange_rate_key(&request.pair))
+        else {
+            bail!("no exchange rate for pair {}", request.pair);
+        };
+        Ok(response.into())
+    }
+}
+
+fn exchange_rate_key(pair: &str) -> Vec<u8> {
+    [EXCHANGE_RATES_PREFIX, pair.as_bytes()].concat()
+}
+
+/// Sets the exchange rate voted for the pair at the current block, serialized
+/// like the module does with the 18 decimals atomic value.
+pub fn set_exchange_rate(app: &mut Simapp, pair: &str, rate: Decimal) {
+    let block = app.block_info();
+    let response = ExchangeRateResponse {
+        exchange_rate: rate.atomics().to_string(),
+        block_timestamp_ms: (block.time.nanos() / 1_000_000) as i64,
+        block_height: block.height,
+    };
+    app.init_modules(|_, _, storage| {
+        storage.set(&exchange_rate_key(pair), &response.encode_to_vec())
+    });
+}
+
 pub trait SimappExtension {
     fn instantiate_contract_with_data<
         T: Serialize,
@@ -76,7 +140,9 @@ impl SimappExtension for Simapp {
 }
 
 pub fn new() -> Simapp {
-    BasicAppBuilder::new_custom().build(no_init)
+    BasicAppBuilder::new_custom()
+        .with_stargate(NibiruOracleStargate)
+        .build(no_init)
 }
 
 pub fn fund(app: &mut Simapp, addr: Addr, coins: &[Coin]) {
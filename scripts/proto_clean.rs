@@ -1,16 +1,16 @@
-/// scripts/proto_clean.rs:
-///
-/// Run with: `cargo run --bin proto_clean`
-///
-/// ## Procedure
-///
-/// 1. Walk through all the files in the nibiru-std/src/proto directory.
-/// 2. For each file, read its content and identify lines that import types with
-///    multiple super components.
-/// 3. Classify each import based on the first non-super part, then replace the
-///    super components with crate::proto::cosmo or crate::proto::tendemint based
-///    on the classification.
-/// 4. Write the modified content back to each file.
+//! scripts/proto_clean.rs:
+//!
+//! Run with: `cargo run --bin proto_clean`
+//!
+//! ## Procedure
+//!
+//! 1. Walk through all the files in the nibiru-std/src/proto directory.
+//! 2. For each file, read its content and identify lines that import types with
+//!    multiple super components.
+//! 3. Classify each import based on the first non-super part, then replace the
+//!    super components with crate::proto::cosmo or crate::proto::tendemint based
+//!    on the classification.
+//! 4. Write the modified content back to each file.
 
 pub static PROTO_PATH: &str = "../nibiru-std/src/proto/buf";
 
@@ -21,9 +21,8 @@ fn main() -> anyhow::Result<()> {
     let matches = cli_cmd.clone().try_get_matches();
 
     let exec_path = CmdExecPath::new(matches)?;
-    let exec_path = exec_path.exec(cli_cmd).map_err(|err| {
+    let exec_path = exec_path.exec(cli_cmd).inspect_err(|_| {
         println!("❌ Compilation failed.\n");
-        err
     })?;
 
     #[allow(clippy::single_match)]